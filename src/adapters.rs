@@ -0,0 +1,729 @@
+use crate::visit::SECRET_KEY;
+use stakker::LogVisitor;
+use std::fmt::{self, Arguments, Write as _};
+
+/// `LogVisitor` adapter which redacts the values of selected keys
+///
+/// This passes everything through to the inner visitor, except that
+/// the value of any key in the given list is replaced with the string
+/// `"<redacted>"`.  Keys are matched at any depth, and if the value is
+/// a map or array, the whole value is replaced, including everything
+/// nested within it.  This keeps secrets out of the logs when logging
+/// request bodies and the like.  To apply it to a record, wrap the
+/// `kvscan` closure before passing it to a renderer:
+///
+/// ```ignore
+/// const SECRETS: &[&str] = &["password", "token"];
+/// let kvscan = |lv: &mut dyn LogVisitor| (r.kvscan)(&mut RedactingVisitor::new(lv, SECRETS));
+/// let json = KvToJson::new(&kvscan, "", "").to_string();
+/// ```
+///
+/// Values wrapped in [`Secret`], e.g. with the `secret!` shortcut of
+/// the logging macros, are also redacted, whatever their key.  The
+/// marker is part of the visited data, so it is kept when a record
+/// is collected and visited again later, e.g. by a
+/// [`MapRecordSink`].
+///
+/// [`MapRecordSink`]: struct.MapRecordSink.html
+/// [`Secret`]: struct.Secret.html
+pub struct RedactingVisitor<'a> {
+    inner: &'a mut dyn LogVisitor,
+    keys: &'a [&'a str],
+    // Depth of nesting within a redacted map or array, or 0 if not
+    // currently within one
+    skip: usize,
+    // Key of a map held back until the next item shows whether it is
+    // a `Secret` wrapper
+    pending: Option<Option<String>>,
+}
+
+impl<'a> RedactingVisitor<'a> {
+    /// Create an adapter which redacts the values of `keys` and
+    /// passes everything else through to `inner`
+    pub fn new(inner: &'a mut dyn LogVisitor, keys: &'a [&'a str]) -> Self {
+        Self {
+            inner,
+            keys,
+            skip: 0,
+            pending: None,
+        }
+    }
+
+    // Output a held-back map, or if the next item has the `Secret`
+    // key, the replacement for the map, skipping its contents
+    fn resolve(&mut self, key: Option<&str>) {
+        if let Some(outer) = self.pending.take() {
+            if key == Some(SECRET_KEY) {
+                self.inner.kv_str(outer.as_deref(), "<redacted>");
+                self.skip = 1;
+            } else {
+                self.inner.kv_map(outer.as_deref());
+            }
+        }
+    }
+
+    // Check whether a value should be dropped, outputting the
+    // replacement if the key is one to redact
+    fn redact(&mut self, key: Option<&str>) -> bool {
+        self.resolve(key);
+        if self.skip > 0 {
+            return true;
+        }
+        match key {
+            Some(k) if self.keys.contains(&k) => {
+                self.inner.kv_str(key, "<redacted>");
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Start a map or array, returning `true` if it is redacted
+    fn start(&mut self, key: Option<&str>) -> bool {
+        if self.redact(key) {
+            self.skip += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    // End a map or array, returning `true` if it was redacted
+    fn end(&mut self) -> bool {
+        if self.skip > 0 {
+            self.skip -= 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl<'a> LogVisitor for RedactingVisitor<'a> {
+    fn kv_u64(&mut self, key: Option<&str>, val: u64) {
+        if !self.redact(key) {
+            self.inner.kv_u64(key, val);
+        }
+    }
+    fn kv_i64(&mut self, key: Option<&str>, val: i64) {
+        if !self.redact(key) {
+            self.inner.kv_i64(key, val);
+        }
+    }
+    fn kv_f64(&mut self, key: Option<&str>, val: f64) {
+        if !self.redact(key) {
+            self.inner.kv_f64(key, val);
+        }
+    }
+    fn kv_bool(&mut self, key: Option<&str>, val: bool) {
+        if !self.redact(key) {
+            self.inner.kv_bool(key, val);
+        }
+    }
+    fn kv_null(&mut self, key: Option<&str>) {
+        if !self.redact(key) {
+            self.inner.kv_null(key);
+        }
+    }
+    fn kv_str(&mut self, key: Option<&str>, val: &str) {
+        if !self.redact(key) {
+            self.inner.kv_str(key, val);
+        }
+    }
+    fn kv_fmt(&mut self, key: Option<&str>, val: &Arguments<'_>) {
+        if !self.redact(key) {
+            self.inner.kv_fmt(key, val);
+        }
+    }
+    fn kv_map(&mut self, key: Option<&str>) {
+        if !self.start(key) {
+            self.pending = Some(key.map(str::to_string));
+        }
+    }
+    fn kv_mapend(&mut self, key: Option<&str>) {
+        if let Some(outer) = self.pending.take() {
+            self.inner.kv_map(outer.as_deref());
+            self.inner.kv_mapend(key);
+        } else if !self.end() {
+            self.inner.kv_mapend(key);
+        }
+    }
+    fn kv_arr(&mut self, key: Option<&str>) {
+        if !self.start(key) {
+            self.inner.kv_arr(key);
+        }
+    }
+    fn kv_arrend(&mut self, key: Option<&str>) {
+        if !self.end() {
+            self.inner.kv_arrend(key);
+        }
+    }
+}
+
+// `LogVisitor` adapter which removes the wrapper map that a `Secret`
+// outputs, passing on the plain value with the wrapper's key.  The
+// renderers use this, so that only a `RedactingVisitor` treats
+// secrets differently.
+pub(crate) struct RevealSecrets<'a> {
+    inner: &'a mut dyn LogVisitor,
+    // If a map is held back until the next item shows whether it is
+    // a `Secret` wrapper, whether it has a key, which is in `key`
+    held: Option<bool>,
+    key: String,
+    // For each open map, whether it was a wrapper which was removed
+    removed: Vec<bool>,
+}
+
+impl<'a> RevealSecrets<'a> {
+    pub(crate) fn new(inner: &'a mut dyn LogVisitor) -> Self {
+        Self {
+            inner,
+            held: None,
+            key: String::new(),
+            removed: Vec::new(),
+        }
+    }
+
+    // Pass the key to `f` along with the inner visitor, first
+    // resolving any held-back map.  If the map is a `Secret` wrapper,
+    // `f` gets the map's key instead.
+    fn with_key(&mut self, key: Option<&str>, f: impl FnOnce(&mut dyn LogVisitor, Option<&str>)) {
+        match self.held.take() {
+            Some(has_key) => {
+                let outer = if has_key {
+                    Some(self.key.as_str())
+                } else {
+                    None
+                };
+                if key == Some(SECRET_KEY) {
+                    self.removed.push(true);
+                    f(self.inner, outer);
+                } else {
+                    self.removed.push(false);
+                    self.inner.kv_map(outer);
+                    f(self.inner, key);
+                }
+            }
+            None => f(self.inner, key),
+        }
+    }
+
+    // Hold back a map with the given key
+    fn hold(&mut self, key: Option<&str>) {
+        self.key.clear();
+        self.key.push_str(key.unwrap_or(""));
+        self.held = Some(key.is_some());
+    }
+}
+
+impl<'a> LogVisitor for RevealSecrets<'a> {
+    fn kv_u64(&mut self, key: Option<&str>, val: u64) {
+        self.with_key(key, |lv, key| lv.kv_u64(key, val));
+    }
+    fn kv_i64(&mut self, key: Option<&str>, val: i64) {
+        self.with_key(key, |lv, key| lv.kv_i64(key, val));
+    }
+    fn kv_f64(&mut self, key: Option<&str>, val: f64) {
+        self.with_key(key, |lv, key| lv.kv_f64(key, val));
+    }
+    fn kv_bool(&mut self, key: Option<&str>, val: bool) {
+        self.with_key(key, |lv, key| lv.kv_bool(key, val));
+    }
+    fn kv_null(&mut self, key: Option<&str>) {
+        self.with_key(key, |lv, key| lv.kv_null(key));
+    }
+    fn kv_str(&mut self, key: Option<&str>, val: &str) {
+        self.with_key(key, |lv, key| lv.kv_str(key, val));
+    }
+    fn kv_fmt(&mut self, key: Option<&str>, val: &Arguments<'_>) {
+        self.with_key(key, |lv, key| lv.kv_fmt(key, val));
+    }
+    fn kv_map(&mut self, key: Option<&str>) {
+        match self.held {
+            // A map within a wrapper keeps the wrapper's key
+            Some(has_key) if key == Some(SECRET_KEY) => {
+                self.removed.push(true);
+                self.held = Some(has_key);
+            }
+            Some(has_key) => {
+                self.removed.push(false);
+                self.inner.kv_map(if has_key {
+                    Some(self.key.as_str())
+                } else {
+                    None
+                });
+                self.hold(key);
+            }
+            None => self.hold(key),
+        }
+    }
+    fn kv_mapend(&mut self, key: Option<&str>) {
+        if let Some(has_key) = self.held.take() {
+            let outer = if has_key {
+                Some(self.key.as_str())
+            } else {
+                None
+            };
+            self.inner.kv_map(outer);
+            self.inner.kv_mapend(key);
+        } else if self.removed.pop() != Some(true) {
+            self.inner.kv_mapend(key);
+        }
+    }
+    fn kv_arr(&mut self, key: Option<&str>) {
+        self.with_key(key, |lv, key| lv.kv_arr(key));
+    }
+    fn kv_arrend(&mut self, key: Option<&str>) {
+        self.with_key(key, |lv, key| lv.kv_arrend(key));
+    }
+}
+
+/// `LogVisitor` adapter which limits the length of string values
+///
+/// This passes everything through to the inner visitor, except that
+/// string and formatted values longer than the given number of bytes
+/// are cut short and have `…` appended.  The cut is made at a UTF-8
+/// character boundary, so a multi-byte character is never split, and
+/// the value before the `…` may be a few bytes shorter than the
+/// limit.  Formatted values are only formatted as far as the limit.
+/// This stops huge fields such as stack traces or HTML bodies blowing
+/// up the size of log lines.  It can be used around the `kvscan`
+/// closure in the same way as [`RedactingVisitor`]:
+///
+/// ```ignore
+/// let kvscan = |lv: &mut dyn LogVisitor| (r.kvscan)(&mut TruncatingVisitor::new(lv, 1024));
+/// ```
+///
+/// [`RedactingVisitor`]: struct.RedactingVisitor.html
+pub struct TruncatingVisitor<'a> {
+    inner: &'a mut dyn LogVisitor,
+    max: usize,
+}
+
+impl<'a> TruncatingVisitor<'a> {
+    /// Create an adapter which limits string values to `max` bytes and
+    /// passes everything through to `inner`
+    pub fn new(inner: &'a mut dyn LogVisitor, max: usize) -> Self {
+        Self { inner, max }
+    }
+
+    fn output(&mut self, key: Option<&str>, val: &str) {
+        if val.len() <= self.max {
+            self.inner.kv_str(key, val);
+        } else {
+            let mut end = self.max;
+            while !val.is_char_boundary(end) {
+                end -= 1;
+            }
+            self.inner.kv_str(key, &format!("{}…", &val[..end]));
+        }
+    }
+}
+
+// Formatting target which holds at most `max` bytes.  If more is
+// written, it keeps only as much as fits, cut at a character
+// boundary, appends `…` and stops formatting.
+struct Capped {
+    buf: String,
+    max: usize,
+}
+
+impl fmt::Write for Capped {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let remaining = self.max - self.buf.len();
+        if s.len() <= remaining {
+            self.buf.push_str(s);
+            return Ok(());
+        }
+        let mut end = remaining;
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.buf.push_str(&s[..end]);
+        self.buf.push('…');
+        Err(fmt::Error)
+    }
+}
+
+impl<'a> LogVisitor for TruncatingVisitor<'a> {
+    fn kv_u64(&mut self, key: Option<&str>, val: u64) {
+        self.inner.kv_u64(key, val);
+    }
+    fn kv_i64(&mut self, key: Option<&str>, val: i64) {
+        self.inner.kv_i64(key, val);
+    }
+    fn kv_f64(&mut self, key: Option<&str>, val: f64) {
+        self.inner.kv_f64(key, val);
+    }
+    fn kv_bool(&mut self, key: Option<&str>, val: bool) {
+        self.inner.kv_bool(key, val);
+    }
+    fn kv_null(&mut self, key: Option<&str>) {
+        self.inner.kv_null(key);
+    }
+    fn kv_str(&mut self, key: Option<&str>, val: &str) {
+        self.output(key, val);
+    }
+    fn kv_fmt(&mut self, key: Option<&str>, val: &Arguments<'_>) {
+        let mut capped = Capped {
+            buf: String::new(),
+            max: self.max,
+        };
+        // An error just means that the limit was reached
+        let _ = capped.write_fmt(*val);
+        self.inner.kv_str(key, &capped.buf);
+    }
+    fn kv_map(&mut self, key: Option<&str>) {
+        self.inner.kv_map(key);
+    }
+    fn kv_mapend(&mut self, key: Option<&str>) {
+        self.inner.kv_mapend(key);
+    }
+    fn kv_arr(&mut self, key: Option<&str>) {
+        self.inner.kv_arr(key);
+    }
+    fn kv_arrend(&mut self, key: Option<&str>) {
+        self.inner.kv_arrend(key);
+    }
+}
+
+/// `LogVisitor` adapter which adds a prefix to keys
+///
+/// This passes everything through to the inner visitor, except that
+/// the given prefix is prepended to every top-level key, e.g. with
+/// prefix `"http."`, a `status` key becomes `http.status`.  Keys
+/// within nested maps are left alone, unless [`PrefixVisitor::recursive`]
+/// is used.  Array items have no key, so are never affected.  This
+/// allows a component to namespace all of its fields when records
+/// from several subsystems are merged into one object, to avoid key
+/// collisions.  It can be used around the `kvscan` closure in the
+/// same way as [`RedactingVisitor`]:
+///
+/// ```ignore
+/// let kvscan = |lv: &mut dyn LogVisitor| (r.kvscan)(&mut PrefixVisitor::new(lv, "http."));
+/// ```
+///
+/// [`PrefixVisitor::recursive`]: struct.PrefixVisitor.html#method.recursive
+/// [`RedactingVisitor`]: struct.RedactingVisitor.html
+pub struct PrefixVisitor<'a> {
+    inner: &'a mut dyn LogVisitor,
+    prefix: &'a str,
+    recursive: bool,
+    depth: usize, // Nesting depth of maps and arrays
+    buf: String,
+}
+
+impl<'a> PrefixVisitor<'a> {
+    /// Create an adapter which prepends `prefix` to top-level keys
+    /// and passes everything through to `inner`
+    pub fn new(inner: &'a mut dyn LogVisitor, prefix: &'a str) -> Self {
+        Self {
+            inner,
+            prefix,
+            recursive: false,
+            depth: 0,
+            buf: String::new(),
+        }
+    }
+
+    /// Also prepend the prefix to keys within nested maps, at any
+    /// depth
+    pub fn recursive(mut self) -> Self {
+        self.recursive = true;
+        self
+    }
+
+    // Pass the key to `f` along with the inner visitor, prefixed if
+    // required at the current depth
+    fn with_key(&mut self, key: Option<&str>, f: impl FnOnce(&mut dyn LogVisitor, Option<&str>)) {
+        match key {
+            Some(k) if (self.depth == 0 || self.recursive) && k != SECRET_KEY => {
+                self.buf.clear();
+                self.buf.push_str(self.prefix);
+                self.buf.push_str(k);
+                f(self.inner, Some(&self.buf));
+            }
+            _ => f(self.inner, key),
+        }
+    }
+}
+
+impl<'a> LogVisitor for PrefixVisitor<'a> {
+    fn kv_u64(&mut self, key: Option<&str>, val: u64) {
+        self.with_key(key, |lv, key| lv.kv_u64(key, val));
+    }
+    fn kv_i64(&mut self, key: Option<&str>, val: i64) {
+        self.with_key(key, |lv, key| lv.kv_i64(key, val));
+    }
+    fn kv_f64(&mut self, key: Option<&str>, val: f64) {
+        self.with_key(key, |lv, key| lv.kv_f64(key, val));
+    }
+    fn kv_bool(&mut self, key: Option<&str>, val: bool) {
+        self.with_key(key, |lv, key| lv.kv_bool(key, val));
+    }
+    fn kv_null(&mut self, key: Option<&str>) {
+        self.with_key(key, |lv, key| lv.kv_null(key));
+    }
+    fn kv_str(&mut self, key: Option<&str>, val: &str) {
+        self.with_key(key, |lv, key| lv.kv_str(key, val));
+    }
+    fn kv_fmt(&mut self, key: Option<&str>, val: &Arguments<'_>) {
+        self.with_key(key, |lv, key| lv.kv_fmt(key, val));
+    }
+    fn kv_map(&mut self, key: Option<&str>) {
+        self.with_key(key, |lv, key| lv.kv_map(key));
+        self.depth += 1;
+    }
+    fn kv_mapend(&mut self, key: Option<&str>) {
+        self.depth = self.depth.saturating_sub(1);
+        self.with_key(key, |lv, key| lv.kv_mapend(key));
+    }
+    fn kv_arr(&mut self, key: Option<&str>) {
+        self.with_key(key, |lv, key| lv.kv_arr(key));
+        self.depth += 1;
+    }
+    fn kv_arrend(&mut self, key: Option<&str>) {
+        self.depth = self.depth.saturating_sub(1);
+        self.with_key(key, |lv, key| lv.kv_arrend(key));
+    }
+}
+
+/// `LogVisitor` which discards everything
+///
+/// This is useful when benchmarking, to measure the cost of visiting
+/// the key-value pairs of a record without any formatting cost:
+///
+/// ```ignore
+/// (r.kvscan)(&mut NullVisitor);
+/// ```
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NullVisitor;
+
+impl LogVisitor for NullVisitor {
+    fn kv_u64(&mut self, _key: Option<&str>, _val: u64) {}
+    fn kv_i64(&mut self, _key: Option<&str>, _val: i64) {}
+    fn kv_f64(&mut self, _key: Option<&str>, _val: f64) {}
+    fn kv_bool(&mut self, _key: Option<&str>, _val: bool) {}
+    fn kv_null(&mut self, _key: Option<&str>) {}
+    fn kv_str(&mut self, _key: Option<&str>, _val: &str) {}
+    fn kv_fmt(&mut self, _key: Option<&str>, _val: &Arguments<'_>) {}
+    fn kv_map(&mut self, _key: Option<&str>) {}
+    fn kv_mapend(&mut self, _key: Option<&str>) {}
+    fn kv_arr(&mut self, _key: Option<&str>) {}
+    fn kv_arrend(&mut self, _key: Option<&str>) {}
+}
+
+/// `LogVisitor` which counts the values visited
+///
+/// This tallies the scalar values (everything other than maps and
+/// arrays, including nulls and formatted values), and the maps and
+/// arrays, at any depth.  Formatted values are not formatted.  Like
+/// [`NullVisitor`] this can be used to isolate the cost of visiting
+/// in benchmarks, and it also allows tests to check the structure
+/// visited without comparing rendered strings:
+///
+/// ```ignore
+/// let mut counts = CountingVisitor::default();
+/// (r.kvscan)(&mut counts);
+/// assert_eq!(counts.maps, 1);
+/// ```
+///
+/// [`NullVisitor`]: struct.NullVisitor.html
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CountingVisitor {
+    /// Number of scalar values
+    pub scalars: usize,
+    /// Number of maps
+    pub maps: usize,
+    /// Number of arrays
+    pub arrays: usize,
+}
+
+impl LogVisitor for CountingVisitor {
+    fn kv_u64(&mut self, _key: Option<&str>, _val: u64) {
+        self.scalars += 1;
+    }
+    fn kv_i64(&mut self, _key: Option<&str>, _val: i64) {
+        self.scalars += 1;
+    }
+    fn kv_f64(&mut self, _key: Option<&str>, _val: f64) {
+        self.scalars += 1;
+    }
+    fn kv_bool(&mut self, _key: Option<&str>, _val: bool) {
+        self.scalars += 1;
+    }
+    fn kv_null(&mut self, _key: Option<&str>) {
+        self.scalars += 1;
+    }
+    fn kv_str(&mut self, _key: Option<&str>, _val: &str) {
+        self.scalars += 1;
+    }
+    fn kv_fmt(&mut self, _key: Option<&str>, _val: &Arguments<'_>) {
+        self.scalars += 1;
+    }
+    fn kv_map(&mut self, _key: Option<&str>) {
+        self.maps += 1;
+    }
+    fn kv_mapend(&mut self, _key: Option<&str>) {}
+    fn kv_arr(&mut self, _key: Option<&str>) {
+        self.arrays += 1;
+    }
+    fn kv_arrend(&mut self, _key: Option<&str>) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CountingVisitor, PrefixVisitor, RedactingVisitor, TruncatingVisitor};
+    use crate::{KvToJson, Secret, Visitable};
+    use stakker::LogVisitor;
+
+    const SECRETS: &[&str] = &["password", "auth"];
+
+    fn redacted(kvscan: &dyn Fn(&mut dyn LogVisitor)) -> String {
+        let wrapped = |lv: &mut dyn LogVisitor| kvscan(&mut RedactingVisitor::new(lv, SECRETS));
+        KvToJson::new(&wrapped, "", "").to_string()
+    }
+
+    #[test]
+    fn top_level() {
+        let out = redacted(&|lv| {
+            lv.kv_str(Some("user"), "jim");
+            lv.kv_str(Some("password"), "hunter2");
+            lv.kv_u64(Some("port"), 80);
+        });
+        assert_eq!(out, r#""user":"jim","password":"<redacted>","port":80"#);
+    }
+
+    #[test]
+    fn nested() {
+        let out = redacted(&|lv| {
+            lv.kv_map(Some("request"));
+            lv.kv_str(Some("path"), "/login");
+            lv.kv_map(Some("auth"));
+            lv.kv_str(Some("token"), "abc");
+            lv.kv_arr(Some("scopes"));
+            lv.kv_str(None, "admin");
+            lv.kv_map(None);
+            lv.kv_mapend(None);
+            lv.kv_arrend(Some("scopes"));
+            lv.kv_mapend(Some("auth"));
+            lv.kv_arr(Some("list"));
+            lv.kv_map(None);
+            lv.kv_u64(Some("password"), 1234);
+            lv.kv_mapend(None);
+            lv.kv_arrend(Some("list"));
+            lv.kv_mapend(Some("request"));
+            lv.kv_bool(Some("ok"), true);
+        });
+        assert_eq!(
+            out,
+            r#""request":{"path":"/login","auth":"<redacted>","list":[{"password":"<redacted>"}]},"ok":true"#
+        );
+    }
+
+    #[test]
+    fn secret() {
+        let kvscan = |lv: &mut dyn LogVisitor| {
+            lv.kv_str(Some("user"), "jim");
+            Secret(&"abc123").visit(Some("token"), lv);
+            Secret(&[1, 2]).visit(Some("pin"), lv);
+            lv.kv_arr(Some("list"));
+            Secret(&Secret(&3)).visit(None, lv);
+            lv.kv_map(None);
+            lv.kv_mapend(None);
+            lv.kv_arrend(Some("list"));
+            lv.kv_map(Some("map"));
+            Secret(&vec![("a".to_string(), 1)]).visit(Some("inner"), lv);
+            lv.kv_u64(Some("b"), 2);
+            lv.kv_mapend(Some("map"));
+        };
+        assert_eq!(
+            redacted(&kvscan),
+            r#""user":"jim","token":"<redacted>","pin":"<redacted>","list":["<redacted>",{}],"map":{"inner":"<redacted>","b":2}"#
+        );
+        assert_eq!(
+            KvToJson::new(&kvscan, "", "").to_string(),
+            r#""user":"jim","token":"abc123","pin":[1,2],"list":[3,{}],"map":{"inner":[["a",1]],"b":2}"#
+        );
+
+        // A visitor which doesn't know about the marker sees a map
+        let mut counts = CountingVisitor::default();
+        Secret(&"abc123").visit(Some("token"), &mut counts);
+        assert_eq!((counts.maps, counts.scalars), (1, 1));
+    }
+
+    fn truncated(max: usize, kvscan: &dyn Fn(&mut dyn LogVisitor)) -> String {
+        let wrapped = |lv: &mut dyn LogVisitor| kvscan(&mut TruncatingVisitor::new(lv, max));
+        KvToJson::new(&wrapped, "", "").to_string()
+    }
+
+    #[test]
+    fn truncate_ascii() {
+        let out = truncated(5, &|lv| {
+            lv.kv_str(Some("short"), "abcde");
+            lv.kv_str(Some("long"), "abcdefgh");
+            lv.kv_fmt(Some("fmt"), &format_args!("{}-{}", 1234, 5678));
+            lv.kv_u64(Some("n"), 1234567);
+        });
+        assert_eq!(
+            out,
+            r#""short":"abcde","long":"abcde…","fmt":"1234-…","n":1234567"#
+        );
+    }
+
+    #[test]
+    fn truncate_multibyte() {
+        // 'é' is 2 bytes and '€' is 3 bytes
+        let out = truncated(4, &|lv| {
+            lv.kv_str(Some("a"), "ééé");
+            lv.kv_str(Some("b"), "a€€");
+            lv.kv_fmt(Some("c"), &format_args!("{}", "€€"));
+            lv.kv_str(Some("d"), "éé");
+        });
+        assert_eq!(out, r#""a":"éé…","b":"a€…","c":"€…","d":"éé""#);
+
+        // Formatted in several pieces
+        let out = truncated(4, &|lv| {
+            lv.kv_fmt(Some("a"), &format_args!("{}{}", "é", "€€"));
+            lv.kv_fmt(Some("b"), &format_args!("{}{}", "ab", "cd"));
+            lv.kv_fmt(Some("c"), &format_args!("{}{}{}", "ab", "cd", "e"));
+        });
+        assert_eq!(out, r#""a":"é…","b":"abcd","c":"abcd…""#);
+    }
+
+    fn prefixed(recursive: bool, kvscan: &dyn Fn(&mut dyn LogVisitor)) -> String {
+        let wrapped = |lv: &mut dyn LogVisitor| {
+            let mut pv = PrefixVisitor::new(lv, "http.");
+            if recursive {
+                pv = pv.recursive();
+            }
+            kvscan(&mut pv)
+        };
+        KvToJson::new(&wrapped, "", "").to_string()
+    }
+
+    fn kvscan_request(lv: &mut dyn LogVisitor) {
+        lv.kv_u64(Some("status"), 200);
+        lv.kv_map(Some("peer"));
+        lv.kv_str(Some("addr"), "10.0.0.1");
+        lv.kv_mapend(Some("peer"));
+        lv.kv_arr(Some("hops"));
+        lv.kv_str(None, "a");
+        lv.kv_map(None);
+        lv.kv_u64(Some("ttl"), 3);
+        lv.kv_mapend(None);
+        lv.kv_arrend(Some("hops"));
+    }
+
+    #[test]
+    fn prefix() {
+        assert_eq!(
+            prefixed(false, &kvscan_request),
+            r#""http.status":200,"http.peer":{"addr":"10.0.0.1"},"http.hops":["a",{"ttl":3}]"#
+        );
+        assert_eq!(
+            prefixed(true, &kvscan_request),
+            r#""http.status":200,"http.peer":{"http.addr":"10.0.0.1"},"http.hops":["a",{"http.ttl":3}]"#
+        );
+    }
+}
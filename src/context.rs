@@ -0,0 +1,84 @@
+use stakker::LogVisitor;
+use std::cell::RefCell;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+type Fields = dyn Fn(&mut dyn LogVisitor);
+
+thread_local! {
+    static CONTEXT: RefCell<Vec<Rc<Fields>>> = RefCell::new(Vec::new());
+}
+
+/// Thread-local logging context
+///
+/// This holds key-value pairs which are automatically added to every
+/// record logged by this crate's logging macros on this thread, ahead
+/// of the record's own key-value pairs.  This allows request-scoped
+/// fields such as correlation IDs to be attached to all the records
+/// logged whilst handling a request, without passing them around
+/// explicitly.  For example:
+///
+/// ```ignore
+/// let request_id = req.id;
+/// LogContext::scope(move |lv| lv.kv_u64(Some("request_id"), request_id), || {
+///     handle_request(cx, req);
+/// });
+/// ```
+///
+/// Scopes may be nested, in which case the outer scope's fields are
+/// output first.  Note that records generated outside of the logging
+/// macros (e.g. actor span records from **Stakker** itself) do not
+/// include the context fields.
+pub struct LogContext;
+
+impl LogContext {
+    /// Run `f` with the key-value pairs output by `kvs` added to the
+    /// context
+    pub fn scope<R>(kvs: impl Fn(&mut dyn LogVisitor) + 'static, f: impl FnOnce() -> R) -> R {
+        let _guard = Self::enter(kvs);
+        f()
+    }
+
+    /// Add the key-value pairs output by `kvs` to the context until
+    /// the returned guard is dropped.  Guards must be dropped in
+    /// reverse order of creation, which happens naturally if they are
+    /// held in local variables.
+    pub fn enter(kvs: impl Fn(&mut dyn LogVisitor) + 'static) -> LogContextGuard {
+        let len = CONTEXT.with(|c| {
+            let mut c = c.borrow_mut();
+            c.push(Rc::new(kvs));
+            c.len() - 1
+        });
+        LogContextGuard {
+            len,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Output all the key-value pairs in the current context.  This
+    /// is used by the logging macros.  The context is copied before
+    /// visiting, so the values may themselves log or change the
+    /// context.  Such changes don't affect the current output.
+    pub fn visit(output: &mut dyn LogVisitor) {
+        // Cloning just copies the `Rc` pointers, and doesn't allocate
+        // if the context is empty
+        let fields = CONTEXT.with(|c| c.borrow().clone());
+        for kvs in &fields {
+            kvs(output);
+        }
+    }
+}
+
+/// Guard which removes fields from the [`LogContext`] when dropped
+///
+/// [`LogContext`]: struct.LogContext.html
+pub struct LogContextGuard {
+    len: usize,
+    _not_send: PhantomData<Rc<()>>,
+}
+
+impl Drop for LogContextGuard {
+    fn drop(&mut self) {
+        CONTEXT.with(|c| c.borrow_mut().truncate(self.len));
+    }
+}
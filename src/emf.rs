@@ -0,0 +1,203 @@
+use crate::tree::{self, Value};
+use crate::{KvToJson, Visitable};
+use stakker::{LogRecord, LogVisitor};
+use std::convert::TryFrom;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Description of the metrics to publish with [`EmfRecord`]
+///
+/// This gives the CloudWatch namespace, the keys to use as
+/// dimensions, and the keys to publish as metrics along with their
+/// units.  Create one of these at startup and use it for all records.
+/// For example:
+///
+/// ```ignore
+/// let spec = EmfSpec::new("MyService")
+///     .dimension("region")
+///     .metric("latency", "Milliseconds")
+///     .metric("bytes", "Bytes");
+/// ```
+///
+/// [`EmfRecord`]: struct.EmfRecord.html
+#[derive(Clone, Debug)]
+pub struct EmfSpec {
+    namespace: String,
+    dimensions: Vec<String>,
+    metrics: Vec<(String, &'static str)>,
+}
+
+impl EmfSpec {
+    /// Create a description with the given CloudWatch namespace and
+    /// no dimensions or metrics
+    pub fn new(namespace: impl Into<String>) -> Self {
+        Self {
+            namespace: namespace.into(),
+            dimensions: Vec::new(),
+            metrics: Vec::new(),
+        }
+    }
+
+    /// Add a top-level key to use as a dimension.  The values of
+    /// these keys should be strings.
+    pub fn dimension(mut self, key: impl Into<String>) -> Self {
+        self.dimensions.push(key.into());
+        self
+    }
+
+    /// Add a top-level key to publish as a metric, with the given
+    /// CloudWatch unit, e.g. `"Milliseconds"`, `"Bytes"`, `"Count"` or
+    /// `"None"`
+    pub fn metric(mut self, key: impl Into<String>, unit: &'static str) -> Self {
+        self.metrics.push((key.into(), unit));
+        self
+    }
+}
+
+/// Rendering of a whole log record in the AWS CloudWatch Embedded
+/// Metric Format (EMF)
+///
+/// When formatted with `"{}"`, this produces a single-line JSON
+/// object containing the `_aws` metadata block, then `level`,
+/// `logid`, `target` (if not empty) and `msg`, then all the
+/// key-value pairs at the top level, for example:
+///
+/// ```text
+/// {"_aws":{"Timestamp":1700000000000,"CloudWatchMetrics":[{"Namespace":"MyService",
+/// "Dimensions":[["region"]],"Metrics":[{"Name":"latency","Unit":"Milliseconds"}]}]},
+/// "level":"INFO","logid":12,"msg":"Request done","region":"eu-west-1","latency":35}
+/// ```
+///
+/// (wrapped here for readability).  When written to CloudWatch Logs,
+/// this publishes the metric values to CloudWatch Metrics, and the
+/// other fields remain searchable as log properties.  Only the
+/// metrics of the [`EmfSpec`] whose keys are present in the record
+/// with numeric values are listed, and only the dimensions whose keys
+/// are present.  So records without any metric values can go through
+/// the same output without publishing anything.  Keys in the record
+/// which clash with `_aws`, `level`, `logid`, `target` or `msg` will
+/// give duplicate keys in the output.
+///
+/// [`EmfSpec`]: struct.EmfSpec.html
+pub struct EmfRecord<'a, 'b> {
+    spec: &'a EmfSpec,
+    r: &'a LogRecord<'b>,
+    timestamp: SystemTime,
+}
+
+impl<'a, 'b> EmfRecord<'a, 'b> {
+    /// Create an `EmfRecord` ready to be formatted, with a timestamp
+    /// of the current time
+    pub fn new(spec: &'a EmfSpec, r: &'a LogRecord<'b>) -> Self {
+        Self {
+            spec,
+            r,
+            timestamp: SystemTime::now(),
+        }
+    }
+
+    /// Use the given time as the timestamp of the record
+    pub fn timestamp(mut self, time: SystemTime) -> Self {
+        self.timestamp = time;
+        self
+    }
+}
+
+impl<'a, 'b> fmt::Display for EmfRecord<'a, 'b> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let r = self.r;
+        let pairs = tree::collect_revealed(r.kvscan);
+        let find = |key: &str| pairs.iter().find(|(k, _)| k == key).map(|(_, v)| v);
+        let ms = self
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX));
+        let kvscan = |lv: &mut dyn LogVisitor| {
+            lv.kv_map(Some("_aws"));
+            lv.kv_u64(Some("Timestamp"), ms);
+            lv.kv_arr(Some("CloudWatchMetrics"));
+            lv.kv_map(None);
+            lv.kv_str(Some("Namespace"), &self.spec.namespace);
+            lv.kv_arr(Some("Dimensions"));
+            lv.kv_arr(None);
+            for key in &self.spec.dimensions {
+                if find(key).is_some() {
+                    lv.kv_str(None, key);
+                }
+            }
+            lv.kv_arrend(None);
+            lv.kv_arrend(Some("Dimensions"));
+            lv.kv_arr(Some("Metrics"));
+            for (key, unit) in &self.spec.metrics {
+                if let Some(Value::U64(_) | Value::I64(_) | Value::F64(_)) = find(key) {
+                    lv.kv_map(None);
+                    lv.kv_str(Some("Name"), key);
+                    lv.kv_str(Some("Unit"), unit);
+                    lv.kv_mapend(None);
+                }
+            }
+            lv.kv_arrend(Some("Metrics"));
+            lv.kv_mapend(None);
+            lv.kv_arrend(Some("CloudWatchMetrics"));
+            lv.kv_mapend(Some("_aws"));
+            lv.kv_str(Some("level"), r.level.name());
+            lv.kv_u64(Some("logid"), r.id);
+            if !r.target.is_empty() {
+                lv.kv_str(Some("target"), r.target);
+            }
+            lv.kv_fmt(Some("msg"), &r.fmt);
+            for (key, val) in &pairs {
+                val.visit(Some(key), lv);
+            }
+        };
+        write!(f, "{}", KvToJson::object(&kvscan))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{EmfRecord, EmfSpec};
+    use stakker::{LogLevel, LogRecord, LogVisitor};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test() {
+        let spec = EmfSpec::new("Svc")
+            .dimension("region")
+            .dimension("az")
+            .metric("latency", "Milliseconds")
+            .metric("bytes", "Bytes")
+            .metric("missing", "Count");
+        let kvscan = |lv: &mut dyn LogVisitor| {
+            lv.kv_str(Some("region"), "eu-west-1");
+            lv.kv_f64(Some("latency"), 35.5);
+            lv.kv_str(Some("bytes"), "not a number");
+            lv.kv_bool(Some("ok"), true);
+        };
+        let r = LogRecord {
+            id: 12,
+            level: LogLevel::Info,
+            target: "",
+            fmt: format_args!("Request done"),
+            kvscan: &kvscan,
+        };
+        let out = EmfRecord::new(&spec, &r)
+            .timestamp(UNIX_EPOCH + Duration::from_millis(1_700_000_000_123))
+            .to_string();
+        assert_eq!(
+            out,
+            concat!(
+                r#"{"_aws":{"Timestamp":1700000000123,"CloudWatchMetrics":[{"Namespace":"Svc","#,
+                r#""Dimensions":[["region"]],"Metrics":[{"Name":"latency","Unit":"Milliseconds"}]}]},"#,
+                r#""level":"INFO","logid":12,"msg":"Request done","region":"eu-west-1","#,
+                r#""latency":35.5,"bytes":"not a number","ok":true}"#
+            )
+        );
+        let v: serde_json::Value = serde_json::from_str(&out).unwrap();
+        let cwm = &v["_aws"]["CloudWatchMetrics"][0];
+        assert_eq!(cwm["Namespace"], "Svc");
+        assert!(cwm["Dimensions"][0].is_array());
+        assert_eq!(cwm["Metrics"].as_array().unwrap().len(), 1);
+        assert_eq!(v[cwm["Metrics"][0]["Name"].as_str().unwrap()], 35.5);
+    }
+}
@@ -1,4 +1,7 @@
+use crate::adapters::RevealSecrets;
+use crate::{NullRendering, Value};
 use stakker::LogVisitor;
+use std::cell::Cell;
 use std::fmt;
 use std::fmt::Arguments;
 use std::fmt::Write;
@@ -14,10 +17,21 @@ use std::fmt::Write;
 /// characters, where `XX` is two hex digits.  Anything higher than
 /// ASCII is passed unchanged.  Arrays are enclosed in `[...]` and
 /// maps are enclosed in `{...}`.
+///
+/// Control characters (including `\n` and `\r`) are always escaped,
+/// in both keys and values, so the output is guaranteed to fit on a
+/// single line.  This makes it safe to use with line-based
+/// transports, since a logged value cannot split the line or inject
+/// a fake record.
 pub struct KvSingleLine<'a> {
     kvscan: &'a dyn Fn(&mut dyn LogVisitor),
     prefix: &'static str,
     suffix: &'static str,
+    empty: &'static str,
+    html: bool,
+    color: bool,
+    nulls: NullRendering,
+    buffer: Cell<Option<&'a mut String>>,
 }
 
 impl<'a> KvSingleLine<'a> {
@@ -34,27 +48,307 @@ impl<'a> KvSingleLine<'a> {
             kvscan,
             prefix,
             suffix,
+            empty: "",
+            html: false,
+            color: false,
+            nulls: NullRendering::Null,
+            buffer: Cell::new(None),
+        }
+    }
+
+    /// Create a `KvSingleLine` which surrounds the key-value pairs
+    /// with `{` and `}`.  Unlike using [`KvSingleLine::new`] with
+    /// that prefix and suffix, `{}` is output if there are no
+    /// key-value pairs.
+    ///
+    /// [`KvSingleLine::new`]: struct.KvSingleLine.html#method.new
+    pub fn braced(kvscan: &'a dyn Fn(&mut dyn LogVisitor)) -> Self {
+        Self {
+            empty: "{}",
+            ..Self::new(kvscan, "{", "}")
+        }
+    }
+
+    /// Also escape `<`, `>` and `&` in keys and values, so that the
+    /// output can be embedded in HTML without any further escaping
+    /// and cannot inject markup.
+    pub fn html_safe(mut self) -> Self {
+        self.html = true;
+        self
+    }
+
+    /// Show keys in a dimmed color using ANSI escape sequences, to
+    /// make the values stand out when output to a terminal
+    pub fn ansi_color(mut self) -> Self {
+        self.color = true;
+        self
+    }
+
+    /// Set how null values are rendered.  The default is
+    /// `NullRendering::Null`, which outputs the key alone, with no
+    /// `=`.
+    pub fn null_rendering(mut self, nulls: NullRendering) -> Self {
+        self.nulls = nulls;
+        self
+    }
+
+    /// Use the given `String` as scratch space for formatting values
+    /// passed to `kv_fmt`, keeping its capacity between records.  See
+    /// [`KvToJson::with_buffer`].
+    ///
+    /// [`KvToJson::with_buffer`]: struct.KvToJson.html#method.with_buffer
+    pub fn with_buffer(self, buf: &'a mut String) -> Self {
+        self.buffer.set(Some(buf));
+        self
+    }
+}
+
+impl KvSingleLine<'_> {
+    /// Parse output of `KvSingleLine` back into key-value pairs
+    ///
+    /// `line` must contain just the key-value pairs, as output with
+    /// an empty prefix and suffix, without `ansi_color`.  A bare value
+    /// which is exactly as a number or bool would be rendered gives
+    /// `Value::U64`, `Value::I64`, `Value::F64` or `Value::Bool`, and
+    /// any other bare or quoted value gives `Value::Str`.  A key
+    /// without a value gives `Value::Null`, and `{...}` and `[...]`
+    /// give `Value::Map` and `Value::Arr`.  Returns `None` if the line
+    /// is not valid output.
+    ///
+    /// The grammar of the output is as follows:
+    ///
+    /// - Items are separated by a single space.  Top-level items and
+    ///   map items have a key, and array items do not.
+    /// - An item is a key followed by `=` and a value, or `{` and
+    ///   items and `}` for a map, or `[` and items and `]` for an
+    ///   array, or nothing for a null.  In an array, a null item is
+    ///   empty.
+    /// - A key is output as-is, except that control characters,
+    ///   space, `"`, `=`, `\`, `[`, `]`, `{` and `}` are output as
+    ///   `\XX` where `XX` is two uppercase hex digits.  An empty key
+    ///   is output as `\20`.
+    /// - A value is output as-is if it contains none of those
+    ///   characters, which means that an empty string gives nothing
+    ///   after the `=`.  Otherwise it is surrounded by `"`, and
+    ///   control characters, `"` and `\` within it are output as
+    ///   `\XX`.
+    /// - With `html_safe`, `<`, `>` and `&` are also treated as
+    ///   special and output as `\XX`.
+    ///
+    /// Some information is lost, so these cases don't round-trip:
+    /// a key of a single space is parsed as an empty key, an array
+    /// containing a single null is parsed as an empty array, a string
+    /// which looks like a number or bool is parsed as that type, and
+    /// a whole-number float is parsed as an integer.
+    pub fn parse(line: &str) -> Option<Vec<(String, Value)>> {
+        let mut p = Parser {
+            s: line.as_bytes(),
+            pos: 0,
+        };
+        let items = p.map_items(None)?;
+        if p.pos == p.s.len() {
+            Some(items)
+        } else {
+            None
         }
     }
 }
 
 impl<'a> fmt::Display for KvSingleLine<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut visitor = Visitor::new(f, self.prefix);
-        (self.kvscan)(&mut visitor);
-        if visitor.error {
+        let mut buffer = self.buffer.take();
+        let mut visitor = Visitor::new(f, self.prefix, self.html, self.color).nulls(self.nulls);
+        if let Some(ref mut buf) = buffer {
+            visitor.fmtbuf = std::mem::take(*buf);
+        }
+        (self.kvscan)(&mut RevealSecrets::new(&mut visitor));
+        let (error, empty) = (visitor.error, visitor.empty);
+        if let Some(buf) = buffer {
+            *buf = std::mem::take(&mut visitor.fmtbuf);
+            self.buffer.set(Some(buf));
+        }
+        if error {
             Err(fmt::Error)
-        } else if visitor.empty {
-            Ok(()) // Didn't output anything
+        } else if empty {
+            f.write_str(self.empty) // Didn't output anything
         } else {
             f.write_str(self.suffix)
         }
     }
 }
 
+// Parser for `KvSingleLine::parse`.  Works on bytes, since all the
+// syntax is ASCII, and only splits the input at ASCII characters.
+struct Parser<'a> {
+    s: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.s.get(self.pos).copied()
+    }
+
+    // Parse space-separated keyed items up to `end`, or the end of
+    // the input if `end` is `None`.  Doesn't consume `end`.
+    fn map_items(&mut self, end: Option<u8>) -> Option<Vec<(String, Value)>> {
+        let mut items = Vec::new();
+        if self.peek() == end {
+            return Some(items);
+        }
+        loop {
+            let key = self.key()?;
+            items.push((key, self.value(true)?));
+            match self.peek() {
+                Some(b' ') => self.pos += 1,
+                ch if ch == end => return Some(items),
+                _ => return None,
+            }
+        }
+    }
+
+    // Parse space-separated unkeyed items up to `]`.  Doesn't
+    // consume the `]`.
+    fn arr_items(&mut self) -> Option<Vec<Value>> {
+        let mut items = Vec::new();
+        if self.peek() == Some(b']') {
+            return Some(items);
+        }
+        loop {
+            items.push(self.value(false)?);
+            match self.peek() {
+                Some(b' ') => self.pos += 1,
+                Some(b']') => return Some(items),
+                _ => return None,
+            }
+        }
+    }
+
+    // Parse a value, either following a key, or as an array item
+    fn value(&mut self, keyed: bool) -> Option<Value> {
+        match self.peek() {
+            Some(b'=') if keyed => {
+                self.pos += 1;
+                self.scalar()
+            }
+            Some(b'{') => {
+                self.pos += 1;
+                let items = self.map_items(Some(b'}'))?;
+                self.pos += 1;
+                Some(Value::Map(items))
+            }
+            Some(b'[') => {
+                self.pos += 1;
+                let items = self.arr_items()?;
+                self.pos += 1;
+                Some(Value::Arr(items))
+            }
+            None | Some(b' ') | Some(b']') | Some(b'}') => Some(Value::Null),
+            _ if keyed => None,
+            _ => self.scalar(),
+        }
+    }
+
+    // Parse a quoted string value, or a bare value which may be a
+    // number or bool
+    fn scalar(&mut self) -> Option<Value> {
+        if self.peek() == Some(b'"') {
+            self.pos += 1;
+            Some(Value::Str(self.quoted()?))
+        } else {
+            Some(typed(self.bare(false)?))
+        }
+    }
+
+    fn key(&mut self) -> Option<String> {
+        let start = self.pos;
+        let key = self.bare(true)?;
+        if key.is_empty() {
+            None
+        } else if &self.s[start..self.pos] == b"\\20" {
+            Some(String::new()) // Empty key is output as `\20`
+        } else {
+            Some(key)
+        }
+    }
+
+    // Parse an unquoted key or value, decoding `\XX` escapes in keys
+    fn bare(&mut self, is_key: bool) -> Option<String> {
+        let mut out = Vec::new();
+        while let Some(ch) = self.peek() {
+            match ch {
+                b'\\' if is_key => {
+                    self.pos += 1;
+                    out.push(self.hex_byte()?);
+                }
+                b' ' | b']' | b'}' => break,
+                b'=' | b'{' | b'[' if is_key => break,
+                b'"' | b'\\' | b'=' | b'{' | b'[' | 0..=0x1F => return None,
+                _ => {
+                    out.push(ch);
+                    self.pos += 1;
+                }
+            }
+        }
+        String::from_utf8(out).ok()
+    }
+
+    // Parse the rest of a quoted value, after the opening `"`
+    fn quoted(&mut self) -> Option<String> {
+        let mut out = Vec::new();
+        loop {
+            match self.peek()? {
+                b'"' => {
+                    self.pos += 1;
+                    return String::from_utf8(out).ok();
+                }
+                b'\\' => {
+                    self.pos += 1;
+                    out.push(self.hex_byte()?);
+                }
+                ch => {
+                    out.push(ch);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    fn hex_byte(&mut self) -> Option<u8> {
+        let hex = self.s.get(self.pos..self.pos + 2)?;
+        let hex = std::str::from_utf8(hex).ok()?;
+        let byte = u8::from_str_radix(hex, 16).ok().filter(|b| b.is_ascii())?;
+        self.pos += 2;
+        Some(byte)
+    }
+}
+
+// Convert a bare value to a number or bool if it is exactly as one
+// would be rendered, otherwise leave it as a string
+fn typed(s: String) -> Value {
+    fn exact<T: std::str::FromStr + fmt::Display>(s: &str) -> Option<T> {
+        s.parse::<T>().ok().filter(|v| v.to_string() == s)
+    }
+    if let Some(v) = exact::<u64>(&s) {
+        Value::U64(v)
+    } else if let Some(v) = exact::<i64>(&s) {
+        Value::I64(v)
+    } else if let Some(v) = exact::<f64>(&s) {
+        Value::F64(v)
+    } else if let Some(v) = exact::<bool>(&s) {
+        Value::Bool(v)
+    } else {
+        Value::Str(s)
+    }
+}
+
+// ANSI escape sequences for key coloring
+const KEY_COLOR: &str = "\x1b[2m";
+const RESET_COLOR: &str = "\x1b[0m";
+
 // Reserved characters outside quotes
 #[inline]
-fn is_reserved(ch: char) -> bool {
+fn is_reserved(ch: char, html: bool) -> bool {
     ch <= ' '
         || ch == '"'
         || ch == '='
@@ -63,15 +357,22 @@ fn is_reserved(ch: char) -> bool {
         || ch == ']'
         || ch == '{'
         || ch == '}'
+        || (html && is_html_special(ch))
+}
+
+// Characters that must be escaped for safe embedding in HTML
+#[inline]
+fn is_html_special(ch: char) -> bool {
+    ch == '<' || ch == '>' || ch == '&'
 }
 
 // This has to be outside Visitor due to borrowing issues
 #[inline]
-fn push_str_val(f: &mut fmt::Formatter<'_>, val: &str) -> fmt::Result {
-    if val.find(is_reserved).is_some() {
+fn push_str_val(f: &mut fmt::Formatter<'_>, val: &str, html: bool) -> fmt::Result {
+    if val.find(|ch| is_reserved(ch, html)).is_some() {
         f.write_char('"')?;
         for ch in val.chars() {
-            if ch < ' ' || ch == '"' || ch == '\\' {
+            if ch < ' ' || ch == '"' || ch == '\\' || (html && is_html_special(ch)) {
                 write!(f, "\\{:02X}", ch as u8)?;
             } else {
                 f.write_char(ch)?;
@@ -99,28 +400,41 @@ struct Visitor<'a, 'b: 'a> {
     prefix: &'static str, // Whatever needs adding before the next item, or ""
     empty: bool,
     error: bool,
+    html: bool,
+    color: bool,
+    nulls: NullRendering,
 }
 
 impl<'a, 'b> Visitor<'a, 'b> {
-    fn new(fmt: &'a mut fmt::Formatter<'b>, prefix: &'static str) -> Self {
+    fn new(fmt: &'a mut fmt::Formatter<'b>, prefix: &'static str, html: bool, color: bool) -> Self {
         Self {
             fmt,
             fmtbuf: String::new(),
             prefix,
             empty: true,
             error: false,
+            html,
+            color,
+            nulls: NullRendering::Null,
         }
     }
+    fn nulls(mut self, nulls: NullRendering) -> Self {
+        self.nulls = nulls;
+        self
+    }
     fn push_key(&mut self, key: Option<&str>, sep: Option<char>) {
         catch!(self, self.fmt.write_str(self.prefix));
         self.empty = false;
         self.prefix = " ";
         if let Some(key) = key {
+            if self.color {
+                catch!(self, self.fmt.write_str(KEY_COLOR));
+            }
             if key.is_empty() {
                 catch!(self, self.fmt.write_str("\\20"));
             } else {
                 for ch in key.chars() {
-                    if is_reserved(ch) {
+                    if is_reserved(ch, self.html) {
                         catch!(self, write!(self.fmt, "\\{:02X}", ch as u8));
                     } else {
                         catch!(self, self.fmt.write_char(ch));
@@ -130,6 +444,9 @@ impl<'a, 'b> Visitor<'a, 'b> {
             if let Some(sep) = sep {
                 catch!(self, self.fmt.write_char(sep));
             }
+            if self.color {
+                catch!(self, self.fmt.write_str(RESET_COLOR));
+            }
         }
     }
 }
@@ -152,11 +469,19 @@ impl<'a, 'b> LogVisitor for Visitor<'a, 'b> {
         catch!(self, write!(self.fmt, "{}", val));
     }
     fn kv_null(&mut self, key: Option<&str>) {
-        self.push_key(key, None);
+        match self.nulls {
+            NullRendering::Null => self.push_key(key, None),
+            NullRendering::Omit => (),
+            NullRendering::EmptyString => {
+                self.push_key(key, Some('='));
+                catch!(self, self.fmt.write_str("\"\""));
+            }
+            NullRendering::Custom(val) => self.kv_str(key, val),
+        }
     }
     fn kv_str(&mut self, key: Option<&str>, val: &str) {
         self.push_key(key, Some('='));
-        catch!(self, push_str_val(self.fmt, val));
+        catch!(self, push_str_val(self.fmt, val, self.html));
     }
     fn kv_fmt(&mut self, key: Option<&str>, val: &Arguments<'_>) {
         self.push_key(key, Some('='));
@@ -165,7 +490,7 @@ impl<'a, 'b> LogVisitor for Visitor<'a, 'b> {
         }
         self.fmtbuf.clear();
         catch!(self, write!(self.fmtbuf, "{}", val));
-        catch!(self, push_str_val(self.fmt, &self.fmtbuf));
+        catch!(self, push_str_val(self.fmt, &self.fmtbuf, self.html));
     }
     fn kv_map(&mut self, key: Option<&str>) {
         self.push_key(key, None);
@@ -189,7 +514,9 @@ impl<'a, 'b> LogVisitor for Visitor<'a, 'b> {
 
 #[cfg(test)]
 mod test {
-    use super::{KvSingleLine, LogVisitor};
+    use super::{KvSingleLine, LogVisitor, NullRendering};
+    use crate::kvjson::test::kvscan_newlines;
+    use crate::{Value, Visitable};
     use std::fmt::Write;
 
     fn kvscan(lv: &mut dyn LogVisitor) {
@@ -233,6 +560,21 @@ mod test {
         write!(s, "{}", KvSingleLine::new(kvscan, prefix, suffix)).unwrap();
     }
 
+    #[test]
+    fn with_buffer() {
+        let mut ptr = None;
+        let mut buf = String::with_capacity(2000);
+        for _ in 0..3 {
+            let out = KvSingleLine::new(&kvscan, "", "")
+                .with_buffer(&mut buf)
+                .to_string();
+            assert_eq!(out, KvSingleLine::new(&kvscan, "", "").to_string());
+            assert!(buf.capacity() >= 2000);
+            // The same allocation is reused on each call
+            assert_eq!(*ptr.get_or_insert(buf.as_ptr()), buf.as_ptr());
+        }
+    }
+
     /// Basic sanity-check
     #[test]
     fn test() {
@@ -241,4 +583,199 @@ mod test {
         println!("{}", buf);
         assert_eq!(buf, "dummy=1 u64=123456789 i64=-123456789 f64=12345.6789 b0=false b1=true null str=ABCDEFGHIJ str_ctrl=\"ABC\\09DEF\" str_quote=\"ABC\\22DEF\\22GHI\" str_bsl=\"ABC\\5CDEF\\5CGHI\" fmt=ABC123DEF map{map_u64=987654321 map_str=JIHGFEDCBA map_nested{map_nested_bool=false}} map_empty{} arr[987654321 JIHGFEDCBA [true]] arr_empty[]");
     }
+
+    /// Newlines must never appear raw in the output
+    #[test]
+    fn newlines() {
+        let mut buf = String::new();
+        append(&mut buf, &kvscan_newlines, "", "");
+        println!("{}", buf);
+        assert!(!buf.contains('\n') && !buf.contains('\r'));
+        assert_eq!(buf, "str=\"ABC\\0ADEF\\0D\\0AGHI\" fmt=\"ABC\\0ADEF\" key\\0Awith\\0Dnewlines=1 arr[\"\\0A\"]");
+    }
+
+    // Parse the line, and check that rendering the result gives the
+    // same line again
+    fn round_trip(line: &str) -> Vec<(String, Value)> {
+        let items = KvSingleLine::parse(line).unwrap();
+        let kvscan = |lv: &mut dyn LogVisitor| {
+            for (k, v) in &items {
+                v.visit(Some(k), lv);
+            }
+        };
+        assert_eq!(KvSingleLine::new(&kvscan, "", "").to_string(), line);
+        items
+    }
+
+    #[test]
+    fn parse() {
+        let mut buf = String::new();
+        append(&mut buf, &kvscan, "", "");
+        let items = round_trip(&buf);
+        let str = |s: &str| Value::Str(s.to_string());
+        assert_eq!(items.len(), 15);
+        assert_eq!(items[0], ("u64".to_string(), Value::U64(123456789)));
+        assert_eq!(items[5], ("null".to_string(), Value::Null));
+        assert_eq!(items[7], ("str_ctrl".to_string(), str("ABC\tDEF")));
+        assert_eq!(items[8], ("str_quote".to_string(), str("ABC\"DEF\"GHI")));
+        assert_eq!(items[9], ("str_bsl".to_string(), str("ABC\\DEF\\GHI")));
+        assert_eq!(
+            items[11],
+            (
+                "map".to_string(),
+                Value::Map(vec![
+                    ("map_u64".to_string(), Value::U64(987654321)),
+                    ("map_str".to_string(), str("JIHGFEDCBA")),
+                    (
+                        "map_nested".to_string(),
+                        Value::Map(vec![("map_nested_bool".to_string(), Value::Bool(false))])
+                    ),
+                ])
+            )
+        );
+        assert_eq!(items[12], ("map_empty".to_string(), Value::Map(vec![])));
+        assert_eq!(
+            items[13],
+            (
+                "arr".to_string(),
+                Value::Arr(vec![
+                    Value::U64(987654321),
+                    str("JIHGFEDCBA"),
+                    Value::Arr(vec![Value::Bool(true)])
+                ])
+            )
+        );
+
+        let mut buf = String::new();
+        append(&mut buf, &kvscan_newlines, "", "");
+        let items = round_trip(&buf);
+        assert_eq!(items[0].1, str("ABC\nDEF\r\nGHI"));
+        assert_eq!(items[2].0, "key\nwith\rnewlines");
+
+        // Edge cases
+        let items = round_trip("\\20=1 a= c=\"x y\" d[ 1  {e=\"[\"}] f=é");
+        assert_eq!(items[0], (String::new(), Value::U64(1)));
+        assert_eq!(items[1], ("a".to_string(), str("")));
+        assert_eq!(items[2], ("c".to_string(), str("x y")));
+        assert_eq!(
+            items[3].1,
+            Value::Arr(vec![
+                Value::Null,
+                Value::U64(1),
+                Value::Null,
+                Value::Map(vec![("e".to_string(), str("["))])
+            ])
+        );
+        assert_eq!(items[4], ("f".to_string(), str("é")));
+        let html = "a\\26b=\"\\3Cx\\3E\"";
+        assert_eq!(
+            KvSingleLine::parse(html),
+            Some(vec![("a&b".to_string(), str("<x>"))])
+        );
+        assert_eq!(
+            KvSingleLine::parse("b=\"\""),
+            Some(vec![("b".to_string(), str(""))])
+        );
+        assert_eq!(KvSingleLine::parse(""), Some(vec![]));
+
+        // Invalid input
+        for bad in &[
+            "a=\"x",
+            "a{b=1",
+            "a=1]",
+            "a=x\"y",
+            "a=\\2",
+            "=1",
+            "a  b",
+            "a=\"\\G0\"",
+        ] {
+            assert_eq!(KvSingleLine::parse(bad), None, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn parse_typed() {
+        let items = round_trip("a=12 b=-3 c=1.5 d=true e=false f g=\"7 8\" h[1 x]");
+        let vals: Vec<Value> = items.into_iter().map(|(_, v)| v).collect();
+        assert_eq!(
+            vals,
+            vec![
+                Value::U64(12),
+                Value::I64(-3),
+                Value::F64(1.5),
+                Value::Bool(true),
+                Value::Bool(false),
+                Value::Null,
+                Value::Str("7 8".to_string()),
+                Value::Arr(vec![Value::U64(1), Value::Str("x".to_string())]),
+            ]
+        );
+        // Not exactly as a number or bool would be rendered
+        for s in &["+1", "007", "1.50", "1e5", "True", "-"] {
+            let items = KvSingleLine::parse(&format!("a={}", s)).unwrap();
+            assert_eq!(items[0].1, Value::Str(s.to_string()), "{}", s);
+        }
+        // Quoted values are always strings
+        assert_eq!(
+            KvSingleLine::parse("a=\"7\""),
+            Some(vec![("a".to_string(), Value::Str("7".to_string()))])
+        );
+    }
+
+    #[test]
+    fn html_safe() {
+        let kvscan = |lv: &mut dyn LogVisitor| {
+            lv.kv_str(Some("str"), "<script>alert(1)</script>");
+            lv.kv_str(Some("a&b"), "x");
+        };
+        let buf = format!("{}", KvSingleLine::new(&kvscan, "", ""));
+        assert_eq!(buf, "str=<script>alert(1)</script> a&b=x");
+        let buf = format!("{}", KvSingleLine::new(&kvscan, "", "").html_safe());
+        assert_eq!(
+            buf,
+            "str=\"\\3Cscript\\3Ealert(1)\\3C/script\\3E\" a\\26b=x"
+        );
+    }
+
+    #[test]
+    fn braced() {
+        let buf = format!("{}", KvSingleLine::braced(&|_| ()));
+        assert_eq!(buf, "{}");
+        let buf = format!("{}", KvSingleLine::braced(&kvscan_newlines));
+        assert!(buf.starts_with("{str="));
+        assert!(buf.ends_with(" arr[\"\\0A\"]}"));
+    }
+
+    #[test]
+    fn ansi_color() {
+        let kvscan = |lv: &mut dyn LogVisitor| {
+            lv.kv_u64(Some("a"), 1);
+            lv.kv_null(Some("b"));
+            lv.kv_arr(None);
+            lv.kv_u64(None, 2);
+            lv.kv_arrend(None);
+        };
+        let buf = format!("{}", KvSingleLine::new(&kvscan, "", "").ansi_color());
+        assert_eq!(buf, "\x1b[2ma=\x1b[0m1 \x1b[2mb\x1b[0m [2]");
+    }
+
+    #[test]
+    fn null_rendering() {
+        let kvscan = |lv: &mut dyn LogVisitor| {
+            lv.kv_null(Some("a"));
+            lv.kv_u64(Some("b"), 1);
+            lv.kv_null(Some("c"));
+        };
+        let render = |nulls| format!("{}", KvSingleLine::braced(&kvscan).null_rendering(nulls));
+        assert_eq!(render(NullRendering::Null), "{a b=1 c}");
+        assert_eq!(render(NullRendering::Omit), "{b=1}");
+        assert_eq!(render(NullRendering::EmptyString), "{a=\"\" b=1 c=\"\"}");
+        assert_eq!(render(NullRendering::Custom("none")), "{a=none b=1 c=none}");
+        let only_null = |lv: &mut dyn LogVisitor| lv.kv_null(Some("a"));
+        let buf = format!(
+            "{}",
+            KvSingleLine::braced(&only_null).null_rendering(NullRendering::Omit)
+        );
+        assert_eq!(buf, "{}");
+    }
 }
@@ -1,16 +1,56 @@
+use crate::adapters::RevealSecrets;
 use stakker::LogVisitor;
+use std::cell::Cell;
 use std::fmt;
 use std::fmt::Arguments;
 use std::fmt::Write;
+use std::io;
+
+/// How null values are rendered
+///
+/// This allows the null policy to be set per renderer, using
+/// `null_rendering` on [`KvToJson`] or [`KvSingleLine`].  Null values
+/// come from `()` and `None`, amongst others.
+///
+/// [`KvSingleLine`]: struct.KvSingleLine.html
+/// [`KvToJson`]: struct.KvToJson.html
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NullRendering {
+    /// Output as the renderer's null value (the default)
+    Null,
+    /// Omit the item entirely, including its key
+    Omit,
+    /// Output as an empty string
+    EmptyString,
+    /// Output as the given string
+    Custom(&'static str),
+}
 
 /// JSON rendering of key-value pairs
 ///
 /// When formatted with `"{}"`, this produces a single-line compact
-/// JSON rendering of the key-value pairs.
+/// JSON rendering of the key-value pairs.  Control characters
+/// (including `\n` and `\r`) are always escaped within strings, so
+/// the output is guaranteed to contain no raw newlines.  This makes
+/// it safe to use with line-based transports.
+///
+/// JSON has no representation for non-finite floating-point values,
+/// so by default NaN and the infinities are output as `null`, to keep
+/// the output valid JSON.  See [`KvToJson::nonfinite_as_strings`] for
+/// an alternative.
+///
+/// [`KvToJson::nonfinite_as_strings`]: struct.KvToJson.html#method.nonfinite_as_strings
 pub struct KvToJson<'a> {
     kvscan: &'a dyn Fn(&mut dyn LogVisitor),
     prefix: &'static str,
     suffix: &'static str,
+    empty: &'static str,
+    html: bool,
+    nonfinite_strings: bool,
+    nulls: NullRendering,
+    quoted: &'a [&'a str],
+    precision: Option<usize>,
+    buffer: Cell<Option<&'a mut String>>,
 }
 
 impl<'a> KvToJson<'a> {
@@ -38,29 +78,213 @@ impl<'a> KvToJson<'a> {
             kvscan,
             prefix,
             suffix,
+            empty: "",
+            html: false,
+            nonfinite_strings: false,
+            nulls: NullRendering::Null,
+            quoted: &[],
+            precision: None,
+            buffer: Cell::new(None),
+        }
+    }
+
+    /// Create a `KvToJson` which outputs a complete JSON object, i.e.
+    /// the key-value pairs surrounded by `{` and `}`.  Unlike using
+    /// [`KvToJson::new`] with that prefix and suffix, `{}` is output
+    /// if there are no key-value pairs.
+    ///
+    /// [`KvToJson::new`]: struct.KvToJson.html#method.new
+    pub fn object(kvscan: &'a dyn Fn(&mut dyn LogVisitor)) -> Self {
+        Self {
+            empty: "{}",
+            ..Self::new(kvscan, "{", "}")
+        }
+    }
+
+    /// Also escape `<`, `>` and `&` within strings as `\u003C`,
+    /// `\u003E` and `\u0026`.  The output is still valid JSON, but
+    /// can now be embedded within HTML (including within a `<script>`
+    /// element) without any risk of injecting markup.
+    pub fn html_safe(mut self) -> Self {
+        self.html = true;
+        self
+    }
+
+    /// Output non-finite floating-point values as the strings
+    /// `"NaN"`, `"Infinity"` and `"-Infinity"` instead of as `null`.
+    /// This is the convention understood by some JSON consumers, and
+    /// avoids losing information.
+    pub fn nonfinite_as_strings(mut self) -> Self {
+        self.nonfinite_strings = true;
+        self
+    }
+
+    /// Set how null values are rendered.  The default is
+    /// `NullRendering::Null`, which outputs `null`.
+    pub fn null_rendering(mut self, nulls: NullRendering) -> Self {
+        self.nulls = nulls;
+        self
+    }
+
+    /// Output integer values with any of the given keys as JSON
+    /// strings instead of as numbers, e.g. `"id":"12345"`.  This is
+    /// for consumers which must receive certain 64-bit ID fields as
+    /// strings, since they can't hold all 64-bit values exactly as
+    /// numbers, whilst other fields remain numeric.  Keys are matched
+    /// against the key of the value itself, so a key within a nested
+    /// map matches on its own name, regardless of the enclosing keys.
+    /// Array items have no key and so are never matched.
+    pub fn numbers_as_strings(mut self, keys: &'a [&'a str]) -> Self {
+        self.quoted = keys;
+        self
+    }
+
+    /// Round floating-point values to the given number of significant
+    /// digits, e.g. with `precision(3)`, `0.1 + 0.2` is output as
+    /// `0.3` and `2.0 / 3.0` as `0.667`.  By default values are
+    /// output with the shortest representation that parses back to
+    /// exactly the same value, which may be long.  Limiting the
+    /// precision keeps metrics compact when full accuracy isn't
+    /// needed.  Values of `1e16` and above, or smaller than `1e-5`,
+    /// are output in exponent form, e.g. `1.23e20`, to avoid long
+    /// runs of zeros.  The output is always a valid JSON number.  A
+    /// `digits` of zero is treated as one, and values above 17 as 17,
+    /// since 17 significant digits are always enough to reproduce an
+    /// `f64` exactly.
+    pub fn precision(mut self, digits: usize) -> Self {
+        self.precision = Some(digits.max(1));
+        self
+    }
+
+    /// Use the given `String` as scratch space for formatting values
+    /// passed to `kv_fmt`.  Otherwise a new buffer is allocated each
+    /// time this is formatted.  The `String` keeps its capacity after
+    /// formatting, so a high-volume logger can create one buffer and
+    /// pass it for every record to avoid that allocation.  The
+    /// contents of the buffer are not significant.
+    pub fn with_buffer(self, buf: &'a mut String) -> Self {
+        self.buffer.set(Some(buf));
+        self
+    }
+}
+
+impl<'a> KvToJson<'a> {
+    /// Write the output directly to an `io::Write`, without building
+    /// an intermediate `String`.  The output is identical to that
+    /// produced by formatting with `"{}"`.  Any `io::Error` from the
+    /// writer is returned.  Note that the writer is not buffered, so
+    /// pass a `BufWriter` if the underlying writer is expensive to
+    /// call.
+    pub fn write_to(&self, w: &mut dyn io::Write) -> io::Result<()> {
+        let mut adapter = IoAdapter {
+            inner: w,
+            error: None,
+        };
+        match (self.render(&mut adapter), adapter.error) {
+            (_, Some(e)) => Err(e),
+            (Ok(()), None) => Ok(()),
+            (Err(_), None) => Err(io::Error::new(io::ErrorKind::Other, "formatting error")),
+        }
+    }
+
+    fn render(&self, f: &mut dyn Write) -> fmt::Result {
+        let mut buffer = self.buffer.take();
+        let mut visitor = Visitor::new(f, self.prefix, self.html, self.nonfinite_strings)
+            .nulls(self.nulls)
+            .quoted(self.quoted)
+            .precision(self.precision);
+        if let Some(ref mut buf) = buffer {
+            visitor.fmtbuf = std::mem::take(*buf);
+        }
+        (self.kvscan)(&mut RevealSecrets::new(&mut visitor));
+        let (error, empty) = (visitor.error, visitor.empty);
+        if let Some(buf) = buffer {
+            *buf = std::mem::take(&mut visitor.fmtbuf);
+            self.buffer.set(Some(buf));
+        }
+        if error {
+            Err(fmt::Error)
+        } else if empty {
+            f.write_str(self.empty) // Didn't output anything
+        } else {
+            f.write_str(self.suffix)
         }
     }
 }
 
 impl<'a> fmt::Display for KvToJson<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut visitor = Visitor::new(f, self.prefix);
-        (self.kvscan)(&mut visitor);
+        self.render(f)
+    }
+}
+
+/// Adapter to write to an `io::Write` via `fmt::Write`, keeping the
+/// `io::Error`, which `fmt::Error` can't carry
+struct IoAdapter<'a> {
+    inner: &'a mut dyn io::Write,
+    error: Option<io::Error>,
+}
+
+impl<'a> Write for IoAdapter<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.inner.write_all(s.as_bytes()).map_err(|e| {
+            self.error = Some(e);
+            fmt::Error
+        })
+    }
+}
+
+/// Pretty multi-line JSON rendering of key-value pairs
+///
+/// When formatted with `"{}"`, this produces a complete JSON object
+/// containing the key-value pairs, with each item on its own line,
+/// indented according to its nesting depth, in the same layout as
+/// `serde_json::to_string_pretty`.  Empty maps and arrays are output
+/// as `{}` and `[]`.  This is intended for human inspection of deeply
+/// nested data, e.g. when debugging.  String quoting is the same as
+/// for [`KvToJson`].
+///
+/// [`KvToJson`]: struct.KvToJson.html
+pub struct KvToJsonPretty<'a> {
+    kvscan: &'a dyn Fn(&mut dyn LogVisitor),
+    indent: usize,
+}
+
+impl<'a> KvToJsonPretty<'a> {
+    /// Create a `KvToJsonPretty` ready to be formatted, indenting
+    /// each level of nesting by `indent` spaces.  An `indent` of zero
+    /// is treated as one.
+    pub fn new(kvscan: &'a dyn Fn(&mut dyn LogVisitor), indent: usize) -> Self {
+        Self {
+            kvscan,
+            indent: indent.max(1),
+        }
+    }
+}
+
+impl<'a> fmt::Display for KvToJsonPretty<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut visitor = Visitor::new(f, "{", false, false).pretty(self.indent, 1);
+        (self.kvscan)(&mut RevealSecrets::new(&mut visitor));
         if visitor.error {
             Err(fmt::Error)
         } else if visitor.empty {
-            Ok(()) // Didn't output anything
+            f.write_str("{}")
         } else {
-            f.write_str(self.suffix)
+            f.write_str("\n}")
         }
     }
 }
 
 /// JSON string quoting
 #[inline]
-fn push_str_literal(f: &mut fmt::Formatter<'_>, val: &str) -> fmt::Result {
+fn push_str_literal(f: &mut dyn Write, val: &str, html: bool) -> fmt::Result {
+    let html_special = |ch| html && (ch == '<' || ch == '>' || ch == '&');
     f.write_char('"')?;
-    if val.find(|ch| ch < ' ' || ch == '"' || ch == '\\').is_some() {
+    if val
+        .find(|ch| ch < ' ' || ch == '"' || ch == '\\' || html_special(ch))
+        .is_some()
+    {
         for ch in val.chars() {
             match ch {
                 '"' | '\\' => {
@@ -68,6 +292,7 @@ fn push_str_literal(f: &mut fmt::Formatter<'_>, val: &str) -> fmt::Result {
                     f.write_char(ch)?;
                 }
                 '\u{0000}'..='\u{001F}' => write!(f, "\\u{:04X}", ch as u32)?,
+                _ if html_special(ch) => write!(f, "\\u{:04X}", ch as u32)?,
                 _ => f.write_char(ch)?,
             }
         }
@@ -86,59 +311,171 @@ macro_rules! catch {
     }};
 }
 
-struct Visitor<'a, 'b: 'a> {
-    fmt: &'a mut fmt::Formatter<'b>,
+struct Visitor<'a> {
+    fmt: &'a mut dyn Write,
     fmtbuf: String,
     prefix: &'static str, // Whatever needs adding before the next item, or ""
     empty: bool,
     error: bool,
+    html: bool,
+    nonfinite_strings: bool,
+    indent: usize, // Indent width for pretty output, or 0 for compact
+    depth: usize,  // Current nesting depth, for pretty output
+    opened: bool,  // Just opened a map or array, i.e. may be empty
+    nulls: NullRendering,
+    quoted: &'a [&'a str],    // Keys of integers to output as strings
+    precision: Option<usize>, // Significant digits for floats
 }
 
-impl<'a, 'b> Visitor<'a, 'b> {
-    fn new(fmt: &'a mut fmt::Formatter<'b>, prefix: &'static str) -> Self {
+impl<'a> Visitor<'a> {
+    fn new(
+        fmt: &'a mut dyn Write,
+        prefix: &'static str,
+        html: bool,
+        nonfinite_strings: bool,
+    ) -> Self {
         Self {
             fmt,
             fmtbuf: String::new(),
             prefix,
             empty: true,
             error: false,
+            html,
+            nonfinite_strings,
+            indent: 0,
+            depth: 0,
+            opened: false,
+            nulls: NullRendering::Null,
+            quoted: &[],
+            precision: None,
+        }
+    }
+    fn nulls(mut self, nulls: NullRendering) -> Self {
+        self.nulls = nulls;
+        self
+    }
+    fn quoted(mut self, quoted: &'a [&'a str]) -> Self {
+        self.quoted = quoted;
+        self
+    }
+    fn precision(mut self, precision: Option<usize>) -> Self {
+        self.precision = precision;
+        self
+    }
+    // Write a finite float, rounded if a precision is set
+    fn push_f64(&mut self, val: f64) {
+        // More than 17 digits would add no accuracy
+        let digits = match self.precision {
+            Some(digits) => digits.min(17),
+            None => {
+                catch!(self, write!(self.fmt, "{}", val));
+                return;
+            }
+        };
+        // Round by formatting in exponent form, then parse it back to
+        // get the shortest output for the rounded value
+        self.fmtbuf.clear();
+        let _ = write!(self.fmtbuf, "{:.*e}", digits - 1, val);
+        let val = self.fmtbuf.parse::<f64>().unwrap_or(val);
+        let abs = val.abs();
+        if abs != 0.0 && !(1e-5..1e16).contains(&abs) {
+            catch!(self, write!(self.fmt, "{:e}", val));
+        } else {
+            catch!(self, write!(self.fmt, "{}", val));
+        }
+    }
+    fn push_int(&mut self, key: Option<&str>, val: &dyn fmt::Display) {
+        let quote = matches!(key, Some(k) if self.quoted.contains(&k));
+        self.push_key(key);
+        if quote {
+            catch!(self, write!(self.fmt, "\"{}\"", val));
+        } else {
+            catch!(self, write!(self.fmt, "{}", val));
+        }
+    }
+    fn pretty(mut self, indent: usize, depth: usize) -> Self {
+        self.indent = indent;
+        self.depth = depth;
+        self
+    }
+    fn newline(&mut self) {
+        if self.indent > 0 {
+            catch!(self, self.fmt.write_char('\n'));
+            for _ in 0..self.indent * self.depth {
+                catch!(self, self.fmt.write_char(' '));
+            }
         }
     }
     fn push_key(&mut self, key: Option<&str>) {
         catch!(self, self.fmt.write_str(self.prefix));
+        self.newline();
         self.prefix = ",";
         self.empty = false;
+        self.opened = false;
         if let Some(key) = key {
-            catch!(self, push_str_literal(self.fmt, key));
-            catch!(self, self.fmt.write_char(':'));
+            catch!(self, push_str_literal(self.fmt, key, self.html));
+            catch!(
+                self,
+                self.fmt.write_str(if self.indent > 0 { ": " } else { ":" })
+            );
+        }
+    }
+    fn open(&mut self, ch: char) {
+        catch!(self, self.fmt.write_char(ch));
+        self.prefix = "";
+        self.depth += 1;
+        self.opened = true;
+    }
+    fn close(&mut self, ch: char) {
+        self.depth = self.depth.saturating_sub(1);
+        if !self.opened {
+            self.newline();
         }
+        catch!(self, self.fmt.write_char(ch));
+        self.prefix = ",";
+        self.opened = false;
     }
 }
 
-impl<'a, 'b> LogVisitor for Visitor<'a, 'b> {
+impl<'a> LogVisitor for Visitor<'a> {
     fn kv_u64(&mut self, key: Option<&str>, val: u64) {
-        self.push_key(key);
-        catch!(self, write!(self.fmt, "{}", val));
+        self.push_int(key, &val);
     }
     fn kv_i64(&mut self, key: Option<&str>, val: i64) {
-        self.push_key(key);
-        catch!(self, write!(self.fmt, "{}", val));
+        self.push_int(key, &val);
     }
     fn kv_f64(&mut self, key: Option<&str>, val: f64) {
         self.push_key(key);
-        catch!(self, write!(self.fmt, "{}", val));
+        if val.is_finite() {
+            self.push_f64(val);
+        } else if !self.nonfinite_strings {
+            catch!(self, self.fmt.write_str("null"));
+        } else if val.is_nan() {
+            catch!(self, self.fmt.write_str("\"NaN\""));
+        } else if val > 0.0 {
+            catch!(self, self.fmt.write_str("\"Infinity\""));
+        } else {
+            catch!(self, self.fmt.write_str("\"-Infinity\""));
+        }
     }
     fn kv_bool(&mut self, key: Option<&str>, val: bool) {
         self.push_key(key);
         catch!(self, write!(self.fmt, "{}", val));
     }
     fn kv_null(&mut self, key: Option<&str>) {
-        self.push_key(key);
-        catch!(self, self.fmt.write_str("null"));
+        match self.nulls {
+            NullRendering::Null => {
+                self.push_key(key);
+                catch!(self, self.fmt.write_str("null"));
+            }
+            NullRendering::Omit => (),
+            NullRendering::EmptyString => self.kv_str(key, ""),
+            NullRendering::Custom(val) => self.kv_str(key, val),
+        }
     }
     fn kv_str(&mut self, key: Option<&str>, val: &str) {
         self.push_key(key);
-        catch!(self, push_str_literal(self.fmt, val));
+        catch!(self, push_str_literal(self.fmt, val, self.html));
     }
     fn kv_fmt(&mut self, key: Option<&str>, val: &Arguments<'_>) {
         self.push_key(key);
@@ -147,31 +484,27 @@ impl<'a, 'b> LogVisitor for Visitor<'a, 'b> {
         }
         self.fmtbuf.clear();
         catch!(self, write!(self.fmtbuf, "{}", val));
-        catch!(self, push_str_literal(self.fmt, &self.fmtbuf));
+        catch!(self, push_str_literal(self.fmt, &self.fmtbuf, self.html));
     }
     fn kv_map(&mut self, key: Option<&str>) {
         self.push_key(key);
-        catch!(self, self.fmt.write_char('{'));
-        self.prefix = "";
+        self.open('{');
     }
     fn kv_mapend(&mut self, _: Option<&str>) {
-        catch!(self, self.fmt.write_char('}'));
-        self.prefix = ",";
+        self.close('}');
     }
     fn kv_arr(&mut self, key: Option<&str>) {
         self.push_key(key);
-        catch!(self, self.fmt.write_char('['));
-        self.prefix = "";
+        self.open('[');
     }
     fn kv_arrend(&mut self, _: Option<&str>) {
-        catch!(self, self.fmt.write_char(']'));
-        self.prefix = ",";
+        self.close(']');
     }
 }
 
 #[cfg(test)]
-mod test {
-    use super::{KvToJson, LogVisitor};
+pub(crate) mod test {
+    use super::{KvToJson, KvToJsonPretty, LogVisitor, NullRendering};
     use std::fmt::Write;
 
     fn kvscan_empty(_lv: &mut dyn LogVisitor) {}
@@ -181,7 +514,8 @@ mod test {
         lv.kv_str(Some("str"), "ABCDEFGHIJ");
     }
 
-    fn kvscan_all(lv: &mut dyn LogVisitor) {
+    // Also used by the `kvserde` tests
+    pub(crate) fn kvscan_all(lv: &mut dyn LogVisitor) {
         lv.kv_u64(Some("u64"), 123456789);
         lv.kv_i64(Some("i64"), -123456789);
         lv.kv_f64(Some("f64"), 12345.6789);
@@ -223,6 +557,22 @@ mod test {
     }
 
     #[test]
+    fn with_buffer() {
+        let mut ptr = None;
+        let mut buf = String::new();
+        for _ in 0..3 {
+            let out = KvToJson::new(&kvscan_all, "", "")
+                .with_buffer(&mut buf)
+                .to_string();
+            assert_eq!(out, KvToJson::new(&kvscan_all, "", "").to_string());
+            assert!(buf.capacity() >= 1024);
+            // The same allocation is reused on each call
+            assert_eq!(*ptr.get_or_insert(buf.as_ptr()), buf.as_ptr());
+        }
+    }
+
+    #[test]
+    #[allow(clippy::single_char_add_str)]
     fn test() {
         // To verify JSON with `jq . -` (for example), run `cargo test
         // -- --nocapture` and paste in the printed JSON data
@@ -265,4 +615,234 @@ mod test {
             "{\"dummy\":1,\"u64\":123456789,\"i64\":-123456789,\"f64\":12345.6789,\"b0\":false,\"b1\":true,\"null\":null,\"str\":\"ABCDEFGHIJ\",\"str_ctrl\":\"ABC\\u0009DEF\",\"str_quote\":\"ABC\\\"DEF\\\"GHI\",\"str_bsl\":\"ABC\\\\DEF\\\\GHI\",\"fmt\":\"ABC123DEF\",\"map\":{\"map_u64\":987654321,\"map_str\":\"JIHGFEDCBA\",\"map_nested\":{\"map_nested_bool\":false}},\"map_empty\":{},\"arr\":[987654321,\"JIHGFEDCBA\",[true]],\"arr_empty\":[]}"
         );
     }
+
+    // Also used by the `kvdisp` tests
+    pub(crate) fn kvscan_newlines(lv: &mut dyn LogVisitor) {
+        lv.kv_str(Some("str"), "ABC\nDEF\r\nGHI");
+        lv.kv_fmt(Some("fmt"), &format_args!("{}\n{}", "ABC", "DEF"));
+        lv.kv_u64(Some("key\nwith\rnewlines"), 1);
+        lv.kv_arr(Some("arr"));
+        lv.kv_str(None, "\n");
+        lv.kv_arrend(Some("arr"));
+    }
+
+    #[test]
+    fn newlines() {
+        let mut buf = String::new();
+        append(&mut buf, &kvscan_newlines, "", "");
+        println!("{}", buf);
+        assert!(!buf.contains('\n') && !buf.contains('\r'));
+        assert_eq!(
+            buf,
+            "\"str\":\"ABC\\u000ADEF\\u000D\\u000AGHI\",\"fmt\":\"ABC\\u000ADEF\",\"key\\u000Awith\\u000Dnewlines\":1,\"arr\":[\"\\u000A\"]"
+        );
+    }
+
+    #[test]
+    fn html_safe() {
+        let kvscan = |lv: &mut dyn LogVisitor| {
+            lv.kv_str(Some("str"), "<script>alert(1)</script>");
+            lv.kv_str(Some("a&b"), "x");
+        };
+        let buf = format!("{}", KvToJson::new(&kvscan, "", ""));
+        assert_eq!(buf, "\"str\":\"<script>alert(1)</script>\",\"a&b\":\"x\"");
+        let buf = format!("{}", KvToJson::new(&kvscan, "", "").html_safe());
+        assert_eq!(
+            buf,
+            "\"str\":\"\\u003Cscript\\u003Ealert(1)\\u003C/script\\u003E\",\"a\\u0026b\":\"x\""
+        );
+    }
+
+    #[test]
+    fn object() {
+        let buf = format!("{}", KvToJson::object(&kvscan_empty));
+        assert_eq!(buf, "{}");
+        let buf = format!("{}", KvToJson::object(&kvscan_simple));
+        assert_eq!(buf, "{\"u64\":123456789,\"str\":\"ABCDEFGHIJ\"}");
+    }
+
+    #[test]
+    fn nonfinite() {
+        let kvscan = |lv: &mut dyn LogVisitor| {
+            lv.kv_f64(Some("nan"), f64::NAN);
+            lv.kv_f64(Some("inf"), f64::INFINITY);
+            lv.kv_f64(Some("ninf"), f64::NEG_INFINITY);
+            lv.kv_f64(Some("nzero"), -0.0);
+        };
+        let buf = format!("{}", KvToJson::object(&kvscan));
+        assert_eq!(
+            buf,
+            "{\"nan\":null,\"inf\":null,\"ninf\":null,\"nzero\":-0}"
+        );
+        serde_json::from_str::<serde_json::Value>(&buf).unwrap();
+
+        let buf = format!("{}", KvToJson::object(&kvscan).nonfinite_as_strings());
+        assert_eq!(
+            buf,
+            "{\"nan\":\"NaN\",\"inf\":\"Infinity\",\"ninf\":\"-Infinity\",\"nzero\":-0}"
+        );
+        serde_json::from_str::<serde_json::Value>(&buf).unwrap();
+    }
+
+    #[test]
+    fn numbers_as_strings() {
+        let kvscan = |lv: &mut dyn LogVisitor| {
+            lv.kv_u64(Some("id"), u64::MAX);
+            lv.kv_i64(Some("count"), -3);
+            lv.kv_map(Some("peer"));
+            lv.kv_u64(Some("id"), 7);
+            lv.kv_mapend(Some("peer"));
+            lv.kv_arr(Some("id"));
+            lv.kv_u64(None, 8);
+            lv.kv_arrend(Some("id"));
+        };
+        let buf = format!("{}", KvToJson::object(&kvscan).numbers_as_strings(&["id"]));
+        assert_eq!(
+            buf,
+            "{\"id\":\"18446744073709551615\",\"count\":-3,\"peer\":{\"id\":\"7\"},\"id\":[8]}"
+        );
+        serde_json::from_str::<serde_json::Value>(&buf).unwrap();
+    }
+
+    #[test]
+    fn precision() {
+        let kvscan = |lv: &mut dyn LogVisitor| {
+            lv.kv_f64(Some("sum"), 0.1 + 0.2);
+            lv.kv_f64(Some("third"), 2.0 / 3.0);
+            lv.kv_f64(Some("whole"), 1234.0);
+            lv.kv_f64(Some("big"), 1.2345e20);
+            lv.kv_f64(Some("tiny"), -1.2345e-7);
+            lv.kv_f64(Some("zero"), 0.0);
+        };
+        let buf = format!("{}", KvToJson::object(&kvscan));
+        assert_eq!(
+            buf,
+            "{\"sum\":0.30000000000000004,\"third\":0.6666666666666666,\"whole\":1234,\
+             \"big\":123450000000000000000,\"tiny\":-0.00000012345,\"zero\":0}"
+        );
+        serde_json::from_str::<serde_json::Value>(&buf).unwrap();
+
+        let buf = format!("{}", KvToJson::object(&kvscan).precision(3));
+        assert_eq!(
+            buf,
+            "{\"sum\":0.3,\"third\":0.667,\"whole\":1230,\"big\":1.23e20,\
+             \"tiny\":-1.23e-7,\"zero\":0}"
+        );
+        serde_json::from_str::<serde_json::Value>(&buf).unwrap();
+
+        // Clamped to 17 digits, enough for the exact value
+        let buf = format!("{}", KvToJson::object(&kvscan).precision(1000));
+        assert!(buf.starts_with("{\"sum\":0.30000000000000004,\"third\":0.6666666666666666,"));
+    }
+
+    #[test]
+    fn counting() {
+        let mut counts = crate::CountingVisitor::default();
+        kvscan_all(&mut counts);
+        assert_eq!(
+            counts,
+            crate::CountingVisitor {
+                scalars: 17,
+                maps: 3,
+                arrays: 3,
+            }
+        );
+        kvscan_all(&mut crate::NullVisitor);
+    }
+
+    #[test]
+    fn write_to() {
+        let mut buf = Vec::new();
+        KvToJson::new(&kvscan_all, "{", "}")
+            .write_to(&mut buf)
+            .unwrap();
+        let expected = format!("{}", KvToJson::new(&kvscan_all, "{", "}"));
+        assert_eq!(String::from_utf8(buf).unwrap(), expected);
+
+        let mut buf = Vec::new();
+        KvToJson::object(&kvscan_empty).write_to(&mut buf).unwrap();
+        assert_eq!(buf, b"{}");
+
+        // Error from writer is passed through
+        let mut full = [0_u8; 8];
+        let e = KvToJson::object(&kvscan_simple)
+            .write_to(&mut &mut full[..])
+            .unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::WriteZero);
+    }
+
+    #[test]
+    fn pretty() {
+        let buf = format!("{}", KvToJsonPretty::new(&kvscan_all, 2));
+        println!("{}", buf);
+        assert_eq!(
+            buf,
+            r#"{
+  "u64": 123456789,
+  "i64": -123456789,
+  "f64": 12345.6789,
+  "b0": false,
+  "b1": true,
+  "null": null,
+  "str": "ABCDEFGHIJ",
+  "str_ctrl": "ABC\u0009DEF",
+  "str_quote": "ABC\"DEF\"GHI",
+  "str_bsl": "ABC\\DEF\\GHI",
+  "fmt": "ABC123DEF",
+  "map": {
+    "map_u64": 987654321,
+    "map_str": "JIHGFEDCBA",
+    "map_nested": {
+      "map_nested_bool": false
+    }
+  },
+  "map_empty": {},
+  "arr": [
+    987654321,
+    "JIHGFEDCBA",
+    [
+      true
+    ]
+  ],
+  "arr_empty": []
+}"#
+        );
+        serde_json::from_str::<serde_json::Value>(&buf).unwrap();
+
+        let buf = format!("{}", KvToJsonPretty::new(&kvscan_empty, 4));
+        assert_eq!(buf, "{}");
+    }
+
+    #[test]
+    fn null_rendering() {
+        let kvscan = |lv: &mut dyn LogVisitor| {
+            lv.kv_null(Some("a"));
+            lv.kv_arr(Some("arr"));
+            lv.kv_null(None);
+            lv.kv_u64(None, 1);
+            lv.kv_null(None);
+            lv.kv_arrend(Some("arr"));
+            lv.kv_null(Some("b"));
+        };
+        let render = |nulls| format!("{}", KvToJson::object(&kvscan).null_rendering(nulls));
+        assert_eq!(
+            render(NullRendering::Null),
+            r#"{"a":null,"arr":[null,1,null],"b":null}"#
+        );
+        assert_eq!(render(NullRendering::Omit), r#"{"arr":[1]}"#);
+        assert_eq!(
+            render(NullRendering::EmptyString),
+            r#"{"a":"","arr":["",1,""],"b":""}"#
+        );
+        assert_eq!(
+            render(NullRendering::Custom("none")),
+            r#"{"a":"none","arr":["none",1,"none"],"b":"none"}"#
+        );
+        let only_null = |lv: &mut dyn LogVisitor| lv.kv_null(Some("a"));
+        let buf = format!(
+            "{}",
+            KvToJson::object(&only_null).null_rendering(NullRendering::Omit)
+        );
+        assert_eq!(buf, "{}");
+    }
 }
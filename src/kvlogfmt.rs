@@ -0,0 +1,261 @@
+use crate::adapters::RevealSecrets;
+use stakker::LogVisitor;
+use std::fmt;
+use std::fmt::Arguments;
+use std::fmt::Write;
+
+/// logfmt rendering of key-value pairs
+///
+/// When formatted with `"{}"`, this produces the key-value pairs in
+/// logfmt format, i.e. `key=value key2="value with space"`, as
+/// accepted by Loki and other log ingestion systems.  Values are
+/// output as bare tokens where this is safe, otherwise they are
+/// double-quoted, with `\"`, `\\`, `\n`, `\r` and `\t` escapes, and
+/// `\u00XX` escapes for other control characters.  Null values are
+/// output with an empty value (`key=`).
+///
+/// logfmt has no nesting, so maps and arrays are flattened into
+/// dotted keys, e.g. `map.map_u64=1` or `arr.0=1`.  Empty maps and
+/// arrays produce no output.  Characters which are not permitted in
+/// logfmt keys (spaces, `=`, `"` and control characters) are
+/// replaced with `_`.
+pub struct KvLogfmt<'a> {
+    kvscan: &'a dyn Fn(&mut dyn LogVisitor),
+    prefix: &'static str,
+    suffix: &'static str,
+}
+
+impl<'a> KvLogfmt<'a> {
+    /// Create a `KvLogfmt` ready to be formatted.  `prefix` and
+    /// `suffix` are two strings which are output before and after the
+    /// key-value pairs, but only if the list of key-value pairs is
+    /// non-empty.
+    pub fn new(
+        kvscan: &'a dyn Fn(&mut dyn LogVisitor),
+        prefix: &'static str,
+        suffix: &'static str,
+    ) -> Self {
+        Self {
+            kvscan,
+            prefix,
+            suffix,
+        }
+    }
+}
+
+impl<'a> fmt::Display for KvLogfmt<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut visitor = Visitor {
+            fmt: f,
+            fmtbuf: String::new(),
+            prefix: self.prefix,
+            stack: Vec::new(),
+            empty: true,
+            error: false,
+        };
+        (self.kvscan)(&mut RevealSecrets::new(&mut visitor));
+        if visitor.error {
+            Err(fmt::Error)
+        } else if visitor.empty {
+            Ok(())
+        } else {
+            f.write_str(self.suffix)
+        }
+    }
+}
+
+// Characters which require a value to be quoted
+#[inline]
+fn needs_quotes(ch: char) -> bool {
+    ch <= ' ' || ch == '"' || ch == '=' || ch == '\\' || ch == '\u{7F}'
+}
+
+// This has to be outside Visitor due to borrowing issues
+fn push_str_val(f: &mut fmt::Formatter<'_>, val: &str) -> fmt::Result {
+    if !val.is_empty() && val.find(needs_quotes).is_none() {
+        return f.write_str(val);
+    }
+    f.write_char('"')?;
+    for ch in val.chars() {
+        match ch {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            '\u{0000}'..='\u{001F}' | '\u{007F}' => write!(f, "\\u{:04x}", ch as u32)?,
+            _ => f.write_char(ch)?,
+        }
+    }
+    f.write_char('"')
+}
+
+// Catch error return and set error flag
+macro_rules! catch {
+    ($self:ident, $call:expr) => {{
+        if $call.is_err() {
+            $self.error = true;
+        }
+    }};
+}
+
+struct Visitor<'a, 'b: 'a> {
+    fmt: &'a mut fmt::Formatter<'b>,
+    fmtbuf: String,
+    prefix: &'static str, // Whatever needs adding before the next item, or ""
+    // Key prefix and next array index for each level of nesting
+    stack: Vec<(String, Option<usize>)>,
+    empty: bool,
+    error: bool,
+}
+
+impl<'a, 'b> Visitor<'a, 'b> {
+    // Get the full dotted key for an item
+    fn full_key(&mut self, key: Option<&str>) -> String {
+        let mut full = String::new();
+        let index = match self.stack.last_mut() {
+            Some((prefix, index)) => {
+                full.push_str(prefix);
+                full.push('.');
+                index.as_mut().map(|i| {
+                    *i += 1;
+                    *i - 1
+                })
+            }
+            None => None,
+        };
+        match (index, key) {
+            (Some(i), _) => {
+                let _ = write!(full, "{}", i);
+            }
+            (None, Some(key)) if !key.is_empty() => {
+                full.extend(
+                    key.chars()
+                        .map(|ch| if needs_quotes(ch) { '_' } else { ch }),
+                );
+            }
+            (None, _) => full.push('_'),
+        }
+        full
+    }
+    fn push_key(&mut self, key: Option<&str>) {
+        let key = self.full_key(key);
+        catch!(self, self.fmt.write_str(self.prefix));
+        self.prefix = " ";
+        self.empty = false;
+        catch!(self, self.fmt.write_str(&key));
+        catch!(self, self.fmt.write_char('='));
+    }
+}
+
+impl<'a, 'b> LogVisitor for Visitor<'a, 'b> {
+    fn kv_u64(&mut self, key: Option<&str>, val: u64) {
+        self.push_key(key);
+        catch!(self, write!(self.fmt, "{}", val));
+    }
+    fn kv_i64(&mut self, key: Option<&str>, val: i64) {
+        self.push_key(key);
+        catch!(self, write!(self.fmt, "{}", val));
+    }
+    fn kv_f64(&mut self, key: Option<&str>, val: f64) {
+        self.push_key(key);
+        catch!(self, write!(self.fmt, "{}", val));
+    }
+    fn kv_bool(&mut self, key: Option<&str>, val: bool) {
+        self.push_key(key);
+        catch!(self, write!(self.fmt, "{}", val));
+    }
+    fn kv_null(&mut self, key: Option<&str>) {
+        self.push_key(key);
+    }
+    fn kv_str(&mut self, key: Option<&str>, val: &str) {
+        self.push_key(key);
+        catch!(self, push_str_val(self.fmt, val));
+    }
+    fn kv_fmt(&mut self, key: Option<&str>, val: &Arguments<'_>) {
+        self.push_key(key);
+        if self.fmtbuf.capacity() == 0 {
+            self.fmtbuf = String::with_capacity(1024);
+        }
+        self.fmtbuf.clear();
+        catch!(self, write!(self.fmtbuf, "{}", val));
+        catch!(self, push_str_val(self.fmt, &self.fmtbuf));
+    }
+    fn kv_map(&mut self, key: Option<&str>) {
+        let prefix = self.full_key(key);
+        self.stack.push((prefix, None));
+    }
+    fn kv_mapend(&mut self, _: Option<&str>) {
+        self.stack.pop();
+    }
+    fn kv_arr(&mut self, key: Option<&str>) {
+        let prefix = self.full_key(key);
+        self.stack.push((prefix, Some(0)));
+    }
+    fn kv_arrend(&mut self, _: Option<&str>) {
+        self.stack.pop();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{KvLogfmt, LogVisitor};
+
+    fn kvscan(lv: &mut dyn LogVisitor) {
+        lv.kv_u64(Some("u64"), 123456789);
+        lv.kv_i64(Some("i64"), -123456789);
+        lv.kv_f64(Some("f64"), 12345.6789);
+        lv.kv_bool(Some("b1"), true);
+        lv.kv_null(Some("null"));
+        lv.kv_str(Some("str"), "ABCDEFGHIJ");
+        lv.kv_fmt(Some("fmt"), &format_args!("{}{}{}", "ABC", 123, "DEF"));
+        lv.kv_map(Some("map"));
+        lv.kv_u64(Some("map_u64"), 987654321);
+        lv.kv_map(Some("map_nested"));
+        lv.kv_bool(Some("map_nested_bool"), false);
+        lv.kv_mapend(Some("map_nested"));
+        lv.kv_mapend(Some("map"));
+        lv.kv_map(Some("map_empty"));
+        lv.kv_mapend(Some("map_empty"));
+        lv.kv_arr(Some("arr"));
+        lv.kv_u64(None, 987654321);
+        lv.kv_arr(None);
+        lv.kv_bool(None, true);
+        lv.kv_arrend(None);
+        lv.kv_arrend(Some("arr"));
+    }
+
+    #[test]
+    fn test() {
+        let buf = format!("{}", KvLogfmt::new(&kvscan, "<", ">"));
+        assert_eq!(
+            buf,
+            "<u64=123456789 i64=-123456789 f64=12345.6789 b1=true null= str=ABCDEFGHIJ \
+             fmt=ABC123DEF map.map_u64=987654321 map.map_nested.map_nested_bool=false \
+             arr.0=987654321 arr.1.0=true>"
+        );
+        let buf = format!("{}", KvLogfmt::new(&|_| (), "<", ">"));
+        assert_eq!(buf, "");
+    }
+
+    #[test]
+    fn quoting() {
+        let kvscan = |lv: &mut dyn LogVisitor| {
+            lv.kv_str(Some("space"), "a b");
+            lv.kv_str(Some("quote"), "say \"hi\"");
+            lv.kv_str(Some("equals"), "a=b");
+            lv.kv_str(Some("bsl"), "a\\b");
+            lv.kv_str(Some("ctrl"), "a\nb\tc\u{1}");
+            lv.kv_str(Some("empty"), "");
+            lv.kv_str(Some("utf8"), "héllo");
+            lv.kv_u64(Some("bad key=\"x\""), 1);
+            lv.kv_u64(Some(""), 2);
+        };
+        let buf = format!("{}", KvLogfmt::new(&kvscan, "", ""));
+        assert_eq!(
+            buf,
+            "space=\"a b\" quote=\"say \\\"hi\\\"\" equals=\"a=b\" bsl=\"a\\\\b\" \
+             ctrl=\"a\\nb\\tc\\u0001\" empty=\"\" utf8=héllo bad_key__x_=1 _=2"
+        );
+    }
+}
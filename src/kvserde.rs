@@ -0,0 +1,111 @@
+use crate::tree::{self, Value};
+use serde_json::{Map, Number};
+use stakker::LogVisitor;
+
+/// Conversion of key-value pairs to a `serde_json::Value`
+///
+/// This builds a `serde_json::Value::Object` directly from the
+/// key-value pairs, with nested maps and arrays, which avoids
+/// rendering to a JSON string and parsing it again, e.g. when making
+/// assertions in tests or passing records to code that already uses
+/// **serde**.  Values are converted as for [`KvToJson`]: non-finite
+/// floats become `null`, and formatted values become strings.  Note
+/// that a `serde_json` object keeps only the last value for any
+/// duplicate key, and only keeps the original order of the keys if
+/// its `preserve_order` feature is enabled.
+///
+/// This is only available with the `serde` feature.
+///
+/// [`KvToJson`]: struct.KvToJson.html
+pub struct KvToSerdeValue<'a> {
+    kvscan: &'a dyn Fn(&mut dyn LogVisitor),
+}
+
+impl<'a> KvToSerdeValue<'a> {
+    /// Create a `KvToSerdeValue` ready to build a value from the
+    /// given key-value pairs
+    pub fn new(kvscan: &'a dyn Fn(&mut dyn LogVisitor)) -> Self {
+        Self { kvscan }
+    }
+
+    /// Build the object containing the key-value pairs
+    pub fn build(&self) -> serde_json::Value {
+        to_object(tree::collect_revealed(self.kvscan))
+    }
+}
+
+fn to_object(pairs: Vec<(String, Value)>) -> serde_json::Value {
+    let mut map = Map::new();
+    for (key, val) in pairs {
+        map.insert(key, to_serde(val));
+    }
+    serde_json::Value::Object(map)
+}
+
+fn to_serde(val: Value) -> serde_json::Value {
+    match val {
+        Value::U64(v) => v.into(),
+        Value::I64(v) => v.into(),
+        Value::F64(v) => Number::from_f64(v).map_or(serde_json::Value::Null, Into::into),
+        Value::Bool(v) => v.into(),
+        Value::Null => serde_json::Value::Null,
+        Value::Str(v) => v.into(),
+        Value::Secret(v) => to_serde(*v),
+        Value::Map(pairs) => to_object(pairs),
+        Value::Arr(items) => serde_json::Value::Array(items.into_iter().map(to_serde).collect()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::KvToSerdeValue;
+    use crate::kvjson::test::kvscan_all;
+    use crate::KvToJson;
+    use serde_json::json;
+    use stakker::LogVisitor;
+
+    #[test]
+    fn build() {
+        let val = KvToSerdeValue::new(&kvscan_all).build();
+        assert_eq!(
+            val,
+            json!({
+                "u64": 123456789,
+                "i64": -123456789,
+                "f64": 12345.6789,
+                "b0": false,
+                "b1": true,
+                "null": null,
+                "str": "ABCDEFGHIJ",
+                "str_ctrl": "ABC\tDEF",
+                "str_quote": "ABC\"DEF\"GHI",
+                "str_bsl": "ABC\\DEF\\GHI",
+                "fmt": "ABC123DEF",
+                "map": {
+                    "map_u64": 987654321,
+                    "map_str": "JIHGFEDCBA",
+                    "map_nested": {"map_nested_bool": false},
+                },
+                "map_empty": {},
+                "arr": [987654321, "JIHGFEDCBA", [true]],
+                "arr_empty": [],
+            })
+        );
+        let json = KvToJson::object(&kvscan_all).to_string();
+        assert_eq!(
+            val,
+            serde_json::from_str::<serde_json::Value>(&json).unwrap()
+        );
+        assert_eq!(KvToSerdeValue::new(&|_| ()).build(), json!({}));
+        let kvscan = |lv: &mut dyn LogVisitor| {
+            lv.kv_f64(Some("nan"), f64::NAN);
+            lv.kv_arr(Some("arr"));
+            lv.kv_null(None);
+            lv.kv_arrend(Some("arr"));
+        };
+        assert_eq!(
+            KvToSerdeValue::new(&kvscan).build(),
+            json!({"nan": null, "arr": [null]})
+        );
+    }
+}
@@ -0,0 +1,202 @@
+use crate::tree::{self, Value};
+use stakker::LogVisitor;
+use std::fmt;
+use std::fmt::Write;
+
+/// TOML rendering of key-value pairs
+///
+/// When formatted with `"{}"`, this produces a TOML fragment with one
+/// `key = value` line for each key-value pair, which is useful when
+/// logging an effective configuration.  Nested maps are output as
+/// `[table]` sections after the plain values, and arrays use TOML
+/// array syntax, with any maps inside arrays output as inline tables.
+///
+/// TOML has no null value, so a null is output as a comment line
+/// (`# key = null`) where it occurs as a plain value, and is omitted
+/// where it occurs within an array or inline table.
+///
+/// Unlike the other renderers, this collects all the data before
+/// output, since the TOML table structure can't be generated in a
+/// single pass.
+pub struct KvToToml<'a> {
+    kvscan: &'a dyn Fn(&mut dyn LogVisitor),
+}
+
+impl<'a> KvToToml<'a> {
+    /// Create a `KvToToml` ready to be formatted.  Each line output
+    /// is terminated with `\n`.  Nothing is output if there are no
+    /// key-value pairs.
+    pub fn new(kvscan: &'a dyn Fn(&mut dyn LogVisitor)) -> Self {
+        Self { kvscan }
+    }
+}
+
+impl<'a> fmt::Display for KvToToml<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pairs = tree::collect_revealed(self.kvscan);
+        write_table(f, &mut String::new(), &pairs, true)
+    }
+}
+
+/// Write out a table's plain values, and then its sub-tables
+fn write_table(
+    f: &mut fmt::Formatter<'_>,
+    path: &mut String,
+    pairs: &[(String, Value)],
+    root: bool,
+) -> fmt::Result {
+    if !root {
+        writeln!(f, "[{}]", path)?;
+    }
+    for (key, val) in pairs {
+        match val {
+            Value::Map(_) => (),
+            Value::Null => {
+                f.write_str("# ")?;
+                write_key(f, key)?;
+                f.write_str(" = null\n")?;
+            }
+            _ => {
+                write_key(f, key)?;
+                f.write_str(" = ")?;
+                write_value(f, val)?;
+                f.write_char('\n')?;
+            }
+        }
+    }
+    for (key, val) in pairs {
+        if let Value::Map(sub) = val {
+            let len = path.len();
+            if !root {
+                path.push('.');
+            }
+            write_key(path, key)?;
+            write_table(f, path, sub, false)?;
+            path.truncate(len);
+        }
+    }
+    Ok(())
+}
+
+fn write_value(f: &mut fmt::Formatter<'_>, val: &Value) -> fmt::Result {
+    match val {
+        Value::U64(v) => write!(f, "{}", v),
+        Value::I64(v) => write!(f, "{}", v),
+        Value::F64(v) if v.is_nan() => f.write_str("nan"),
+        // Debug formatting always includes a `.` or exponent, as TOML
+        // requires
+        Value::F64(v) => write!(f, "{:?}", v),
+        Value::Bool(v) => write!(f, "{}", v),
+        Value::Null => Ok(()),
+        Value::Str(v) => write_str_literal(f, v),
+        Value::Secret(v) => write_value(f, v),
+        Value::Arr(items) => {
+            f.write_char('[')?;
+            let mut sep = "";
+            for item in items.iter().filter(|v| **v != Value::Null) {
+                f.write_str(sep)?;
+                sep = ", ";
+                write_value(f, item)?;
+            }
+            f.write_char(']')
+        }
+        Value::Map(pairs) => {
+            f.write_char('{')?;
+            let mut sep = " ";
+            for (key, val) in pairs.iter().filter(|(_, v)| *v != Value::Null) {
+                f.write_str(sep)?;
+                sep = ", ";
+                write_key(f, key)?;
+                f.write_str(" = ")?;
+                write_value(f, val)?;
+            }
+            f.write_str(if pairs.is_empty() { "}" } else { " }" })
+        }
+    }
+}
+
+/// Write a key, bare if possible, otherwise quoted
+fn write_key(f: &mut impl Write, key: &str) -> fmt::Result {
+    let bare = |ch: char| ch.is_ascii_alphanumeric() || ch == '_' || ch == '-';
+    if !key.is_empty() && key.chars().all(bare) {
+        f.write_str(key)
+    } else {
+        write_str_literal(f, key)
+    }
+}
+
+/// TOML basic string quoting
+fn write_str_literal(f: &mut impl Write, val: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for ch in val.chars() {
+        match ch {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            '\u{0000}'..='\u{001F}' | '\u{007F}' => write!(f, "\\u{:04X}", ch as u32)?,
+            _ => f.write_char(ch)?,
+        }
+    }
+    f.write_char('"')
+}
+
+#[cfg(test)]
+mod test {
+    use super::KvToToml;
+
+    #[test]
+    fn table() {
+        let kvscan = |lv: &mut dyn stakker::LogVisitor| {
+            lv.kv_map(Some("server"));
+            lv.kv_str(Some("host"), "example.com");
+            lv.kv_map(Some("tls"));
+            lv.kv_bool(Some("enabled"), true);
+            lv.kv_mapend(Some("tls"));
+            lv.kv_u64(Some("port"), 443);
+            lv.kv_mapend(Some("server"));
+            lv.kv_str(Some("name"), "say \"hi\"\n");
+            lv.kv_f64(Some("ratio"), 1.0);
+            lv.kv_null(Some("user"));
+            lv.kv_i64(Some("dotted.key"), -5);
+        };
+        assert_eq!(
+            KvToToml::new(&kvscan).to_string(),
+            "name = \"say \\\"hi\\\"\\n\"\n\
+             ratio = 1.0\n\
+             # user = null\n\
+             \"dotted.key\" = -5\n\
+             [server]\n\
+             host = \"example.com\"\n\
+             port = 443\n\
+             [server.tls]\n\
+             enabled = true\n"
+        );
+        assert_eq!(KvToToml::new(&|_| ()).to_string(), "");
+    }
+
+    #[test]
+    fn array() {
+        let kvscan = |lv: &mut dyn stakker::LogVisitor| {
+            lv.kv_arr(Some("list"));
+            lv.kv_u64(None, 1);
+            lv.kv_null(None);
+            lv.kv_str(None, "two");
+            lv.kv_arr(None);
+            lv.kv_f64(None, f64::INFINITY);
+            lv.kv_arrend(None);
+            lv.kv_map(None);
+            lv.kv_u64(Some("a"), 3);
+            lv.kv_null(Some("b"));
+            lv.kv_mapend(None);
+            lv.kv_arrend(Some("list"));
+            lv.kv_arr(Some("empty"));
+            lv.kv_arrend(Some("empty"));
+        };
+        assert_eq!(
+            KvToToml::new(&kvscan).to_string(),
+            "list = [1, \"two\", [inf], { a = 3 }]\nempty = []\n"
+        );
+    }
+}
@@ -14,6 +14,16 @@
 //! The `[cx]` comes first, followed by an optional target
 //! specification (`target: "target-name"`), followed by optional
 //! key-value pairs, followed by a format-string and its arguments.
+//! If no target is given, the target is the empty string, unless the
+//! `target-module-path` feature is enabled, in which case it is the
+//! module path of the call site (`module_path!()`), as for the `log`
+//! crate.  With the `target-actor-type` feature, a call made with an
+//! actor context (`[cx]`) or against an actor (`[actor, core]`)
+//! instead defaults to the actor's type name, as given by
+//! `std::any::type_name`, e.g. `my_crate::net::Listener`.  These are
+//! opt-in because changing the target of existing records could
+//! break filters that depend on it.  An explicit `target: ""` always
+//! gives an empty target.
 //!
 //! For [`audit!`], `[cx]` comes first, followed by a tag for the
 //! record, followed by key-value pairs.  The tag will normally be a
@@ -21,6 +31,11 @@
 //! expression in parentheses which will be formatted to generate the
 //! tag.
 //!
+//! [`audit_msg!`] is the same as [`audit!`], but also accepts an
+//! optional format string and arguments after the key-value pairs,
+//! which are output as a `note` key, to give a human-readable
+//! explanation alongside the fixed tag.
+//!
 //! `[cx]` can refer to either an actor context (`stakker::Cx`) or a
 //! [`LogCx`].  Where the call is not being made from a context that
 //! provides a `LogID`, `[core]` may be passed instead of `[cx]`,
@@ -29,7 +44,10 @@
 //! instead of `[cx]`, which takes the `LogID` from that source using
 //! a `source.access_log_id()` call.  (In general the `[a]` form must
 //! support `a.access_log_id()` and `a.access_core()`, and the `[a,b]`
-//! form must support `a.access_log_id()` and `b.access_core()`.)
+//! form must support `a.access_log_id()` and `b.access_core()`.
+//! These requirements are expressed by the [`LogIdSource`] and
+//! [`CoreSource`] traits, which can be implemented for your own
+//! types.)
 //!
 //! For key-value pairs, the most general form is `"key": expr`, but
 //! there are a number of shortcuts as follows:
@@ -44,6 +62,24 @@
 //! `src_addr: %addr` | `"src_addr": format_args!("{}", addr)`
 //! `?stream` | `"stream": format_args!("{:?}", stream)`
 //! `stream: ?input_stream` | `"stream": format_args!("{:?}", input_stream)`
+//! `both!(addr, peer, octets)` | `"addr": format_args!("{}", peer), "addr_octets": peer.octets()`
+//! `payload: hex!(packet.data)` | `"payload": Hex(packet.data.as_ref())`
+//! `secret!(token)` | `"token": Secret(&token)`
+//! `auth: secret!(req.token)` | `"auth": Secret(&req.token)`
+//! `flat!(headers)` | `"name1": value1, "name2": value2, ...` for each entry in `headers`
+//!
+//! `flat!(value)` takes any value implementing [`FlattenVisitable`],
+//! which includes `HashMap` and `BTreeMap` with string-like keys, and
+//! splices its entries into the record as separate key-value pairs,
+//! for when the keys aren't known until runtime.  Note that a
+//! `HashMap` gives its entries in an arbitrary order.  This uses the
+//! same form as `both!` rather than a `flat:` prefix, since that
+//! would change the meaning of existing `flat: value` pairs.
+//!
+//! The format-string arguments are only evaluated if the record
+//! passes the logger's level filter, so expensive arguments cost
+//! nothing when that level is disabled.  Key-value values are only
+//! visited when the logger asks for them.
 //!
 //! Conversion of values is determined by implementation of the
 //! [`Visitable`] trait.  All Rust primitives and standard collections
@@ -54,34 +90,188 @@
 //! primitive instead of a string, you could also output a structured
 //! value such as an array or map to represent your type.
 //!
+//! # Disabling levels
+//!
+//! Levels can be disabled in two ways:
+//!
+//! - At run-time, by the `LogFilter` passed when setting the logger.
+//!   A disabled call costs a check of the filter.  The format
+//!   arguments and plain key-value values are not evaluated, but
+//!   values wrapped with `%`, `?`, `hex!`, `secret!`, `flat!` or
+//!   `both!` are evaluated and borrowed before the check.
+//!
+//! - At compile-time, using one of the `max_level_*` cargo features.
+//!   Calls for the disabled levels expand to code under a constant
+//!   `false` condition.  This means that their arguments are still
+//!   type-checked, so that logging code for disabled levels can't
+//!   bit-rot and bindings only used in logging don't give
+//!   unused-variable warnings, but the code is never run, and the
+//!   optimizer removes it from the executable.
+//!
+//! Disabled calls are always type-checked.  A mode which removes
+//! them without type-checking is not provided.
+//!
+//! # Deriving `Visitable`
+//!
+//! With the `derive` feature, [`Visitable`] can be derived for your
+//! own types.  A struct with named fields is output as a map of its
+//! fields, a tuple struct as an array, and an enum as a map with the
+//! variant name under the key `variant` plus the variant's fields.
+//! Fields may be marked with `#[log(skip)]` to leave them out,
+//! `#[log(rename = "name")]` to change the key, `#[log(display)]` or
+//! `#[log(debug)]` to output their `Display` or `Debug` formatting
+//! instead, or `#[log(flatten)]` to output the entries of a nested
+//! struct or map directly in the enclosing map (see [`Flatten`]).
+//! For example:
+//!
+//! ```ignore
+//! #[derive(Visitable)]
+//! struct Request {
+//!     id: u64,
+//!     #[log(display)]
+//!     addr: SocketAddr,
+//!     #[log(flatten)]
+//!     peer: Peer,
+//!     #[log(skip)]
+//!     body: Vec<u8>,
+//! }
+//! ```
+//!
 //! # Logging output
 //!
 //! You can write you own code which accepts a `&dyn Fn(&mut dyn
 //! LogVisitor)`, and calls it to receive all the logging data.  There
-//! are also provided types for JSON output ([`KvToJson`]) and simple
-//! human-readable output ([`KvSingleLine`]).
+//! are also provided types for JSON output ([`KvToJson`]), TOML
+//! output ([`KvToToml`]), logfmt output ([`KvLogfmt`]), CSV output
+//! against a fixed list of columns ([`KvToCsv`]) and simple
+//! human-readable output ([`KvSingleLine`]).  Whole records can be
+//! rendered in the AWS CloudWatch Embedded Metric Format with
+//! [`EmfRecord`], to publish selected values as metrics.  With the
+//! `serde` feature, `KvToSerdeValue` builds a `serde_json::Value`
+//! directly from the key-value pairs.  To keep
+//! secrets out of the output, wrap the visitor passed to the
+//! `kvscan` closure in a [`RedactingVisitor`], and to limit the size
+//! of string values, in a [`TruncatingVisitor`].  To namespace the
+//! top-level keys, wrap it in a [`PrefixVisitor`].  For benchmarks
+//! and tests, [`NullVisitor`] discards the key-value pairs, and
+//! [`CountingVisitor`] counts them.
+//!
+//! # Cargo features
 //!
+//! Feature | Effect
+//! --- | ---
+//! `log` | Enables [`loggers::LogCrateBridge`]
+//! `slog` | Enables [`loggers::SlogBridge`]
+//! `tracing` | Enables [`loggers::TracingBridge`]
+//! `derive` | Enables `#[derive(Visitable)]`.  See [Deriving `Visitable`](#deriving-visitable)
+//! `prost` | Enables `AsProst`, to output protobuf messages as maps of their fields.  This adds a dependency on `prost-reflect`, since `prost` has no runtime reflection
+//! `serde` | Enables `KvToSerdeValue`, to convert key-value pairs directly to a `serde_json::Value`
+//! `journald` | Enables [`loggers::journald_sink`] for the systemd journal (Linux only)
+//! `windows` | Enables `loggers::EventLogSink` for the Windows Event Log (Windows only)
+//! `max_level_off`, `max_level_error`, `max_level_warn`, `max_level_info`, `max_level_debug`, `max_level_trace` | Compiles out the logging macros for all severity levels above the given one, e.g. with `max_level_info`, [`debug!`] and [`trace!`] calls are still type-checked but generate no code, and their arguments are never evaluated.  See [Disabling levels](#disabling-levels).  If several are enabled, the most restrictive applies.  Audit records are not affected.
+//! `recent-messages` | Adds a `recent` array field to every error record logged by the macros, containing the most recent non-error messages logged on the same thread by the macros (as `"LEVEL message"`, oldest first), to show what led up to the error.  Only records that pass the logger's filter are recorded.  This costs formatting each message a second time and storing it, plus the memory for the ring, which by default holds 8 messages and can be resized with [`set_recent_capacity`].
+//! `template-hash` | Adds a `template_hash` field to every record logged by the macros, containing a 64-bit FNV-1a hash of the format string literal as 16 hex digits.  This is calculated at compile time.  Records from the same message template get the same hash whatever the interpolated values, and unlike a hash of the source location it doesn't change when code is moved, so it can be used to group similar records in log analysis.  Different call sites with the same template share a hash.
+//! `location` | Adds a `loc` field to every record logged by the macros, giving the source file and line of the call site, e.g. `"src/net.rs:42"`, ahead of the key-value pairs of the call.  This helps with crash triage.  If the call gives its own `loc` key, that is output instead, so there is never more than one `loc` key.  Any other top-level `loc`, from a `flat!` value or the `LogContext`, is output as `kv.loc`.
+//! `target-module-path` | Makes the target of records logged by the macros default to the module path of the call site, when no `target:` is given.  Otherwise it defaults to the empty string.
+//! `target-actor-type` | Makes the target of records logged by the macros against an actor default to the actor's type name, when no `target:` is given.  This takes precedence over `target-module-path`.  The name comes from `std::any::type_name`, which is resolved at compile time, so there is no runtime cost beyond passing a different `&'static str`, but its exact format is not guaranteed to be stable between compiler versions, and it includes any type parameters.  Custom `LogID` sources must implement [`LogIdSource`] rather than just providing an inherent `access_log_id` method.
+//! `default_json`, `default_line` | Selects the logger installed by [`quick_init`], as JSON lines or as single lines of text.  `default_json` takes precedence, and the default is `default_line`.
+//! `test-util` | Enables [`capture_logs`] and the `assert_logs!` macro, for checking the records logged by code under test.
+//! `render-timing` | Adds a `_render_ns` field to every record logged by the macros, giving the time in nanoseconds taken to visit its key-value pairs.  This is a profiling aid which adds overhead to every record, so it should not be enabled in production.
+//!
+//! [`CoreSource`]: trait.CoreSource.html
+//! [`CountingVisitor`]: struct.CountingVisitor.html
+//! [`EmfRecord`]: struct.EmfRecord.html
+//! [`Flatten`]: struct.Flatten.html
+//! [`FlattenVisitable`]: trait.FlattenVisitable.html
+//! [`KvLogfmt`]: struct.KvLogfmt.html
 //! [`KvSingleLine`]: struct.KvSingleLine.html
+//! [`KvToCsv`]: struct.KvToCsv.html
 //! [`KvToJson`]: struct.KvToJson.html
+//! [`KvToToml`]: struct.KvToToml.html
 //! [`LogCx`]: struct.LogCx.html
+//! [`LogIdSource`]: trait.LogIdSource.html
+//! [`NullVisitor`]: struct.NullVisitor.html
+//! [`PrefixVisitor`]: struct.PrefixVisitor.html
+//! [`RedactingVisitor`]: struct.RedactingVisitor.html
+//! [`capture_logs`]: fn.capture_logs.html
+//! [`quick_init`]: fn.quick_init.html
+//! [`TruncatingVisitor`]: struct.TruncatingVisitor.html
 //! [`Visitable`]: trait.Visitable.html
 //! [`audit!`]: macro.audit.html
+//! [`audit_msg!`]: macro.audit_msg.html
 //! [`debug!`]: macro.debug.html
 //! [`error!`]: macro.error.html
 //! [`info!`]: macro.info.html
+//! [`loggers::LogCrateBridge`]: loggers/struct.LogCrateBridge.html
+//! [`loggers::SlogBridge`]: loggers/struct.SlogBridge.html
+//! [`loggers::TracingBridge`]: loggers/struct.TracingBridge.html
+//! [`loggers::journald_sink`]: loggers/fn.journald_sink.html
+//! [`set_recent_capacity`]: fn.set_recent_capacity.html
 //! [`trace!`]: macro.trace.html
 //! [`warn!`]: macro.warn.html
 
+mod adapters;
+mod context;
+mod emf;
+mod kvcsv;
 mod kvdisp;
 mod kvjson;
+mod kvlogfmt;
+#[cfg(feature = "serde")]
+mod kvserde;
+mod kvtoml;
+mod line;
 mod logcx;
+pub mod loggers;
+#[doc(hidden)]
+pub mod macro_support;
 mod macros;
+#[cfg(feature = "prost")]
+mod proto;
+mod recent;
+mod sink;
+mod snapshot;
+mod source;
+#[cfg(feature = "test-util")]
+mod testing;
+mod tree;
 mod visit;
 
+pub use adapters::{
+    CountingVisitor, NullVisitor, PrefixVisitor, RedactingVisitor, TruncatingVisitor,
+};
+pub use context::{LogContext, LogContextGuard};
+pub use emf::{EmfRecord, EmfSpec};
+pub use kvcsv::KvToCsv;
 pub use kvdisp::KvSingleLine;
-pub use kvjson::KvToJson;
+pub use kvjson::{KvToJson, KvToJsonPretty, NullRendering};
+pub use kvlogfmt::KvLogfmt;
+#[cfg(feature = "serde")]
+pub use kvserde::KvToSerdeValue;
+pub use kvtoml::KvToToml;
+pub use line::{Color, LineRecord};
 pub use logcx::LogCx;
-pub use visit::Visitable;
+#[cfg(feature = "prost")]
+pub use proto::AsProst;
+pub use recent::set_recent_capacity;
+pub use sink::{
+    quick_init, split_audit, stderr_sink, write_json_sink, write_sink, BoxedLogger, JsonArraySink,
+    LabeledSink, LevelRoutingSink, MapRecordSink, RateLimitSink, RecordView, TypeProfiler,
+};
+pub use snapshot::{register_snapshot, SnapshotGuard, Snapshots};
+pub use source::{CoreSource, LogIdSource};
+#[cfg(feature = "test-util")]
+pub use testing::capture_logs;
+pub use tree::Value;
+pub use visit::{
+    Aggregation, AsDisplay, BacktraceStructured, Base64, Bounded, ByteStats, Checksummed, Contains,
+    DurationBoth, ErrInfo, ErrorChain, Flatten, FlattenVisitable, FloatInfo, Hex, HexGrouped,
+    HexUpper, Interval, Joined, LockStatus, MergePatch, Metric, Pairs, Secret, SparseArr,
+    VirtualTime, Visitable,
+};
+
+#[cfg(feature = "derive")]
+pub use stakker_log_derive::Visitable;
 
 // Re-export so that macros can access stakker::LogLevel
 #[doc(hidden)]
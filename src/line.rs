@@ -0,0 +1,238 @@
+use crate::tree::{self, Value};
+use crate::{KvSingleLine, Visitable};
+use stakker::{LogLevel, LogRecord, LogVisitor};
+use std::fmt;
+use std::io::IsTerminal;
+
+/// Whether to use ANSI colors in output
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Color {
+    /// Never use color
+    Never,
+    /// Always use color
+    Always,
+    /// Use color if the output is a terminal
+    Auto,
+}
+
+impl Color {
+    /// Resolve whether color is enabled for output to a stream with
+    /// the given terminal status
+    pub fn enabled(self, stream: &impl IsTerminal) -> bool {
+        match self {
+            Color::Never => false,
+            Color::Always => true,
+            Color::Auto => stream.is_terminal(),
+        }
+    }
+}
+
+/// Single-line rendering of a whole log record
+///
+/// When formatted with `"{}"`, this produces the level, the `LogID`,
+/// the target (if not empty), the freeform text and the key-value
+/// pairs as rendered by [`KvSingleLine`], all on a single line, for
+/// example:
+///
+/// ```text
+/// WARN  #12 net: Connection lost addr=10.0.0.1 retries=3
+/// ```
+///
+/// No newline is added at the end.  Note that the freeform text is
+/// output as it is, so unlike the key-value pairs it is not
+/// guaranteed to contain no newlines.
+///
+/// The layout can be adjusted to suit the consumer of the logs, by
+/// putting the freeform text after the key-value pairs with
+/// [`LineRecord::message_last`], and by moving selected keys to the
+/// front with [`LineRecord::pinned_keys`].  Where the key-value pairs
+/// must appear exactly in the order they were logged, use
+/// [`LineRecord::preserve_order`], which overrides any reordering.
+///
+/// [`KvSingleLine`]: struct.KvSingleLine.html
+/// [`LineRecord::message_last`]: struct.LineRecord.html#method.message_last
+/// [`LineRecord::pinned_keys`]: struct.LineRecord.html#method.pinned_keys
+/// [`LineRecord::preserve_order`]: struct.LineRecord.html#method.preserve_order
+pub struct LineRecord<'a, 'b> {
+    r: &'a LogRecord<'b>,
+    color: bool,
+    message_last: bool,
+    pinned: &'a [&'a str],
+    preserve_order: bool,
+}
+
+impl<'a, 'b> LineRecord<'a, 'b> {
+    /// Create a `LineRecord` ready to be formatted
+    pub fn new(r: &'a LogRecord<'b>) -> Self {
+        Self {
+            r,
+            color: false,
+            message_last: false,
+            pinned: &[],
+            preserve_order: false,
+        }
+    }
+
+    /// Output the freeform text after the key-value pairs instead of
+    /// before them
+    pub fn message_last(mut self) -> Self {
+        self.message_last = true;
+        self
+    }
+
+    /// Output any top-level key-value pairs with the given keys
+    /// first, in the order of this list, followed by the remaining
+    /// pairs in their original order.  For example with `&["error",
+    /// "request_id"]`, those keys are always found at the start of
+    /// the key-value pairs, making logs easier to scan.  To do this
+    /// the pairs have to be collected and reordered, which costs an
+    /// allocation per record, so this is skipped if the list is
+    /// empty.
+    pub fn pinned_keys(mut self, keys: &'a [&'a str]) -> Self {
+        self.pinned = keys;
+        self
+    }
+
+    /// Guarantee that the key-value pairs are output in exactly the
+    /// order they were logged.  This takes precedence over
+    /// [`LineRecord::pinned_keys`], so that code which sets up pinned
+    /// keys for general use can still get strict emission order where
+    /// the order is significant.  It doesn't affect the position of
+    /// the freeform text set by [`LineRecord::message_last`].
+    ///
+    /// [`LineRecord::message_last`]: struct.LineRecord.html#method.message_last
+    /// [`LineRecord::pinned_keys`]: struct.LineRecord.html#method.pinned_keys
+    pub fn preserve_order(mut self) -> Self {
+        self.preserve_order = true;
+        self
+    }
+
+    /// Color the level according to its severity (red for errors,
+    /// yellow for warnings, and so on), and dim the keys, using ANSI
+    /// escape sequences
+    pub fn ansi_color(mut self) -> Self {
+        self.color = true;
+        self
+    }
+}
+
+impl<'a, 'b> fmt::Display for LineRecord<'a, 'b> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let r = self.r;
+        if self.color {
+            let color = match r.level {
+                LogLevel::Error => "\x1b[1;31m",
+                LogLevel::Warn => "\x1b[33m",
+                LogLevel::Info => "\x1b[32m",
+                LogLevel::Debug => "\x1b[34m",
+                LogLevel::Trace => "\x1b[2m",
+                _ => "\x1b[35m",
+            };
+            write!(f, "{}{:5}\x1b[0m", color, r.level.name())?;
+        } else {
+            write!(f, "{:5}", r.level.name())?;
+        }
+        write!(f, " #{} ", r.id)?;
+        if !r.target.is_empty() {
+            write!(f, "{}: ", r.target)?;
+        }
+        let reorder = !self.pinned.is_empty() && !self.preserve_order;
+        let pairs = if !reorder {
+            Vec::new()
+        } else {
+            pinned_first(tree::collect(r.kvscan), self.pinned)
+        };
+        let reordered = |output: &mut dyn LogVisitor| {
+            for (key, val) in &pairs {
+                val.visit(Some(key), output);
+            }
+        };
+        let kvscan: &dyn Fn(&mut dyn LogVisitor) = if reorder { &reordered } else { r.kvscan };
+        let kv = if self.message_last {
+            KvSingleLine::new(kvscan, "", " ")
+        } else {
+            KvSingleLine::new(kvscan, " ", "")
+        };
+        let kv = if self.color { kv.ansi_color() } else { kv };
+        if self.message_last {
+            write!(f, "{}{}", kv, r.fmt)
+        } else {
+            write!(f, "{}{}", r.fmt, kv)
+        }
+    }
+}
+
+// Move pairs with pinned keys to the front, in the order of `pinned`
+pub(crate) fn pinned_first(
+    mut pairs: Vec<(String, Value)>,
+    pinned: &[&str],
+) -> Vec<(String, Value)> {
+    let mut out = Vec::with_capacity(pairs.len());
+    for key in pinned {
+        let mut i = 0;
+        while i < pairs.len() {
+            if pairs[i].0 == *key {
+                out.push(pairs.remove(i));
+            } else {
+                i += 1;
+            }
+        }
+    }
+    out.append(&mut pairs);
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::LineRecord;
+    use stakker::{LogLevel, LogRecord, LogVisitor};
+
+    #[test]
+    fn layout() {
+        let kvscan = |output: &mut dyn LogVisitor| {
+            output.kv_u64(Some("port"), 80);
+            output.kv_str(Some("request_id"), "r1");
+            output.kv_str(Some("error"), "refused");
+            output.kv_u64(Some("retries"), 3);
+        };
+        let r = LogRecord {
+            id: 12,
+            level: LogLevel::Warn,
+            target: "net",
+            fmt: format_args!("Connection lost"),
+            kvscan: &kvscan,
+        };
+        assert_eq!(
+            LineRecord::new(&r).to_string(),
+            "WARN  #12 net: Connection lost port=80 request_id=r1 error=refused retries=3"
+        );
+        assert_eq!(
+            LineRecord::new(&r).message_last().to_string(),
+            "WARN  #12 net: port=80 request_id=r1 error=refused retries=3 Connection lost"
+        );
+        assert_eq!(
+            LineRecord::new(&r)
+                .pinned_keys(&["error", "request_id", "missing"])
+                .to_string(),
+            "WARN  #12 net: Connection lost error=refused request_id=r1 port=80 retries=3"
+        );
+        assert_eq!(
+            LineRecord::new(&r)
+                .pinned_keys(&["error", "request_id"])
+                .preserve_order()
+                .to_string(),
+            "WARN  #12 net: Connection lost port=80 request_id=r1 error=refused retries=3"
+        );
+        let empty = LogRecord {
+            id: 12,
+            level: LogLevel::Warn,
+            target: "net",
+            fmt: format_args!("Connection lost"),
+            kvscan: &|_| (),
+        };
+        assert_eq!(
+            LineRecord::new(&empty).message_last().to_string(),
+            "WARN  #12 net: Connection lost"
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use crate::{LogIdSource, VirtualTime};
 use stakker::{Core, LogID};
 
 /// Logging context
@@ -22,6 +23,17 @@ impl<'a> LogCx<'a> {
         Self { logid, core }
     }
 
+    /// Create from any [`LogIdSource`], such as an `Actor`, and a
+    /// `Core` reference.  This allows code running outside of an
+    /// actor's context, e.g. a background task, to log against that
+    /// actor's `LogID` through a `LogCx` created once, instead of
+    /// using `[actor, core]` at every call site.
+    ///
+    /// [`LogIdSource`]: trait.LogIdSource.html
+    pub fn from_source(source: &impl LogIdSource, core: &'a mut Core) -> Self {
+        Self::new(source.access_log_id(), core)
+    }
+
     /// Used by macros to obtain the `LogID`
     pub fn access_log_id(&self) -> LogID {
         self.logid
@@ -31,4 +43,30 @@ impl<'a> LogCx<'a> {
     pub fn access_core(&mut self) -> &mut Core {
         self.core
     }
+
+    /// Run `f` with a `LogCx` for a new child span
+    ///
+    /// **Stakker** allocates `LogID` values itself, sequentially,
+    /// when a span is opened, so the child `LogID` is obtained by
+    /// calling `Core::log_span_open`, which logs an OPEN record with
+    /// the given tag and with this context's `LogID` as the `parent`.
+    /// Everything logged through the child `LogCx` is then logged
+    /// against the child `LogID`, which gives request handlers and
+    /// the like a correlation ID without having to thread it through
+    /// explicitly.  When `f` returns, a CLOSE record is logged for the
+    /// child span, and logging continues against this context's own
+    /// `LogID`.  Note that if the **Stakker** `logger` feature is not
+    /// enabled, no IDs are allocated and the child `LogID` is zero.
+    pub fn with_child<R>(&mut self, tag: &str, f: impl FnOnce(&mut LogCx<'_>) -> R) -> R {
+        let id = self.core.log_span_open(tag, self.logid, |_| {});
+        let ret = f(&mut LogCx::new(id, self.core));
+        self.core.log_span_close(id, format_args!(""), |_| {});
+        ret
+    }
+
+    /// Get the current virtual time from `Core`, in a form that can
+    /// be logged
+    pub fn now(&self) -> VirtualTime {
+        VirtualTime::now(self.core)
+    }
 }
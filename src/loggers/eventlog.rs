@@ -0,0 +1,144 @@
+use crate::tree;
+use crate::{KvSingleLine, Visitable};
+use stakker::{Core, LogLevel, LogRecord, LogVisitor};
+use std::ffi::c_void;
+use std::io;
+use std::ptr;
+
+const EVENTLOG_ERROR_TYPE: u16 = 0x0001;
+const EVENTLOG_WARNING_TYPE: u16 = 0x0002;
+const EVENTLOG_INFORMATION_TYPE: u16 = 0x0004;
+
+#[link(name = "advapi32")]
+extern "system" {
+    fn RegisterEventSourceW(server: *const u16, source: *const u16) -> *mut c_void;
+    fn DeregisterEventSource(handle: *mut c_void) -> i32;
+    fn ReportEventW(
+        handle: *mut c_void,
+        event_type: u16,
+        category: u16,
+        event_id: u32,
+        user_sid: *mut c_void,
+        num_strings: u16,
+        data_size: u32,
+        strings: *const *const u16,
+        raw_data: *mut c_void,
+    ) -> i32;
+}
+
+/// Convert to a NUL-terminated UTF-16 string
+fn wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(Some(0)).collect()
+}
+
+/// Logger which writes records to the Windows Event Log
+///
+/// This is only available on Windows, with the `windows` feature.
+/// Each record is reported with the `ReportEvent` API under the event
+/// source given when the sink is created, with the event type mapped
+/// from the level as follows:
+///
+/// **Stakker** level | Event type
+/// --- | ---
+/// `Error` | Error
+/// `Warn` | Warning
+/// All others | Information
+///
+/// The insertion strings are the freeform text (prefixed with the
+/// target, if any), then `logid=N`, then one string for each
+/// top-level key-value pair, as `key=value` rendered with
+/// [`KvSingleLine`].  The event ID is always 0.  Unless a message
+/// file has been registered for the event source, the Event Viewer
+/// will say that the description can't be found, but it will still
+/// show the insertion strings.  To install it:
+///
+/// ```ignore
+/// let mut sink = EventLogSink::new("MyService")?;
+/// stakker.set_logger(LogFilter::all(&[LogLevel::Info]), move |core, r| sink.log(core, r));
+/// ```
+///
+/// [`KvSingleLine`]: ../struct.KvSingleLine.html
+pub struct EventLogSink {
+    handle: *mut c_void,
+}
+
+impl EventLogSink {
+    /// Register the given event source on the local machine, and
+    /// create a sink which reports events under it
+    pub fn new(source: &str) -> io::Result<Self> {
+        let source = wide(source);
+        // Safety: `source` is a valid NUL-terminated UTF-16 string
+        let handle = unsafe { RegisterEventSourceW(ptr::null(), source.as_ptr()) };
+        if handle.is_null() {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(Self { handle })
+        }
+    }
+
+    /// Report a log record to the Event Log.  Errors are ignored.
+    pub fn log(&mut self, _core: &mut Core, r: &LogRecord<'_>) {
+        let event_type = match r.level {
+            LogLevel::Error => EVENTLOG_ERROR_TYPE,
+            LogLevel::Warn => EVENTLOG_WARNING_TYPE,
+            _ => EVENTLOG_INFORMATION_TYPE,
+        };
+        let mut strings = Vec::new();
+        if r.target.is_empty() {
+            strings.push(wide(&r.fmt.to_string()));
+        } else {
+            strings.push(wide(&format!("{}: {}", r.target, r.fmt)));
+        }
+        strings.push(wide(&format!("logid={}", r.id)));
+        for (key, val) in tree::collect_revealed(r.kvscan) {
+            let kvscan = |lv: &mut dyn LogVisitor| val.visit(Some(&key), lv);
+            strings.push(wide(&KvSingleLine::new(&kvscan, "", "").to_string()));
+        }
+        strings.truncate(u16::MAX as usize);
+        let ptrs: Vec<*const u16> = strings.iter().map(|s| s.as_ptr()).collect();
+        // Safety: `ptrs` holds `ptrs.len()` pointers to valid
+        // NUL-terminated UTF-16 strings, which outlive the call
+        unsafe {
+            ReportEventW(
+                self.handle,
+                event_type,
+                0,
+                0,
+                ptr::null_mut(),
+                ptrs.len() as u16,
+                0,
+                ptrs.as_ptr(),
+                ptr::null_mut(),
+            );
+        }
+    }
+}
+
+impl Drop for EventLogSink {
+    fn drop(&mut self) {
+        // Safety: handle came from a successful RegisterEventSourceW
+        unsafe {
+            DeregisterEventSource(self.handle);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::EventLogSink;
+    use crate::{error, info};
+    use stakker::{LogFilter, LogLevel, Stakker};
+    use std::time::Instant;
+
+    #[test]
+    fn event_log() {
+        let mut sink = EventLogSink::new("stakker_log test").unwrap();
+        let mut s = Stakker::new(Instant::now());
+        s.set_logger(LogFilter::all(&[LogLevel::Info]), move |core, r| {
+            sink.log(core, r)
+        });
+        let s = &mut s;
+        info!([s], port: 80, "Started");
+        error!([s], target: "net", list: [1, 2], "Failed");
+    }
+}
@@ -0,0 +1,144 @@
+use crate::sink::BoxedLogger;
+use crate::tree::{self, Value};
+use crate::{KvToJson, Visitable};
+use stakker::{LogLevel, LogRecord, LogVisitor};
+use std::io;
+use std::os::unix::net::UnixDatagram;
+
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Logger which writes records to the systemd journal
+///
+/// This is only available on Linux, with the `journald` feature.
+/// Each record is sent as a single datagram over the journald native
+/// protocol, with these fields:
+///
+/// - `MESSAGE`: The freeform text
+/// - `PRIORITY`: The syslog priority from the level: `Error` is 3,
+///   `Warn` is 4, `Info`, `Audit`, `Open` and `Close` are 6, and
+///   `Debug` and `Trace` are 7
+/// - `STAKKER_TARGET`: The target, if not empty
+/// - `STAKKER_LOGID`: The `LogID`
+///
+/// followed by one field for each top-level key-value pair.  Since
+/// journald requires field names to be made up of `A-Z`, `0-9` and
+/// `_`, keys are converted to uppercase and other characters are
+/// replaced with `_`.  Leading underscores are removed (these are
+/// reserved for trusted fields), and keys that would then be empty or
+/// start with a digit get an `F_` prefix.  Names are truncated to 64
+/// characters.  Keys that map to the same name produce repeated
+/// fields, which journald accepts.  Maps and arrays are passed as
+/// their [`KvToJson`] rendering, and null as an empty value.
+///
+/// Records too large to send as one datagram are dropped, as are any
+/// records that fail to send.  To install it:
+///
+/// ```ignore
+/// stakker.set_logger(LogFilter::all(&[LogLevel::Info]), journald_sink()?);
+/// ```
+///
+/// [`KvToJson`]: ../struct.KvToJson.html
+pub fn journald_sink() -> io::Result<BoxedLogger> {
+    let socket = UnixDatagram::unbound()?;
+    socket.connect(JOURNALD_SOCKET)?;
+    Ok(Box::new(move |_, r| {
+        let _ = socket.send(&payload(r));
+    }))
+}
+
+/// Encode a record as a journald native protocol datagram
+fn payload(r: &LogRecord<'_>) -> Vec<u8> {
+    let priority = match r.level {
+        LogLevel::Error => 3,
+        LogLevel::Warn => 4,
+        LogLevel::Debug | LogLevel::Trace => 7,
+        _ => 6,
+    };
+    let mut out = Vec::new();
+    add_field(&mut out, "MESSAGE", &r.fmt.to_string());
+    add_field(&mut out, "PRIORITY", &priority.to_string());
+    if !r.target.is_empty() {
+        add_field(&mut out, "STAKKER_TARGET", r.target);
+    }
+    add_field(&mut out, "STAKKER_LOGID", &r.id.to_string());
+    for (key, val) in tree::collect_revealed(r.kvscan) {
+        let text = match &val {
+            Value::U64(v) => v.to_string(),
+            Value::I64(v) => v.to_string(),
+            Value::F64(v) => v.to_string(),
+            Value::Bool(v) => v.to_string(),
+            Value::Null => String::new(),
+            Value::Str(v) => v.clone(),
+            Value::Map(_) | Value::Arr(_) | Value::Secret(_) => {
+                let kvscan = |lv: &mut dyn LogVisitor| val.visit(None, lv);
+                KvToJson::new(&kvscan, "", "").to_string()
+            }
+        };
+        add_field(&mut out, &field_name(&key), &text);
+    }
+    out
+}
+
+/// Append a field.  Values containing a newline must use the
+/// length-prefixed binary form.
+fn add_field(out: &mut Vec<u8>, name: &str, val: &str) {
+    out.extend_from_slice(name.as_bytes());
+    if val.contains('\n') {
+        out.push(b'\n');
+        out.extend_from_slice(&(val.len() as u64).to_le_bytes());
+    } else {
+        out.push(b'=');
+    }
+    out.extend_from_slice(val.as_bytes());
+    out.push(b'\n');
+}
+
+/// Convert a key to a valid journald field name.  Leading `_` are
+/// trimmed after mapping other characters to `_`, since fields
+/// starting with `_` are reserved for journald's trusted fields.
+fn field_name(key: &str) -> String {
+    let name: String = key
+        .chars()
+        .map(|c| match c {
+            'A'..='Z' | '0'..='9' | '_' => c,
+            'a'..='z' => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect();
+    let mut name = name.trim_start_matches('_').to_string();
+    if !name.starts_with(|c: char| c.is_ascii_uppercase()) {
+        name.insert_str(0, "F_");
+    }
+    name.truncate(64);
+    name
+}
+
+#[cfg(test)]
+mod test {
+    use super::{add_field, field_name};
+
+    #[test]
+    fn field_names() {
+        assert_eq!(field_name("port"), "PORT");
+        assert_eq!(field_name("Remote.Addr"), "REMOTE_ADDR");
+        assert_eq!(field_name("req-id"), "REQ_ID");
+        assert_eq!(field_name("__secret"), "SECRET");
+        assert_eq!(field_name("größe"), "GR__E");
+        assert_eq!(field_name("2xx"), "F_2XX");
+        assert_eq!(field_name(""), "F_");
+        assert_eq!(field_name("_"), "F_");
+        assert_eq!(field_name("-port"), "PORT");
+        assert_eq!(field_name(".x"), "X");
+        assert_eq!(field_name("_-_9"), "F_9");
+        assert_eq!(field_name("é"), "F_");
+        assert_eq!(field_name(&"k".repeat(100)), "K".repeat(64));
+    }
+
+    #[test]
+    fn fields() {
+        let mut out = Vec::new();
+        add_field(&mut out, "A", "one");
+        add_field(&mut out, "B", "x\ny");
+        assert_eq!(out, b"A=one\nB\n\x03\0\0\0\0\0\0\0x\ny\n");
+    }
+}
@@ -0,0 +1,156 @@
+use crate::KvSingleLine;
+use stakker::{Core, LogLevel, LogRecord};
+
+/// Bridge to forward records to the `log` crate's global logger
+///
+/// This allows records to be output through an existing `log`
+/// backend such as `env_logger`.  To install it:
+///
+/// ```ignore
+/// let mut bridge = LogCrateBridge::new();
+/// stakker.set_logger(LogFilter::all(&[LogLevel::Info]), move |core, r| bridge.log(core, r));
+/// ```
+///
+/// Levels are mapped as follows:
+///
+/// **Stakker** level | `log` level
+/// --- | ---
+/// `Trace` | `Trace`
+/// `Debug` | `Debug`
+/// `Info` | `Info`
+/// `Warn` | `Warn`
+/// `Error` | `Error`
+/// `Audit`, `Open`, `Close` | `Info`, or as set by [`LogCrateBridge::other_level`]
+///
+/// The freeform text becomes the message, followed by the key-value
+/// pairs formatted with [`KvSingleLine`], so that no data is lost.
+/// The record target is passed through, or `"stakker"` if it is
+/// empty.  The `LogID` is passed as the structured key `logid`.
+///
+/// [`KvSingleLine`]: ../struct.KvSingleLine.html
+/// [`LogCrateBridge::other_level`]: struct.LogCrateBridge.html#method.other_level
+pub struct LogCrateBridge {
+    other_level: log::Level,
+}
+
+impl LogCrateBridge {
+    /// Create a new bridge
+    pub fn new() -> Self {
+        Self {
+            other_level: log::Level::Info,
+        }
+    }
+
+    /// Set the `log` level used for **Stakker** levels that have no
+    /// equivalent, i.e. `Audit`, `Open` and `Close`.  Defaults to
+    /// `Info`.
+    pub fn other_level(mut self, level: log::Level) -> Self {
+        self.other_level = level;
+        self
+    }
+
+    /// Forward a log record to the `log` crate's global logger
+    pub fn log(&mut self, _core: &mut Core, r: &LogRecord<'_>) {
+        let level = match r.level {
+            LogLevel::Trace => log::Level::Trace,
+            LogLevel::Debug => log::Level::Debug,
+            LogLevel::Info => log::Level::Info,
+            LogLevel::Warn => log::Level::Warn,
+            LogLevel::Error => log::Level::Error,
+            LogLevel::Off => return,
+            _ => self.other_level,
+        };
+        let target = if r.target.is_empty() {
+            "stakker"
+        } else {
+            r.target
+        };
+        let logger = log::logger();
+        let kvs = [("logid", r.id)];
+        let metadata = log::Metadata::builder().level(level).target(target).build();
+        if logger.enabled(&metadata) {
+            logger.log(
+                &log::Record::builder()
+                    .metadata(metadata)
+                    .args(format_args!(
+                        "{}{}",
+                        r.fmt,
+                        KvSingleLine::new(r.kvscan, " ", "")
+                    ))
+                    .key_values(&kvs)
+                    .build(),
+            );
+        }
+    }
+}
+
+impl Default for LogCrateBridge {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::LogCrateBridge;
+    use crate::macro_support::max_level_allows;
+    use crate::test::strip_features;
+    use crate::{audit, info};
+    use stakker::{LogFilter, LogLevel, Stakker};
+    use std::sync::Mutex;
+    use std::time::Instant;
+
+    static CAPTURED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    struct Capture;
+
+    impl log::Log for Capture {
+        fn enabled(&self, _: &log::Metadata<'_>) -> bool {
+            true
+        }
+        fn log(&self, r: &log::Record<'_>) {
+            let logid = r.key_values().get("logid".into()).map(|v| v.to_string());
+            CAPTURED.lock().unwrap().push(format!(
+                "{} {} {} logid={}",
+                r.level(),
+                r.target(),
+                r.args(),
+                logid.unwrap_or_default(),
+            ));
+        }
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn bridge() {
+        if !max_level_allows(LogLevel::Info) {
+            return;
+        }
+        log::set_logger(&Capture).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+
+        let mut s = Stakker::new(Instant::now());
+        let mut bridge = LogCrateBridge::new().other_level(log::Level::Warn);
+        s.set_logger(
+            LogFilter::all(&[LogLevel::Trace, LogLevel::Audit]),
+            strip_features(&[], move |core, r| bridge.log(core, r)),
+        );
+        let s = &mut s;
+        info!([s], port: 80, "Listening on {}", "eth0");
+        info!([s], target: "net", "Up");
+        audit!([s], Connected, addr: "1.2.3.4");
+        let default = if cfg!(feature = "target-module-path") {
+            module_path!()
+        } else {
+            "stakker"
+        };
+        assert_eq!(
+            *CAPTURED.lock().unwrap(),
+            vec![
+                format!("INFO {} Listening on eth0 port=80 logid=0", default),
+                "INFO net Up logid=0".to_string(),
+                format!("WARN {} Connected addr=1.2.3.4 logid=0", default),
+            ]
+        );
+    }
+}
@@ -0,0 +1,29 @@
+//! Bridges to forward **Stakker** log records to other logging
+//! ecosystems
+//!
+//! Each bridge is enabled by a cargo feature of the same name as the
+//! crate it bridges to.  There are also sinks for the Windows Event
+//! Log, enabled by the `windows` feature on Windows, and for the
+//! systemd journal, enabled by the `journald` feature on Linux.
+
+#[cfg(all(windows, feature = "windows"))]
+mod eventlog;
+#[cfg(all(target_os = "linux", feature = "journald"))]
+mod journald;
+#[cfg(feature = "log")]
+mod logcrate;
+#[cfg(feature = "slog")]
+mod slog;
+#[cfg(feature = "tracing")]
+mod tracing;
+
+#[cfg(feature = "slog")]
+pub use self::slog::SlogBridge;
+#[cfg(feature = "tracing")]
+pub use self::tracing::TracingBridge;
+#[cfg(all(windows, feature = "windows"))]
+pub use eventlog::EventLogSink;
+#[cfg(all(target_os = "linux", feature = "journald"))]
+pub use journald::journald_sink;
+#[cfg(feature = "log")]
+pub use logcrate::LogCrateBridge;
@@ -0,0 +1,190 @@
+use crate::tree::{self, Value};
+use crate::KvToJson;
+use crate::Visitable;
+use slog::{Key, Level, Logger, Record, RecordLocation, RecordStatic, Serializer, KV};
+use stakker::{Core, LogLevel, LogRecord, LogVisitor};
+
+static LOCATION: RecordLocation = RecordLocation {
+    file: "",
+    line: 0,
+    column: 0,
+    function: "",
+    module: "",
+};
+
+/// Bridge to forward records to a `slog::Logger`
+///
+/// This allows records to be output through an existing `slog` drain
+/// setup.  To install it:
+///
+/// ```ignore
+/// let mut bridge = SlogBridge::new(logger);
+/// stakker.set_logger(LogFilter::all(&[LogLevel::Info]), move |core, r| bridge.log(core, r));
+/// ```
+///
+/// Levels are mapped as follows:
+///
+/// **Stakker** level | `slog` level
+/// --- | ---
+/// `Trace` | `Trace`
+/// `Debug` | `Debug`
+/// `Info` | `Info`
+/// `Warn` | `Warning`
+/// `Error` | `Error`
+/// `Audit`, `Open`, `Close` | `Info`, or as set by [`SlogBridge::other_level`]
+///
+/// The freeform text becomes the message, and the record target
+/// becomes the `slog` tag.  The `LogID` is passed as the key `logid`,
+/// followed by the key-value pairs.  Since `slog` has no nested
+/// values, maps and arrays are passed as a string containing their
+/// [`KvToJson`] rendering.
+///
+/// [`KvToJson`]: ../struct.KvToJson.html
+/// [`SlogBridge::other_level`]: struct.SlogBridge.html#method.other_level
+pub struct SlogBridge {
+    logger: Logger,
+    other_level: Level,
+}
+
+impl SlogBridge {
+    /// Create a new bridge forwarding to the given logger
+    pub fn new(logger: Logger) -> Self {
+        Self {
+            logger,
+            other_level: Level::Info,
+        }
+    }
+
+    /// Set the `slog` level used for **Stakker** levels that have no
+    /// equivalent, i.e. `Audit`, `Open` and `Close`.  Defaults to
+    /// `Info`.
+    pub fn other_level(mut self, level: Level) -> Self {
+        self.other_level = level;
+        self
+    }
+
+    /// Forward a log record to the `slog::Logger`
+    pub fn log(&mut self, _core: &mut Core, r: &LogRecord<'_>) {
+        let level = match r.level {
+            LogLevel::Trace => Level::Trace,
+            LogLevel::Debug => Level::Debug,
+            LogLevel::Info => Level::Info,
+            LogLevel::Warn => Level::Warning,
+            LogLevel::Error => Level::Error,
+            LogLevel::Off => return,
+            _ => self.other_level,
+        };
+        let rstatic = RecordStatic {
+            location: &LOCATION,
+            tag: r.target,
+            level,
+        };
+        let kv = Pairs {
+            logid: r.id,
+            pairs: tree::collect_revealed(r.kvscan),
+        };
+        self.logger
+            .log(&Record::new(&rstatic, &r.fmt, slog::BorrowedKV(&kv)));
+    }
+}
+
+struct Pairs {
+    logid: u64,
+    pairs: Vec<(String, Value)>,
+}
+
+impl KV for Pairs {
+    fn serialize(&self, _: &Record<'_>, ser: &mut dyn Serializer) -> slog::Result {
+        ser.emit_u64(Key::from("logid"), self.logid)?;
+        for (key, val) in &self.pairs {
+            let key = Key::from(key.clone());
+            match val {
+                Value::U64(v) => ser.emit_u64(key, *v)?,
+                Value::I64(v) => ser.emit_i64(key, *v)?,
+                Value::F64(v) => ser.emit_f64(key, *v)?,
+                Value::Bool(v) => ser.emit_bool(key, *v)?,
+                Value::Null => ser.emit_none(key)?,
+                Value::Str(v) => ser.emit_str(key, v)?,
+                Value::Map(_) | Value::Arr(_) | Value::Secret(_) => {
+                    let kvscan = |lv: &mut dyn LogVisitor| val.visit(None, lv);
+                    ser.emit_arguments(key, &format_args!("{}", KvToJson::new(&kvscan, "", "")))?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SlogBridge;
+    use crate::macro_support::max_level_allows;
+    use crate::test::strip_features;
+    use crate::{audit, info};
+    use slog::{Drain, Key, Logger, Never, OwnedKVList, Record, Serializer, KV};
+    use stakker::{LogFilter, LogLevel, Stakker};
+    use std::fmt;
+    use std::sync::{Arc, Mutex};
+    use std::time::Instant;
+
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<String>>>);
+
+    struct Ser(String);
+
+    impl Serializer for Ser {
+        fn emit_arguments(&mut self, key: Key, val: &fmt::Arguments<'_>) -> slog::Result {
+            self.0 += &format!(" {}={}", key, val);
+            Ok(())
+        }
+    }
+
+    impl Drain for Capture {
+        type Ok = ();
+        type Err = Never;
+        fn log(&self, r: &Record<'_>, _: &OwnedKVList) -> Result<(), Never> {
+            let mut ser = Ser(format!(
+                "{} {} {}",
+                r.level().as_short_str(),
+                r.tag(),
+                r.msg()
+            ));
+            r.kv().serialize(r, &mut ser).unwrap();
+            self.0.lock().unwrap().push(ser.0);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn bridge() {
+        if !max_level_allows(LogLevel::Info) {
+            return;
+        }
+        let capture = Capture::default();
+        let logger = Logger::root(capture.clone(), slog::o!());
+        let mut s = Stakker::new(Instant::now());
+        let mut bridge = SlogBridge::new(logger).other_level(slog::Level::Warning);
+        s.set_logger(
+            LogFilter::all(&[LogLevel::Trace, LogLevel::Audit]),
+            strip_features(&[], move |core, r| bridge.log(core, r)),
+        );
+        let s = &mut s;
+        let list = [1, 2];
+        info!([s], port: 80, "Listening on {}", "eth0");
+        info!([s], target: "net", list, b: true, "Up");
+        audit!([s], Connected, addr: "1.2.3.4");
+        let default = if cfg!(feature = "target-module-path") {
+            module_path!()
+        } else {
+            ""
+        };
+        assert_eq!(
+            *capture.0.lock().unwrap(),
+            vec![
+                format!("INFO {} Listening on eth0 logid=0 port=80", default),
+                "INFO net Up logid=0 list=[1,2] b=true".to_string(),
+                format!("WARN {} Connected logid=0 addr=1.2.3.4", default),
+            ]
+        );
+    }
+}
@@ -0,0 +1,344 @@
+use crate::adapters::RevealSecrets;
+use stakker::{Core, LogLevel, LogRecord, LogVisitor};
+use std::collections::HashMap;
+use std::fmt::Arguments;
+use std::sync::OnceLock;
+use tracing_core::field::{FieldSet, Value};
+use tracing_core::{callsite, dispatcher, Event, Interest, Kind, Level, Metadata};
+
+/// Bridge to emit records as `tracing` events
+///
+/// Each record becomes an event dispatched to the current default
+/// `tracing` subscriber, at the level mapped as follows:
+///
+/// **Stakker** level | `tracing` level
+/// --- | ---
+/// `Trace` | `TRACE`
+/// `Debug` | `DEBUG`
+/// `Info` | `INFO`
+/// `Warn` | `WARN`
+/// `Error` | `ERROR`
+/// `Audit`, `Open`, `Close` | `INFO`, or as set by [`TracingBridge::other_level`]
+///
+/// The event target is the record target, or `"stakker"` if it is
+/// empty.  The event has a `message` field containing the freeform
+/// text, a `logid` field, and then one field for each key-value
+/// pair.  Nested maps and arrays are flattened using dotted keys,
+/// e.g. `addr.port` or `list.0`.  Null values are left unset.  A
+/// top-level key of `message` or `logid` would clash with the fixed
+/// fields, so it is output as `kv.message` or `kv.logid` instead.  To
+/// install it:
+///
+/// ```ignore
+/// let mut bridge = TracingBridge::new();
+/// stakker.set_logger(LogFilter::all(&[LogLevel::Info]), move |core, r| bridge.log(core, r));
+/// ```
+///
+/// `tracing` requires field names and metadata to be `'static`, but
+/// our key-value sets are dynamic.  So for each distinct combination
+/// of level, target and keys, a callsite is created and registered
+/// with `tracing`, and this is kept for the life of the process.
+/// This is fine for keys generated by the logging macros, but if you
+/// log maps with unbounded sets of keys, memory use will grow.
+///
+/// [`TracingBridge::other_level`]: struct.TracingBridge.html#method.other_level
+pub struct TracingBridge {
+    other_level: Level,
+    callsites: HashMap<(LogLevel, String, Vec<String>), &'static Metadata<'static>>,
+}
+
+impl TracingBridge {
+    /// Create a new bridge
+    pub fn new() -> Self {
+        Self {
+            other_level: Level::INFO,
+            callsites: HashMap::new(),
+        }
+    }
+
+    /// Set the `tracing` level used for **Stakker** levels that have
+    /// no equivalent, i.e. `Audit`, `Open` and `Close`.  Defaults to
+    /// `INFO`.
+    pub fn other_level(mut self, level: Level) -> Self {
+        self.other_level = level;
+        self
+    }
+
+    /// Emit a log record as a `tracing` event
+    pub fn log(&mut self, _core: &mut Core, r: &LogRecord<'_>) {
+        let level = match r.level {
+            LogLevel::Trace => Level::TRACE,
+            LogLevel::Debug => Level::DEBUG,
+            LogLevel::Info => Level::INFO,
+            LogLevel::Warn => Level::WARN,
+            LogLevel::Error => Level::ERROR,
+            LogLevel::Off => return,
+            _ => self.other_level,
+        };
+        let target = if r.target.is_empty() {
+            "stakker"
+        } else {
+            r.target
+        };
+
+        let mut buf = Buffer::default();
+        (r.kvscan)(&mut RevealSecrets::new(&mut buf));
+        let keys: Vec<String> = buf.fields.iter().map(|(k, _)| k.clone()).collect();
+
+        let cache_key = (r.level, target.to_string(), keys);
+        let meta = match self.callsites.get(&cache_key) {
+            Some(meta) => *meta,
+            None => {
+                let meta = new_callsite(level, target, &cache_key.2);
+                self.callsites.insert(cache_key, meta);
+                meta
+            }
+        };
+
+        let fmt = r.fmt;
+        let mut values: Vec<Option<&dyn Value>> = Vec::with_capacity(buf.fields.len() + 2);
+        values.push(Some(&fmt as &dyn Value));
+        values.push(Some(&r.id as &dyn Value));
+        for (_, val) in &buf.fields {
+            values.push(val.as_value());
+        }
+        dispatcher::get_default(|d| {
+            if d.enabled(meta) {
+                d.event(&Event::new(meta, &meta.fields().value_set_all(&values)));
+            }
+        });
+    }
+}
+
+impl Default for TracingBridge {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Create and register a callsite with static metadata for the given
+/// level, target and keys
+fn new_callsite(level: Level, target: &str, keys: &[String]) -> &'static Metadata<'static> {
+    let mut names: Vec<&'static str> = vec!["message", "logid"];
+    names.extend(keys.iter().map(|k| &*Box::leak(k.clone().into_boxed_str())));
+    let names: &'static [&'static str] = Box::leak(names.into_boxed_slice());
+    let target: &'static str = Box::leak(target.to_string().into_boxed_str());
+    let cs: &'static Callsite = Box::leak(Box::new(Callsite {
+        meta: OnceLock::new(),
+    }));
+    let meta = cs.meta.get_or_init(|| {
+        Metadata::new(
+            "stakker event",
+            target,
+            level,
+            None,
+            None,
+            None,
+            FieldSet::new(names, callsite::Identifier(cs)),
+            Kind::EVENT,
+        )
+    });
+    callsite::register(cs);
+    meta
+}
+
+struct Callsite {
+    meta: OnceLock<Metadata<'static>>,
+}
+
+impl callsite::Callsite for Callsite {
+    fn set_interest(&self, _: Interest) {}
+    fn metadata(&self) -> &Metadata<'_> {
+        self.meta.get().expect("callsite metadata not initialised")
+    }
+}
+
+enum Val {
+    U64(u64),
+    I64(i64),
+    F64(f64),
+    Bool(bool),
+    Str(String),
+    Null,
+}
+
+impl Val {
+    fn as_value(&self) -> Option<&dyn Value> {
+        match self {
+            Val::U64(v) => Some(v),
+            Val::I64(v) => Some(v),
+            Val::F64(v) => Some(v),
+            Val::Bool(v) => Some(v),
+            Val::Str(v) => Some(v),
+            Val::Null => None,
+        }
+    }
+}
+
+/// Visitor which buffers all values with flattened keys
+#[derive(Default)]
+struct Buffer {
+    fields: Vec<(String, Val)>,
+    // Key prefix and array index for each level of nesting
+    stack: Vec<(String, Option<usize>)>,
+}
+
+impl Buffer {
+    fn key(&mut self, key: Option<&str>) -> String {
+        let (prefix, index) = match self.stack.last_mut() {
+            None => {
+                let key = key.unwrap_or("value");
+                return if key == "message" || key == "logid" {
+                    format!("kv.{}", key) // Avoid clashing with the fixed fields
+                } else {
+                    key.to_string()
+                };
+            }
+            Some(top) => top,
+        };
+        let key = match index {
+            Some(i) => {
+                *i += 1;
+                (*i - 1).to_string()
+            }
+            None => key.unwrap_or("value").to_string(),
+        };
+        format!("{}.{}", prefix, key)
+    }
+
+    fn push(&mut self, key: Option<&str>, val: Val) {
+        let key = self.key(key);
+        match self.fields.iter_mut().find(|(k, _)| *k == key) {
+            Some(field) => field.1 = val,
+            None => self.fields.push((key, val)),
+        }
+    }
+}
+
+impl LogVisitor for Buffer {
+    fn kv_u64(&mut self, key: Option<&str>, val: u64) {
+        self.push(key, Val::U64(val));
+    }
+    fn kv_i64(&mut self, key: Option<&str>, val: i64) {
+        self.push(key, Val::I64(val));
+    }
+    fn kv_f64(&mut self, key: Option<&str>, val: f64) {
+        self.push(key, Val::F64(val));
+    }
+    fn kv_bool(&mut self, key: Option<&str>, val: bool) {
+        self.push(key, Val::Bool(val));
+    }
+    fn kv_null(&mut self, key: Option<&str>) {
+        self.push(key, Val::Null);
+    }
+    fn kv_str(&mut self, key: Option<&str>, val: &str) {
+        self.push(key, Val::Str(val.to_string()));
+    }
+    fn kv_fmt(&mut self, key: Option<&str>, val: &Arguments<'_>) {
+        self.push(key, Val::Str(val.to_string()));
+    }
+    fn kv_map(&mut self, key: Option<&str>) {
+        let prefix = self.key(key);
+        self.stack.push((prefix, None));
+    }
+    fn kv_mapend(&mut self, _: Option<&str>) {
+        self.stack.pop();
+    }
+    fn kv_arr(&mut self, key: Option<&str>) {
+        let prefix = self.key(key);
+        self.stack.push((prefix, Some(0)));
+    }
+    fn kv_arrend(&mut self, _: Option<&str>) {
+        self.stack.pop();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TracingBridge;
+    use crate::macro_support::max_level_allows;
+    use crate::test::strip_features;
+    use crate::{audit, info, Pairs};
+    use stakker::{LogFilter, LogLevel, Stakker};
+    use std::fmt;
+    use std::sync::{Arc, Mutex};
+    use std::time::Instant;
+    use tracing_core::field::{Field, Visit};
+    use tracing_core::span::{Attributes, Id, Record};
+    use tracing_core::{dispatcher, Dispatch, Event, Level, Metadata, Subscriber};
+
+    #[derive(Clone, Default)]
+    struct Collect(Arc<Mutex<Vec<String>>>);
+
+    struct Fields(String);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0 += &format!(" {}={:?}", field.name(), value);
+        }
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0 += &format!(" {}={}", field.name(), value);
+        }
+    }
+
+    impl Subscriber for Collect {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, _: &Attributes<'_>) -> Id {
+            Id::from_u64(1)
+        }
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, event: &Event<'_>) {
+            let meta = event.metadata();
+            let mut fields = Fields(format!("{} {}", meta.level(), meta.target()));
+            event.record(&mut fields);
+            self.0.lock().unwrap().push(fields.0);
+        }
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn bridge() {
+        if !max_level_allows(LogLevel::Info) {
+            return;
+        }
+        let collect = Collect::default();
+        let mut s = Stakker::new(Instant::now());
+        let mut bridge = TracingBridge::new().other_level(Level::WARN);
+        s.set_logger(
+            LogFilter::all(&[LogLevel::Trace, LogLevel::Audit]),
+            strip_features(&[], move |core, r| bridge.log(core, r)),
+        );
+        dispatcher::with_default(&Dispatch::new(collect.clone()), || {
+            let s = &mut s;
+            let list = [1, 2];
+            info!([s], port: 80, none: None::<u8>, "Listening on {}", "eth0");
+            info!([s], target: "net", list, "Up");
+            info!([s], target: "net", list, "Up");
+            audit!([s], Connected, addr: "1.2.3.4");
+            let m = Pairs(&[("message", 1)]);
+            info!([s], message: "hi", logid: 5, m, "Clash");
+        });
+        let default = if cfg!(feature = "target-module-path") {
+            module_path!()
+        } else {
+            "stakker"
+        };
+        assert_eq!(
+            *collect.0.lock().unwrap(),
+            vec![
+                format!("INFO {} message=Listening on eth0 logid=0 port=80", default),
+                "INFO net message=Up logid=0 list.0=1 list.1=2".to_string(),
+                "INFO net message=Up logid=0 list.0=1 list.1=2".to_string(),
+                format!("WARN {} message=Connected logid=0 addr=1.2.3.4", default),
+                format!(
+                    "INFO {} message=Clash logid=0 kv.message=hi kv.logid=5 m.message=1",
+                    default
+                ),
+            ]
+        );
+    }
+}
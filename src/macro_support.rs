@@ -0,0 +1,299 @@
+//! Runtime support for the logging macros.  This is not part of the
+//! public API.
+
+use crate::{FlattenVisitable, LogContext, Visitable};
+use stakker::{Core, LogID, LogLevel, LogVisitor};
+use std::any::Any;
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::fmt::Arguments;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::thread::LocalKey;
+use std::time::{Duration, Instant};
+
+/// Per-site cache of the last value, as used by `log_on_change!`
+pub type LastValue = RefCell<Option<Box<dyn Any>>>;
+
+/// Compare `value` against the last value stored in `last`, storing
+/// it if it differs.  Returns `true` if the value changed, or if this
+/// is the first call.
+pub fn changed<T: PartialEq + Clone + 'static>(
+    last: &'static LocalKey<LastValue>,
+    value: &T,
+) -> bool {
+    last.with(|last| {
+        let mut last = last.borrow_mut();
+        if let Some(prev) = last.as_ref().and_then(|v| v.downcast_ref::<T>()) {
+            if prev == value {
+                return false;
+            }
+        }
+        *last = Some(Box::new(value.clone()));
+        true
+    })
+}
+
+/// Base for the millisecond times stored by `log_every!`, set on
+/// first use
+static EVERY_BASE: OnceLock<Instant> = OnceLock::new();
+
+/// Check whether at least `interval` has passed since the last time
+/// this returned `true` for `last`, and if so record `now` as the
+/// last time.  `last` holds milliseconds since `EVERY_BASE` plus one,
+/// or zero if nothing has been logged yet.  This is lock-free, and if
+/// two threads race, only one of them wins.
+pub fn every(last: &AtomicU64, now: Instant, interval: Duration) -> bool {
+    let base = *EVERY_BASE.get_or_init(|| now);
+    let now_ms =
+        u64::try_from(now.saturating_duration_since(base).as_millis()).unwrap_or(u64::MAX - 1) + 1;
+    let prev = last.load(Ordering::Relaxed);
+    let interval_ms = u64::try_from(interval.as_millis()).unwrap_or(u64::MAX);
+    if prev != 0 && now_ms.saturating_sub(prev) < interval_ms {
+        return false;
+    }
+    last.compare_exchange(prev, now_ms, Ordering::Relaxed, Ordering::Relaxed)
+        .is_ok()
+}
+
+/// Returns `true` only on the first call for `done`
+#[inline]
+pub fn once(done: &AtomicBool) -> bool {
+    !done.load(Ordering::Relaxed) && !done.swap(true, Ordering::Relaxed)
+}
+
+/// Check whether a level is allowed by the `max_level_*` cargo
+/// features.  This is evaluated as a constant in the macros, so that
+/// code for disabled levels is eliminated.  Levels other than the
+/// five severity levels are always allowed.
+pub const fn max_level_allows(level: LogLevel) -> bool {
+    let max = if cfg!(feature = "max_level_off") {
+        0
+    } else if cfg!(feature = "max_level_error") {
+        1
+    } else if cfg!(feature = "max_level_warn") {
+        2
+    } else if cfg!(feature = "max_level_info") {
+        3
+    } else if cfg!(feature = "max_level_debug") {
+        4
+    } else {
+        5
+    };
+    let rank = match level {
+        LogLevel::Error => 1,
+        LogLevel::Warn => 2,
+        LogLevel::Info => 3,
+        LogLevel::Debug => 4,
+        LogLevel::Trace => 5,
+        _ => 0,
+    };
+    rank <= max
+}
+
+/// Get the target to use when none is given to a logging macro.
+/// This is the target supplied by the `LogID` source if that is
+/// non-empty, which only happens with the `target-actor-type`
+/// feature.  Otherwise it is the module path of the call site with
+/// the `target-module-path` feature, otherwise the empty string.
+#[inline]
+pub const fn default_target(module_path: &'static str, source: &'static str) -> &'static str {
+    if !source.is_empty() {
+        source
+    } else if cfg!(feature = "target-module-path") {
+        module_path
+    } else {
+        ""
+    }
+}
+
+/// Start timing the visiting of key-value pairs, if the
+/// `render-timing` feature is enabled
+#[inline]
+pub fn render_start() -> Option<Instant> {
+    if cfg!(feature = "render-timing") {
+        Some(Instant::now())
+    } else {
+        None
+    }
+}
+
+/// Output the `_render_ns` field, if timing was started
+#[inline]
+pub fn render_end(start: Option<Instant>, output: &mut dyn LogVisitor) {
+    if let Some(start) = start {
+        let ns = start.elapsed().as_nanos();
+        output.kv_u64(Some("_render_ns"), u64::try_from(ns).unwrap_or(u64::MAX));
+    }
+}
+
+/// Hash a format string literal with 64-bit FNV-1a.  This is
+/// evaluated as a constant in the macros.
+pub const fn template_hash(fmt: &str) -> u64 {
+    let bytes = fmt.as_bytes();
+    let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+        i += 1;
+    }
+    hash
+}
+
+/// Output the `template_hash` field, if the `template-hash` feature
+/// is enabled.  This is output as 16 hex digits rather than as a
+/// number, since many JSON consumers can't represent all 64-bit
+/// integers exactly.
+#[inline]
+pub fn template(hash: u64, output: &mut dyn LogVisitor) {
+    if cfg!(feature = "template-hash") {
+        output.kv_fmt(Some("template_hash"), &format_args!("{:016x}", hash));
+    }
+}
+
+/// Test at compile time whether `key` is one of the keys given in a
+/// macro call
+pub const fn has_key(keys: &[&str], key: &str) -> bool {
+    let key = key.as_bytes();
+    let mut i = 0;
+    'outer: while i < keys.len() {
+        let k = keys[i].as_bytes();
+        i += 1;
+        if k.len() != key.len() {
+            continue;
+        }
+        let mut j = 0;
+        while j < k.len() {
+            if k[j] != key[j] {
+                continue 'outer;
+            }
+            j += 1;
+        }
+        return true;
+    }
+    false
+}
+
+/// Output the fields of the `LogContext`, then the source location
+/// of the call site as `loc` if the `location` feature is enabled,
+/// then the key-value pairs of the call by calling `kvs`.  `has_loc`
+/// is set if the call gives its own `loc`, which then takes the place
+/// of this one.  Any other top-level `loc` key, from the `LogContext`
+/// or from a `flat!` value, is output as `kv.loc` to avoid a clash.
+#[inline]
+pub fn fields(
+    file: &'static str,
+    line: u32,
+    has_loc: bool,
+    output: &mut dyn LogVisitor,
+    kvs: impl FnOnce(&mut dyn LogVisitor),
+) {
+    if cfg!(feature = "location") {
+        LogContext::visit(&mut RenameLoc::new(output));
+        if has_loc {
+            kvs(output);
+        } else {
+            output.kv_fmt(Some("loc"), &format_args!("{}:{}", file, line));
+            kvs(&mut RenameLoc::new(output));
+        }
+    } else {
+        LogContext::visit(output);
+        kvs(output);
+    }
+}
+
+// Visitor which outputs a top-level `loc` key as `kv.loc`, passing
+// everything else through unchanged
+struct RenameLoc<'a> {
+    output: &'a mut dyn LogVisitor,
+    depth: usize, // Nesting depth of maps and arrays
+}
+
+impl<'a> RenameLoc<'a> {
+    fn new(output: &'a mut dyn LogVisitor) -> Self {
+        Self { output, depth: 0 }
+    }
+    fn key<'k>(&self, key: Option<&'k str>) -> Option<&'k str> {
+        match key {
+            Some("loc") if self.depth == 0 => Some("kv.loc"),
+            _ => key,
+        }
+    }
+}
+
+impl<'a> LogVisitor for RenameLoc<'a> {
+    fn kv_u64(&mut self, key: Option<&str>, val: u64) {
+        self.output.kv_u64(self.key(key), val);
+    }
+    fn kv_i64(&mut self, key: Option<&str>, val: i64) {
+        self.output.kv_i64(self.key(key), val);
+    }
+    fn kv_f64(&mut self, key: Option<&str>, val: f64) {
+        self.output.kv_f64(self.key(key), val);
+    }
+    fn kv_bool(&mut self, key: Option<&str>, val: bool) {
+        self.output.kv_bool(self.key(key), val);
+    }
+    fn kv_null(&mut self, key: Option<&str>) {
+        self.output.kv_null(self.key(key));
+    }
+    fn kv_str(&mut self, key: Option<&str>, val: &str) {
+        self.output.kv_str(self.key(key), val);
+    }
+    fn kv_fmt(&mut self, key: Option<&str>, val: &Arguments<'_>) {
+        self.output.kv_fmt(self.key(key), val);
+    }
+    fn kv_map(&mut self, key: Option<&str>) {
+        self.output.kv_map(self.key(key));
+        self.depth += 1;
+    }
+    fn kv_mapend(&mut self, key: Option<&str>) {
+        self.depth = self.depth.saturating_sub(1);
+        self.output.kv_mapend(self.key(key));
+    }
+    fn kv_arr(&mut self, key: Option<&str>) {
+        self.output.kv_arr(self.key(key));
+        self.depth += 1;
+    }
+    fn kv_arrend(&mut self, key: Option<&str>) {
+        self.depth = self.depth.saturating_sub(1);
+        self.output.kv_arrend(self.key(key));
+    }
+}
+
+/// Pass a record to the logger.  With the `recent-messages` feature,
+/// error records get a `recent` array of the preceding messages, and
+/// all other records are added to that ring of messages.
+#[inline]
+pub fn log(
+    core: &mut Core,
+    id: LogID,
+    level: LogLevel,
+    target: &str,
+    fmt: Arguments<'_>,
+    kvscan: impl Fn(&mut dyn LogVisitor),
+) {
+    if !cfg!(feature = "recent-messages") {
+        core.log(id, level, target, fmt, kvscan);
+    } else if level == LogLevel::Error {
+        core.log(id, level, target, fmt, |output: &mut dyn LogVisitor| {
+            kvscan(output);
+            crate::recent::visit(output);
+        });
+    } else {
+        core.log(id, level, target, fmt, kvscan);
+        crate::recent::push(level, fmt);
+    }
+}
+
+/// Value for the `flat!` shortcut, which outputs the entries of the
+/// wrapped value in place, ignoring the key
+pub struct Flat<'a, T: ?Sized>(pub &'a T);
+
+impl<T: FlattenVisitable + ?Sized> Visitable for Flat<'_, T> {
+    #[inline]
+    fn visit(&self, _key: Option<&str>, output: &mut dyn LogVisitor) {
+        self.0.visit_flat(output);
+    }
+}
@@ -1,7 +1,3 @@
-// TODO: Add #[cfg] options to disable levels completely, eliminating
-// all the code associated with logging those levels completely from
-// the executable
-
 // TODO: Switch to proc macros to allow us to automatically access
 // `cx` without mentioning it explicitly.
 
@@ -71,6 +67,316 @@ macro_rules! audit {
     }};
 }
 
+/// Log an audit record with an optional freeform note
+///
+/// This works like [`audit!`], but after the key-value pairs an
+/// optional format string and arguments may be given, which are
+/// formatted into a `note` key.  The tag is still the text of the
+/// record, so records are processed the same as for [`audit!`], but
+/// a human-readable explanation can be attached for incident review.
+/// For example:
+///
+/// ```ignore
+/// audit_msg!([cx], AccountLocked, user: %name, "Locked after {} failures", count);
+/// ```
+///
+/// [`audit!`]: macro.audit.html
+#[macro_export]
+macro_rules! audit_msg {
+    ( [$($cx:tt)+], $tag:ident $(, $($tail:tt)+)? ) => {{
+        $crate::audit_msg!(@start [$($cx)+] (::std::stringify!($tag)) [] $($($tail)+)?);
+    }};
+    ( [$($cx:tt)+], $tag:literal $(, $($tail:tt)+)? ) => {{
+        $crate::audit_msg!(@start [$($cx)+] ($tag) [] $($($tail)+)?);
+    }};
+    ( [$($cx:tt)+], ($tag:expr) $(, $($tail:tt)+)? ) => {{
+        $crate::audit_msg!(@start [$($cx)+] ($tag) [] $($($tail)+)?);
+    }};
+    // At the start of an item: check for the format string, which
+    // can't be followed by `:` like a literal key
+    (@start [$($cx:tt)+] ($tag:expr) [$($acc:tt)*]) => {
+        $crate::log!(Audit [$($cx)+] $($acc)*, "{}", $tag)
+    };
+    (@start [$($cx:tt)+] ($tag:expr) [$($acc:tt)*] $fmt:literal $(, $($args:tt)*)?) => {
+        $crate::log!(Audit [$($cx)+] $($acc)*, note: ::std::format_args!($fmt $(, $($args)*)?), "{}", $tag)
+    };
+    (@start [$($cx:tt)+] ($tag:expr) [$($acc:tt)*] $($rest:tt)+) => {
+        $crate::audit_msg!(@item [$($cx)+] ($tag) [$($acc)* ,] $($rest)+)
+    };
+    // Copy over a key-value item up to the next comma
+    (@item [$($cx:tt)+] ($tag:expr) [$($acc:tt)*] , $($rest:tt)*) => {
+        $crate::audit_msg!(@start [$($cx)+] ($tag) [$($acc)*] $($rest)*)
+    };
+    (@item [$($cx:tt)+] ($tag:expr) [$($acc:tt)*] $next:tt $($rest:tt)*) => {
+        $crate::audit_msg!(@item [$($cx)+] ($tag) [$($acc)* $next] $($rest)*)
+    };
+    (@item [$($cx:tt)+] ($tag:expr) [$($acc:tt)*]) => {
+        $crate::audit_msg!(@start [$($cx)+] ($tag) [$($acc)*])
+    };
+}
+
+/// Log a snapshot of all registered state
+///
+/// This outputs a single INFO-level record with a `snapshot` key
+/// containing a map of the state of every provider registered on
+/// this thread with [`register_snapshot`].  For example:
+///
+/// ```ignore
+/// dump_all!([cx]);
+/// ```
+///
+/// [`register_snapshot`]: fn.register_snapshot.html
+#[macro_export]
+macro_rules! dump_all {
+    ( [$($cx:tt)+] ) => {{
+        $crate::log!(Info [$($cx)+], snapshot: $crate::Snapshots, "State snapshot");
+    }};
+}
+
+/// Log the build metadata of the calling crate
+///
+/// This outputs a single INFO-level record giving a fingerprint of
+/// the build, intended to be logged once just after the logger is
+/// set up, so that every log stream records what produced it.  The
+/// keys are:
+///
+/// - `crate_version`: the version of the crate containing the call,
+///   from `CARGO_PKG_VERSION`
+/// - `rustc_version`: the compiler version, if the `RUSTC_VERSION`
+///   environment variable was set at compile time, e.g. by a build
+///   script outputting `cargo:rustc-env=RUSTC_VERSION=...`
+/// - `git_hash`: the commit hash, if the `GIT_HASH` environment
+///   variable was set at compile time
+/// - `profile`: `debug` or `release`, according to whether debug
+///   assertions are enabled
+///
+/// Values which were not captured are output as null.  A different
+/// environment variable may be given for the git hash, to suit
+/// whatever tool provides it.  For example:
+///
+/// ```ignore
+/// log_build_info!([cx]);
+/// log_build_info!([cx], git_hash_var: "VERGEN_GIT_SHA");
+/// ```
+#[macro_export]
+macro_rules! log_build_info {
+    ( [$($cx:tt)+] ) => {{
+        $crate::log_build_info!([$($cx)+], git_hash_var: "GIT_HASH");
+    }};
+    ( [$($cx:tt)+], git_hash_var: $var:literal ) => {{
+        $crate::log!(Info [$($cx)+],
+                     crate_version: ::std::env!("CARGO_PKG_VERSION"),
+                     rustc_version: ::std::option_env!("RUSTC_VERSION"),
+                     git_hash: ::std::option_env!($var),
+                     profile: if ::std::cfg!(debug_assertions) { "debug" } else { "release" },
+                     "Build info");
+    }};
+}
+
+/// Log a value only when it changes
+///
+/// This logs a record with the value under the given key only when
+/// the value differs from the value passed on the previous call at
+/// the same call site, which suppresses repetitive identical
+/// readings.  The first call at a site always logs.  For example:
+///
+/// ```ignore
+/// log_on_change!([cx], Info, "temp", reading);
+/// ```
+///
+/// The value must implement `PartialEq + Clone` and be `'static`.  A
+/// copy of the last value is kept in a thread-local specific to the
+/// call site, so each site tracks its own value, and the state is
+/// shared between all actors on the thread that run that code.
+#[macro_export]
+macro_rules! log_on_change {
+    ( [$($cx:tt)+], $level:ident, $key:literal, $value:expr ) => {{
+        ::std::thread_local! {
+            static LAST: $crate::macro_support::LastValue = ::std::cell::RefCell::new(None);
+        }
+        let v = $value;
+        if $crate::macro_support::changed(&LAST, &v) {
+            $crate::log!($level [$($cx)+], $key: v, "Value changed");
+        }
+    }};
+}
+
+/// Log a record at most once per interval at this call site
+///
+/// This logs at the given level only if at least the given interval
+/// has passed since this call site last logged, which stops a busy
+/// loop from flooding the log with the same message.  The time used
+/// is `Core::now()`, i.e. the time of the current run of the
+/// **Stakker** main loop.  The last time is kept in a static atomic,
+/// so the interval applies across all threads and actors that run
+/// this code, and the check is cheap and lock-free.  For example:
+///
+/// ```ignore
+/// log_every!([cx], Warn, every: Duration::from_secs(5), queue_len, "Queue full");
+/// ```
+///
+/// See also [`warn_every!`] and [`error_every!`].
+///
+/// [`error_every!`]: macro.error_every.html
+/// [`warn_every!`]: macro.warn_every.html
+#[macro_export]
+macro_rules! log_every {
+    ( [$cx:expr], $level:ident, every: $every:expr, $($tail:tt)+ ) => {{
+        static LAST: ::std::sync::atomic::AtomicU64 = ::std::sync::atomic::AtomicU64::new(0);
+        #[allow(unused_imports)]
+        use $crate::CoreSource as _;
+        let now = $cx.access_core().now();
+        if $crate::macro_support::every(&LAST, now, $every) {
+            $crate::log!($level [$cx], $($tail)+);
+        }
+    }};
+    ( [$src:expr, $core:expr], $level:ident, every: $every:expr, $($tail:tt)+ ) => {{
+        static LAST: ::std::sync::atomic::AtomicU64 = ::std::sync::atomic::AtomicU64::new(0);
+        #[allow(unused_imports)]
+        use $crate::CoreSource as _;
+        let now = $core.access_core().now();
+        if $crate::macro_support::every(&LAST, now, $every) {
+            $crate::log!($level [$src, $core], $($tail)+);
+        }
+    }};
+}
+
+/// Log a warning at most once per interval at this call site
+///
+/// See [`log_every!`] for details.
+///
+/// [`log_every!`]: macro.log_every.html
+#[macro_export]
+macro_rules! warn_every {
+    ( [$($cx:tt)+], $($tail:tt)+ ) => {{
+        $crate::log_every!([$($cx)+], Warn, $($tail)+);
+    }};
+}
+
+/// Log an error at most once per interval at this call site
+///
+/// See [`log_every!`] for details.
+///
+/// [`log_every!`]: macro.log_every.html
+#[macro_export]
+macro_rules! error_every {
+    ( [$($cx:tt)+], $($tail:tt)+ ) => {{
+        $crate::log_every!([$($cx)+], Error, $($tail)+);
+    }};
+}
+
+/// Log a value and return it, for inline debugging
+///
+/// Like `std::dbg!`, this can be wrapped around any subexpression
+/// without restructuring the code.  It logs a record at the given
+/// level, with the source text of the expression as the key and its
+/// value as the value, and the source file and line as the text, and
+/// then evaluates to the value.  For example:
+///
+/// ```ignore
+/// let total = log_val!([cx], Debug, base + extra) * 2;
+/// ```
+///
+/// The expression is evaluated exactly once, whether or not the
+/// record passes the logger's filter or the level is compiled out
+/// with a `max_level_*` feature.  The value is moved in and moved out
+/// again, so an owned value is returned by move, and a reference can
+/// be passed if the value must not be moved.  The value must
+/// implement [`Visitable`].  See also [`debug_val!`] and
+/// [`trace_val!`].
+///
+/// [`Visitable`]: trait.Visitable.html
+/// [`debug_val!`]: macro.debug_val.html
+/// [`trace_val!`]: macro.trace_val.html
+#[macro_export]
+macro_rules! log_val {
+    ( [$($cx:tt)+], $level:ident, $value:expr $(,)? ) => {
+        match $value {
+            v => {
+                $crate::log!($level [$($cx)+], @kv ::std::stringify!($value) => &v,
+                             "{}:{}", ::std::file!(), ::std::line!());
+                v
+            }
+        }
+    };
+}
+
+/// Log a value at debug level and return it
+///
+/// See [`log_val!`] for details.
+///
+/// [`log_val!`]: macro.log_val.html
+#[macro_export]
+macro_rules! debug_val {
+    ( [$($cx:tt)+], $($tail:tt)+ ) => {
+        $crate::log_val!([$($cx)+], Debug, $($tail)+)
+    };
+}
+
+/// Log a value at trace level and return it
+///
+/// See [`log_val!`] for details.
+///
+/// [`log_val!`]: macro.log_val.html
+#[macro_export]
+macro_rules! trace_val {
+    ( [$($cx:tt)+], $($tail:tt)+ ) => {
+        $crate::log_val!([$($cx)+], Trace, $($tail)+)
+    };
+}
+
+/// Log a record only the first time this call site is reached
+///
+/// After the first record, later calls at the same call site log
+/// nothing for the rest of the life of the process, across all
+/// threads.  This suits warnings about deprecated configuration and
+/// the like.  For example:
+///
+/// ```ignore
+/// log_once!([cx], Warn, "Option `foo` is deprecated");
+/// ```
+#[macro_export]
+macro_rules! log_once {
+    ( [$($cx:tt)+], $level:ident, $($tail:tt)+ ) => {{
+        static DONE: ::std::sync::atomic::AtomicBool = ::std::sync::atomic::AtomicBool::new(false);
+        if $crate::macro_support::once(&DONE) {
+            $crate::log!($level [$($cx)+], $($tail)+);
+        }
+    }};
+}
+
+/// Capture the records logged by some code and check them
+///
+/// This runs the first closure against a temporary `Stakker`, and
+/// then runs the second closure with the captured records as a
+/// `Vec<RecordView>`.  See [`capture_logs`] for the conditions.  For
+/// example:
+///
+/// ```ignore
+/// assert_logs!(
+///     |s| error!([s], code: 5, "Failed"),
+///     |records| {
+///         assert_eq!(records.len(), 1);
+///         assert_eq!(records[0].level, LogLevel::Error);
+///         assert_eq!(records[0].msg, "Failed");
+///     }
+/// );
+/// ```
+///
+/// This is only available with the `test-util` feature.
+///
+/// [`capture_logs`]: fn.capture_logs.html
+#[cfg(feature = "test-util")]
+#[macro_export]
+macro_rules! assert_logs {
+    ( |$s:ident| $body:expr, |$records:ident| $check:expr $(,)? ) => {{
+        let $records = $crate::capture_logs(|$s: &mut $crate::stakker::Stakker| {
+            $body;
+        });
+        $check
+    }};
+}
+
 #[macro_export]
 #[doc(hidden)]
 macro_rules! log_key_string {
@@ -82,6 +388,28 @@ macro_rules! log_key_string {
     };
 }
 
+/// Internal macro which gets the default target supplied by the
+/// `LogID` source, which is only consulted with the
+/// `target-actor-type` feature, so that sources which only have an
+/// inherent `access_log_id` method still work without it
+#[cfg(feature = "target-actor-type")]
+#[macro_export]
+#[doc(hidden)]
+macro_rules! log_source_target {
+    ($src:expr) => {
+        $src.log_target()
+    };
+}
+
+#[cfg(not(feature = "target-actor-type"))]
+#[macro_export]
+#[doc(hidden)]
+macro_rules! log_source_target {
+    ($src:expr) => {
+        ""
+    };
+}
+
 /// Internal macro which handles translation to a log call
 //
 // Called with: `(level [cx] kv-args fmt fmt-args)`
@@ -95,14 +423,71 @@ macro_rules! log {
         $crate::log!($level [$cx, $cx] $(, $($tail)+)?)
     }};
     ($level:ident [$src:expr, $core:expr], target: $target:literal $(, $($tail:tt)+)?) => {{
-        $crate::log!([$src.access_log_id(), $core, $level, $target] $($($tail)+)?)
+        #[allow(unused_imports)]
+        use $crate::{CoreSource as _, LogIdSource as _};
+        $crate::log!(@max $level [$src.access_log_id(), $core, $level, $target] $($($tail)+)?)
     }};
     ($level:ident [$src:expr, $core:expr] $(, $($tail:tt)+)?) => {{
-        $crate::log!([$src.access_log_id(), $core, $level, ""] $($($tail)+)?)
+        #[allow(unused_imports)]
+        use $crate::{CoreSource as _, LogIdSource as _};
+        $crate::log!(@max $level [$src.access_log_id(), $core, $level,
+                                  ($crate::macro_support::default_target(
+                                      ::std::module_path!(),
+                                      $crate::log_source_target!($src)))]
+                     $($($tail)+)?)
+    }};
+    // Levels compiled out by the `max_level_*` features are still
+    // type-checked, but the code is never run, so neither the
+    // arguments nor the key-value expressions are evaluated
+    (@max $level:ident $($tail:tt)+) => {{
+        const ENABLED: bool = $crate::macro_support::max_level_allows($crate::stakker::LogLevel::$level);
+        if ENABLED {
+            $crate::log!($($tail)+)
+        }
     }};
     ($level:ident $($tail:tt)*) => {{
         ::std::compile_error!("Stakker logging macros need `[cx]` or `[core]` or `[actor, core]` as first argument");
     }};
+    // Display-formatted value plus a projection of it (with both!)
+    ([$($a:tt)*] both!($key:ident, $value:expr, $proj:ident) $(, $($tail:tt)*)?) => {{
+        let v = &$value; // Do borrow outside of closure
+        $crate::log!([$($a)* (::std::stringify!($key), format_args!("{}", v))
+                      (::std::concat!(::std::stringify!($key), "_", ::std::stringify!($proj)), v.$proj())]
+                     $($($tail)*)?)
+    }};
+    // Sensitive values, masked by a `RedactingVisitor` (with secret!)
+    ([$($a:tt)*] secret!($key1:ident $(. $key2:ident)*) $(, $($tail:tt)*)?) => {{
+        let v = &($key1$(.$key2)*); // Do borrow outside of closure
+        $crate::log!([$($a)* ($crate::log_key_string!($key1$(.$key2)*), $crate::Secret(v))] $($($tail)*)?)
+    }};
+    ([$($a:tt)*] $key:ident : secret!($value:expr) $(, $($tail:tt)*)?) => {{
+        let v = &$value; // Do borrow outside of closure
+        $crate::log!([$($a)* (::std::stringify!($key), $crate::Secret(v))] $($($tail)*)?)
+    }};
+    ([$($a:tt)*] $key:literal : secret!($value:expr) $(, $($tail:tt)*)?) => {{
+        let v = &$value; // Do borrow outside of closure
+        $crate::log!([$($a)* ($key, $crate::Secret(v))] $($($tail)*)?)
+    }};
+    // Key given as an expression, for internal use by other macros
+    ([$($a:tt)*] @kv $key:expr => $value:expr $(, $($tail:tt)*)?) => {
+        $crate::log!([$($a)* ($key, $value)] $($($tail)*)?)
+    };
+    // Entries of a map-like value spliced in (with flat!)
+    ([$($a:tt)*] flat!($value:expr) $(, $($tail:tt)*)?) => {{
+        let v = &$value; // Do borrow outside of closure
+        $crate::log!([$($a)* ("", $crate::macro_support::Flat(v))] $($($tail)*)?)
+    }};
+    // Byte data as a hex string (with hex!)
+    ([$($a:tt)*] $key:ident : hex!($value:expr) $(, $($tail:tt)*)?) => {{
+        let v = &$value; // Do borrow outside of closure
+        $crate::log!([$($a)* (::std::stringify!($key), $crate::Hex(::std::convert::AsRef::<[u8]>::as_ref(v)))]
+                     $($($tail)*)?)
+    }};
+    ([$($a:tt)*] $key:literal : hex!($value:expr) $(, $($tail:tt)*)?) => {{
+        let v = &$value; // Do borrow outside of closure
+        $crate::log!([$($a)* ($key, $crate::Hex(::std::convert::AsRef::<[u8]>::as_ref(v)))]
+                     $($($tail)*)?)
+    }};
     // Primitive values (no % or ?)
     ([$($a:tt)*] $key1:ident $(. $key2:ident)*  $(, $($tail:tt)*)?) => {
         $crate::log!([$($a)* ($crate::log_key_string!($key1$(.$key2)*), $key1$(.$key2)*)] $($($tail)*)?)
@@ -135,22 +520,39 @@ macro_rules! log {
         let v = &$value; // Do borrow outside of closure
         $crate::log!([$($a)* (::std::stringify!($key), format_args!("{:?}", v))] $($($tail)*)?)
     }};
-    ([$($a:tt)*] $key:literal : ? $value:expr $(, $($tail:tt)*)?) => {
+    ([$($a:tt)*] $key:literal : ? $value:expr $(, $($tail:tt)*)?) => {{
         let v = &$value; // Do borrow outside of closure
         $crate::log!([$($a)* ($key, format_args!("{:?}", v))] $($($tail)*)?)
-    };
+    }};
     // Final output
-    ([$logid:expr, $core:expr, $level:ident, $target:literal $( ($key:expr, $val:expr) )*] $fmt:literal $(, $($tail:tt)*)?) => {{
+    ([$logid:expr, $core:expr, $level:ident, $target:tt $( ($key:expr, $val:expr) )*] $fmt:literal $(, $($tail:tt)*)?) => {{
+        #[allow(unused_imports)]
         use $crate::Visitable;
         let id = $logid;
+        let target: &str = $target;
         let core = $core.access_core();
-        core.log(
-            id,
-            $crate::stakker::LogLevel::$level,
-            $target,
-            ::std::format_args!( $fmt $(, $($tail)*)? ),
-            |output| {
-                $( $val.visit(Some($key), output); )*
-            });
+        // Check the filter first so that the message arguments are
+        // only evaluated and formatted if the record will be logged
+        if core.log_check($crate::stakker::LogLevel::$level) {
+            const TEMPLATE_HASH: u64 = $crate::macro_support::template_hash($fmt);
+            const HAS_LOC: bool = $crate::macro_support::has_key(&[$($key),*], "loc");
+            let kvscan = |output: &mut dyn $crate::stakker::LogVisitor| {
+                let start = $crate::macro_support::render_start();
+                #[allow(unused_variables)]
+                let kvs = |output: &mut dyn $crate::stakker::LogVisitor| {
+                    $( $val.visit(Some($key), output); )*
+                };
+                $crate::macro_support::fields(::std::file!(), ::std::line!(), HAS_LOC, output, kvs);
+                $crate::macro_support::template(TEMPLATE_HASH, output);
+                $crate::macro_support::render_end(start, output);
+            };
+            $crate::macro_support::log(
+                core,
+                id,
+                $crate::stakker::LogLevel::$level,
+                target,
+                ::std::format_args!( $fmt $(, $($tail)*)? ),
+                kvscan);
+        }
     }};
 }
@@ -0,0 +1,211 @@
+//! Structured output of protobuf messages, enabled by the `prost`
+//! feature
+
+use crate::{Base64, Visitable};
+use prost_reflect::{DynamicMessage, Kind, MapKey, ReflectMessage, Value};
+use stakker::LogVisitor;
+
+/// Protobuf message output as a map of its fields
+///
+/// `prost` messages have no runtime reflection, so this uses the
+/// `prost-reflect` crate, which adds message descriptors.  The
+/// message type must implement `prost_reflect::ReflectMessage`.  For
+/// generated types this is done by building with `prost-reflect-build`
+/// to derive it, and `prost_reflect::DynamicMessage` implements it
+/// too.  The message is transcoded to a `DynamicMessage` to read its
+/// fields, which costs an encode and decode of the message, so this
+/// is not free for large messages.
+///
+/// Only fields which are set are output, under their protobuf field
+/// names.  Nested messages are output as maps, repeated fields as
+/// arrays, and map fields as maps sorted by key.  Enum values are
+/// output by name if known, otherwise as numbers.  Bytes fields are
+/// output as base64, as for the protobuf JSON mapping.
+///
+/// This is only available with the `prost` feature.
+pub struct AsProst<'a, M>(pub &'a M);
+
+impl<'a, M: ReflectMessage> Visitable for AsProst<'a, M> {
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        visit_message(key, &self.0.transcode_to_dynamic(), output);
+    }
+}
+
+fn visit_message(key: Option<&str>, msg: &DynamicMessage, output: &mut dyn LogVisitor) {
+    output.kv_map(key);
+    for (field, val) in msg.fields() {
+        visit_value(Some(field.name()), &field.kind(), val, output);
+    }
+    output.kv_mapend(key);
+}
+
+// `kind` is the type of a single value, i.e. of a list item, or the
+// map entry message for a map
+fn visit_value(key: Option<&str>, kind: &Kind, val: &Value, output: &mut dyn LogVisitor) {
+    match val {
+        Value::Bool(v) => output.kv_bool(key, *v),
+        Value::I32(v) => output.kv_i64(key, (*v).into()),
+        Value::I64(v) => output.kv_i64(key, *v),
+        Value::U32(v) => output.kv_u64(key, (*v).into()),
+        Value::U64(v) => output.kv_u64(key, *v),
+        Value::F32(v) => output.kv_f64(key, (*v).into()),
+        Value::F64(v) => output.kv_f64(key, *v),
+        Value::String(v) => output.kv_str(key, v),
+        Value::Bytes(v) => Base64::new(v).visit(key, output),
+        Value::EnumNumber(n) => match kind.as_enum().and_then(|e| e.get_value(*n)) {
+            Some(v) => output.kv_str(key, v.name()),
+            None => output.kv_i64(key, (*n).into()),
+        },
+        Value::Message(m) => visit_message(key, m, output),
+        Value::List(items) => {
+            output.kv_arr(key);
+            for item in items {
+                visit_value(None, kind, item, output);
+            }
+            output.kv_arrend(key);
+        }
+        Value::Map(entries) => {
+            let kind = match kind.as_message() {
+                Some(entry) => entry.map_entry_value_field().kind(),
+                None => kind.clone(),
+            };
+            let mut entries: Vec<_> = entries.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            output.kv_map(key);
+            for (k, v) in entries {
+                let k = match k {
+                    MapKey::Bool(k) => k.to_string(),
+                    MapKey::I32(k) => k.to_string(),
+                    MapKey::I64(k) => k.to_string(),
+                    MapKey::U32(k) => k.to_string(),
+                    MapKey::U64(k) => k.to_string(),
+                    MapKey::String(k) => k.clone(),
+                };
+                visit_value(Some(&k), &kind, v, output);
+            }
+            output.kv_mapend(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::AsProst;
+    use crate::{KvToJson, Visitable};
+    use prost_reflect::prost::Message;
+    use prost_reflect::prost_types::field_descriptor_proto::{Label, Type};
+    use prost_reflect::prost_types::{
+        DescriptorProto, EnumDescriptorProto, EnumValueDescriptorProto, FieldDescriptorProto,
+        FileDescriptorProto,
+    };
+    use prost_reflect::{DescriptorPool, DynamicMessage, Value};
+
+    fn field(
+        name: &str,
+        number: i32,
+        typ: Type,
+        label: Label,
+        type_name: &str,
+    ) -> FieldDescriptorProto {
+        FieldDescriptorProto {
+            name: Some(name.into()),
+            number: Some(number),
+            r#type: Some(typ as i32),
+            label: Some(label as i32),
+            type_name: (!type_name.is_empty()).then(|| type_name.into()),
+            ..Default::default()
+        }
+    }
+
+    // Equivalent to:
+    //
+    //     syntax = "proto3";
+    //     package test;
+    //     enum Status { UNKNOWN = 0; ACTIVE = 1; }
+    //     message Point { int32 x = 1; int32 y = 2; }
+    //     message Shape {
+    //         string name = 1;
+    //         Status status = 2;
+    //         repeated Point points = 3;
+    //         bytes tag = 4;
+    //         uint64 count = 5;
+    //     }
+    fn pool() -> DescriptorPool {
+        let file = FileDescriptorProto {
+            name: Some("test.proto".into()),
+            package: Some("test".into()),
+            syntax: Some("proto3".into()),
+            enum_type: vec![EnumDescriptorProto {
+                name: Some("Status".into()),
+                value: vec![
+                    EnumValueDescriptorProto {
+                        name: Some("UNKNOWN".into()),
+                        number: Some(0),
+                        options: None,
+                    },
+                    EnumValueDescriptorProto {
+                        name: Some("ACTIVE".into()),
+                        number: Some(1),
+                        options: None,
+                    },
+                ],
+                ..Default::default()
+            }],
+            message_type: vec![
+                DescriptorProto {
+                    name: Some("Point".into()),
+                    field: vec![
+                        field("x", 1, Type::Int32, Label::Optional, ""),
+                        field("y", 2, Type::Int32, Label::Optional, ""),
+                    ],
+                    ..Default::default()
+                },
+                DescriptorProto {
+                    name: Some("Shape".into()),
+                    field: vec![
+                        field("name", 1, Type::String, Label::Optional, ""),
+                        field("status", 2, Type::Enum, Label::Optional, ".test.Status"),
+                        field("points", 3, Type::Message, Label::Repeated, ".test.Point"),
+                        field("tag", 4, Type::Bytes, Label::Optional, ""),
+                        field("count", 5, Type::Uint64, Label::Optional, ""),
+                    ],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        DescriptorPool::from_file_descriptor_set(prost_reflect::prost_types::FileDescriptorSet {
+            file: vec![file],
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn as_prost() {
+        let pool = pool();
+        let shape = pool.get_message_by_name("test.Shape").unwrap();
+        let point = pool.get_message_by_name("test.Point").unwrap();
+        let mut p1 = DynamicMessage::new(point.clone());
+        p1.set_field_by_name("x", Value::I32(1));
+        p1.set_field_by_name("y", Value::I32(-2));
+        let mut p2 = DynamicMessage::new(point);
+        p2.set_field_by_name("x", Value::I32(3));
+        let mut msg = DynamicMessage::new(shape.clone());
+        msg.set_field_by_name("name", Value::String("tri".into()));
+        msg.set_field_by_name("status", Value::EnumNumber(1));
+        msg.set_field_by_name(
+            "points",
+            Value::List(vec![Value::Message(p1), Value::Message(p2)]),
+        );
+        msg.set_field_by_name("tag", Value::Bytes(vec![0xfb, 0xff].into()));
+
+        // Go through the wire format as a received message would
+        let bytes = msg.encode_to_vec();
+        let msg = DynamicMessage::decode(shape, bytes.as_slice()).unwrap();
+        let out = KvToJson::new(&|lv| AsProst(&msg).visit(Some("shape"), lv), "", "").to_string();
+        assert_eq!(
+            out,
+            r#""shape":{"name":"tri","status":"ACTIVE","points":[{"x":1,"y":-2},{"x":3}],"tag":"+/8="}"#
+        );
+    }
+}
@@ -0,0 +1,58 @@
+//! Ring of recent log messages, attached to error records when the
+//! `recent-messages` feature is enabled
+
+use stakker::{LogLevel, LogVisitor};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fmt::Arguments;
+
+thread_local! {
+    static RECENT: RefCell<VecDeque<String>> = const { RefCell::new(VecDeque::new()) };
+    static CAPACITY: Cell<usize> = const { Cell::new(8) };
+}
+
+/// Set the number of recent messages attached to error records
+///
+/// This only has an effect with the `recent-messages` feature.  The
+/// setting applies to the current thread only, and defaults to 8.
+/// Setting it to zero disables the recording.  If the ring currently
+/// holds more than `count` messages, the oldest are discarded.
+pub fn set_recent_capacity(count: usize) {
+    CAPACITY.with(|c| c.set(count));
+    RECENT.with(|r| {
+        let mut r = r.borrow_mut();
+        while r.len() > count {
+            r.pop_front();
+        }
+    });
+}
+
+/// Record a message in the ring, discarding the oldest if full
+pub(crate) fn push(level: LogLevel, fmt: Arguments<'_>) {
+    let capacity = CAPACITY.with(Cell::get);
+    if capacity == 0 {
+        return;
+    }
+    RECENT.with(|r| {
+        let mut r = r.borrow_mut();
+        while r.len() >= capacity {
+            r.pop_front();
+        }
+        r.push_back(format!("{} {}", level.name(), fmt));
+    });
+}
+
+/// Output the `recent` array of messages, oldest first.  Nothing is
+/// output if the ring is empty.
+pub(crate) fn visit(output: &mut dyn LogVisitor) {
+    RECENT.with(|r| {
+        let r = r.borrow();
+        if !r.is_empty() {
+            output.kv_arr(Some("recent"));
+            for msg in r.iter() {
+                output.kv_str(None, msg);
+            }
+            output.kv_arrend(Some("recent"));
+        }
+    });
+}
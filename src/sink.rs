@@ -0,0 +1,1147 @@
+use crate::adapters::RevealSecrets;
+use crate::line::pinned_first;
+use crate::tree::{self, Value};
+use crate::{Color, KvToJson, LineRecord, Visitable};
+use stakker::{Core, LogFilter, LogID, LogLevel, LogRecord, LogVisitor, Stakker};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Arguments;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::time::Instant;
+
+/// Profiler of value types seen for each key
+///
+/// This accumulates, over many records, the set of value types that
+/// each key has taken (`u64`, `i64`, `f64`, `bool`, `str`, `map` or
+/// `arr`), and can report the keys that have been seen with more than
+/// one type.  This helps to detect schema drift, e.g. a field that is
+/// sometimes a number and sometimes a string.
+///
+/// Keys within maps are recorded as dotted paths, e.g. `map.key`, and
+/// array members as `arr[]`.  Formatted values are recorded as `str`.
+/// Null values are ignored, since they normally indicate a missing
+/// optional value rather than a change of type.
+///
+/// Call [`TypeProfiler::log`] from the logger passed to
+/// `Stakker::set_logger`, sharing the profiler with whatever code
+/// needs to check the report.
+///
+/// [`TypeProfiler::log`]: struct.TypeProfiler.html#method.log
+#[derive(Default)]
+pub struct TypeProfiler {
+    types: BTreeMap<String, BTreeSet<&'static str>>,
+}
+
+impl TypeProfiler {
+    /// Create a new empty profiler
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the value types of all the key-value pairs in a record
+    pub fn log(&mut self, r: &LogRecord<'_>) {
+        self.scan(r.kvscan);
+    }
+
+    /// Record the value types of all the key-value pairs output by
+    /// `kvscan`
+    pub fn scan(&mut self, kvscan: &dyn Fn(&mut dyn LogVisitor)) {
+        kvscan(&mut RevealSecrets::new(&mut Profiler {
+            types: &mut self.types,
+            path: String::new(),
+            stack: Vec::new(),
+        }));
+    }
+
+    /// Get the types seen so far for the given key or dotted path
+    pub fn types(&self, key: &str) -> Vec<&'static str> {
+        self.types
+            .get(key)
+            .map(|t| t.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Get a list of all the keys (or dotted paths) that have been
+    /// seen with more than one type, along with those types, sorted
+    /// by key
+    pub fn inconsistent(&self) -> Vec<(&str, Vec<&'static str>)> {
+        self.types
+            .iter()
+            .filter(|(_, t)| t.len() > 1)
+            .map(|(k, t)| (k.as_str(), t.iter().copied().collect()))
+            .collect()
+    }
+}
+
+struct Profiler<'a> {
+    types: &'a mut BTreeMap<String, BTreeSet<&'static str>>,
+    path: String,
+    stack: Vec<usize>, // Lengths of `path` to restore at end of map/arr
+}
+
+impl<'a> Profiler<'a> {
+    fn push_seg(&mut self, key: Option<&str>) {
+        match key {
+            Some(key) => {
+                if !self.path.is_empty() {
+                    self.path.push('.');
+                }
+                self.path.push_str(key);
+            }
+            None => self.path.push_str("[]"),
+        }
+    }
+    fn record(&mut self, key: Option<&str>, ty: &'static str) {
+        let len = self.path.len();
+        self.push_seg(key);
+        if let Some(set) = self.types.get_mut(&self.path) {
+            set.insert(ty);
+        } else {
+            self.types
+                .insert(self.path.clone(), Some(ty).into_iter().collect());
+        }
+        self.path.truncate(len);
+    }
+    fn open(&mut self, key: Option<&str>, ty: &'static str) {
+        self.record(key, ty);
+        self.stack.push(self.path.len());
+        self.push_seg(key);
+    }
+    fn close(&mut self) {
+        if let Some(len) = self.stack.pop() {
+            self.path.truncate(len);
+        }
+    }
+}
+
+impl<'a> LogVisitor for Profiler<'a> {
+    fn kv_u64(&mut self, key: Option<&str>, _: u64) {
+        self.record(key, "u64");
+    }
+    fn kv_i64(&mut self, key: Option<&str>, _: i64) {
+        self.record(key, "i64");
+    }
+    fn kv_f64(&mut self, key: Option<&str>, _: f64) {
+        self.record(key, "f64");
+    }
+    fn kv_bool(&mut self, key: Option<&str>, _: bool) {
+        self.record(key, "bool");
+    }
+    fn kv_null(&mut self, _: Option<&str>) {}
+    fn kv_str(&mut self, key: Option<&str>, _: &str) {
+        self.record(key, "str");
+    }
+    fn kv_fmt(&mut self, key: Option<&str>, _: &Arguments<'_>) {
+        self.record(key, "str");
+    }
+    fn kv_map(&mut self, key: Option<&str>) {
+        self.open(key, "map");
+    }
+    fn kv_mapend(&mut self, _: Option<&str>) {
+        self.close();
+    }
+    fn kv_arr(&mut self, key: Option<&str>) {
+        self.open(key, "arr");
+    }
+    fn kv_arrend(&mut self, _: Option<&str>) {
+        self.close();
+    }
+}
+
+/// Boxed logger, of the form accepted by `Stakker::set_logger`
+pub type BoxedLogger = Box<dyn FnMut(&mut Core, &LogRecord<'_>)>;
+
+/// Logger which batches records into JSON arrays
+///
+/// Each record is rendered as a JSON object of the form
+/// `{"level":"INFO","id":12,"target":"net","msg":"...","kv":{...}}`
+/// (with `target` omitted if empty), and these are accumulated into
+/// a JSON array, i.e. `[{...},{...}]`.  The array is passed to the
+/// `out` callback when its length in bytes reaches `max_bytes`, or
+/// when [`JsonArraySink::flush`] is called, or when the sink is
+/// dropped.  This suits log APIs which accept records in batches,
+/// e.g. over HTTP.  To allow explicit flushes, the sink can be shared
+/// with the logger closure:
+///
+/// ```ignore
+/// let sink = Rc::new(RefCell::new(JsonArraySink::new(65536, send_batch)));
+/// let sink2 = sink.clone();
+/// stakker.set_logger(filter, move |core, r| sink2.borrow_mut().log(core, r));
+/// ```
+///
+/// Selected keys can be moved to the front of `kv` with
+/// [`JsonArraySink::pinned_keys`], as for [`LineRecord::pinned_keys`].
+///
+/// [`JsonArraySink::flush`]: struct.JsonArraySink.html#method.flush
+/// [`JsonArraySink::pinned_keys`]: struct.JsonArraySink.html#method.pinned_keys
+/// [`LineRecord::pinned_keys`]: struct.LineRecord.html#method.pinned_keys
+pub struct JsonArraySink {
+    buf: String,
+    max_bytes: usize,
+    pinned: &'static [&'static str],
+    out: Box<dyn FnMut(&str)>,
+}
+
+impl JsonArraySink {
+    /// Create a sink which passes each batch to `out`
+    pub fn new(max_bytes: usize, out: impl FnMut(&str) + 'static) -> Self {
+        Self {
+            buf: String::new(),
+            max_bytes,
+            pinned: &[],
+            out: Box::new(out),
+        }
+    }
+
+    /// Output the key-value pairs with the given keys first within
+    /// `kv`, in the order given, with the rest following in the order
+    /// logged.  This makes the important fields easier to find when
+    /// reading the JSON directly.
+    pub fn pinned_keys(mut self, keys: &'static [&'static str]) -> Self {
+        self.pinned = keys;
+        self
+    }
+
+    /// Add a record to the current batch, flushing if the batch has
+    /// reached the size threshold
+    pub fn log(&mut self, _core: &mut Core, r: &LogRecord<'_>) {
+        self.buf.push(if self.buf.is_empty() { '[' } else { ',' });
+        write_json_record(&mut self.buf, r, self.pinned);
+        if self.buf.len() >= self.max_bytes {
+            self.flush();
+        }
+    }
+
+    /// Pass any records in the current batch to the callback as a
+    /// JSON array.  Does nothing if there are no records.
+    pub fn flush(&mut self) {
+        if !self.buf.is_empty() {
+            self.buf.push(']');
+            (self.out)(&self.buf);
+            self.buf.clear();
+        }
+    }
+}
+
+impl Drop for JsonArraySink {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+// Append a record as a JSON object of the form
+// `{"level":"INFO","id":12,"target":"net","msg":"...","kv":{...}}`,
+// with any `pinned` keys first within `kv`
+fn write_json_record(buf: &mut String, r: &LogRecord<'_>, pinned: &[&str]) {
+    let pairs = if pinned.is_empty() {
+        Vec::new()
+    } else {
+        pinned_first(tree::collect(r.kvscan), pinned)
+    };
+    let kvscan = |lv: &mut dyn LogVisitor| {
+        lv.kv_str(Some("level"), r.level.name());
+        lv.kv_u64(Some("id"), r.id);
+        if !r.target.is_empty() {
+            lv.kv_str(Some("target"), r.target);
+        }
+        lv.kv_fmt(Some("msg"), &r.fmt);
+        lv.kv_map(Some("kv"));
+        if pinned.is_empty() {
+            (r.kvscan)(lv);
+        } else {
+            for (key, val) in &pairs {
+                val.visit(Some(key), lv);
+            }
+        }
+        lv.kv_mapend(Some("kv"));
+    };
+    let _ = write!(buf, "{}", KvToJson::object(&kvscan));
+}
+
+/// Logger which writes each record to stderr as a single line
+///
+/// Records are formatted with [`LineRecord`], with the level colored
+/// according to severity if color is enabled.  With `Color::Auto`,
+/// color is used only if stderr is a terminal.  This gives readable
+/// console output with no further setup:
+///
+/// ```ignore
+/// stakker.set_logger(LogFilter::all(&[LogLevel::Info]), stderr_sink(Color::Auto));
+/// ```
+///
+/// [`LineRecord`]: struct.LineRecord.html
+pub fn stderr_sink(color: Color) -> BoxedLogger {
+    let color = color.enabled(&io::stderr());
+    write_sink(io::stderr(), color)
+}
+
+/// Logger which writes each record to `out` as a single line
+///
+/// This works as for [`stderr_sink`], but writes to any
+/// `io::Write`.  Write errors are ignored.
+///
+/// [`stderr_sink`]: fn.stderr_sink.html
+pub fn write_sink(mut out: impl Write + 'static, color: bool) -> BoxedLogger {
+    Box::new(move |_, r| {
+        let line = LineRecord::new(r);
+        let _ = if color {
+            writeln!(out, "{}", line.ansi_color())
+        } else {
+            writeln!(out, "{}", line)
+        };
+    })
+}
+
+/// Logger which writes each record to `out` as a line of JSON
+///
+/// Each record is written as a JSON object on its own line (the "JSON
+/// Lines" format), in the same form as used by [`JsonArraySink`], i.e.
+/// `{"level":"INFO","id":12,"target":"net","msg":"...","kv":{...}}`
+/// with `target` omitted if empty.  Any keys in `pinned` are output
+/// first within `kv`, as for [`JsonArraySink::pinned_keys`].  Write
+/// errors are ignored.
+///
+/// [`JsonArraySink`]: struct.JsonArraySink.html
+/// [`JsonArraySink::pinned_keys`]: struct.JsonArraySink.html#method.pinned_keys
+pub fn write_json_sink(
+    mut out: impl Write + 'static,
+    pinned: &'static [&'static str],
+) -> BoxedLogger {
+    let mut buf = String::new();
+    Box::new(move |_, r| {
+        buf.clear();
+        write_json_record(&mut buf, r, pinned);
+        buf.push('\n');
+        let _ = out.write_all(buf.as_bytes());
+    })
+}
+
+/// Install a default logger writing to stderr
+///
+/// This is a quick way to get log output without choosing and
+/// configuring a renderer.  It calls `Stakker::set_logger` with a
+/// filter that passes INFO, WARN, ERROR and AUDIT records, and a
+/// logger selected by cargo features:
+///
+/// - With the `default_json` feature, each record is written as a line
+///   of JSON, as for [`write_json_sink`].
+///
+/// - Otherwise, or with the `default_line` feature, each record is
+///   written as a single line of text, as for [`stderr_sink`] with
+///   `Color::Auto`.
+///
+/// If both features are enabled, `default_json` takes precedence.
+/// Call `Stakker::set_logger` directly for anything more specific.
+///
+/// [`stderr_sink`]: fn.stderr_sink.html
+/// [`write_json_sink`]: fn.write_json_sink.html
+pub fn quick_init(stakker: &mut Stakker) {
+    let color = Color::Auto.enabled(&io::stderr());
+    quick_init_with(stakker, default_sink(io::stderr(), color));
+}
+
+// Install `logger` with the filter used by `quick_init`
+fn quick_init_with(stakker: &mut Stakker, logger: impl FnMut(&mut Core, &LogRecord<'_>) + 'static) {
+    stakker.set_logger(LogFilter::all(&[LogLevel::Info, LogLevel::Audit]), logger);
+}
+
+// Logger selected by the `default_*` features
+fn default_sink(out: impl Write + 'static, color: bool) -> BoxedLogger {
+    if cfg!(feature = "default_json") {
+        write_json_sink(out, &[])
+    } else {
+        write_sink(out, color)
+    }
+}
+
+/// Logger which passes each record on to a logger selected by level
+///
+/// This allows records at different levels to be formatted or
+/// handled in different ways, e.g. errors as JSON and traces as
+/// single lines.  For example:
+///
+/// ```ignore
+/// let mut routing = LevelRoutingSink::new()
+///     .route(&[LogLevel::Error, LogLevel::Warn], json_logger)
+///     .fallback(line_logger);
+/// stakker.set_logger(filter, move |core, r| routing.log(core, r));
+/// ```
+///
+/// Records at a level with no route and with no fallback logger are
+/// dropped.
+#[derive(Default)]
+pub struct LevelRoutingSink {
+    routes: Vec<(LogLevel, usize)>,
+    loggers: Vec<BoxedLogger>,
+    fallback: Option<usize>,
+}
+
+impl LevelRoutingSink {
+    /// Create a router with no routes configured
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Route records at any of the given levels to `logger`.  This
+    /// replaces any earlier route for those levels.
+    pub fn route(
+        mut self,
+        levels: &[LogLevel],
+        logger: impl FnMut(&mut Core, &LogRecord<'_>) + 'static,
+    ) -> Self {
+        let index = self.loggers.len();
+        self.loggers.push(Box::new(logger));
+        self.routes.retain(|(l, _)| !levels.contains(l));
+        self.routes.extend(levels.iter().map(|l| (*l, index)));
+        self
+    }
+
+    /// Route records at all levels without a route to `logger`
+    pub fn fallback(mut self, logger: impl FnMut(&mut Core, &LogRecord<'_>) + 'static) -> Self {
+        self.fallback = Some(self.loggers.len());
+        self.loggers.push(Box::new(logger));
+        self
+    }
+
+    /// Pass a record on to the logger configured for its level
+    pub fn log(&mut self, core: &mut Core, r: &LogRecord<'_>) {
+        let index = self
+            .routes
+            .iter()
+            .find(|(l, _)| *l == r.level)
+            .map(|(_, i)| *i)
+            .or(self.fallback);
+        if let Some(index) = index {
+            (self.loggers[index])(core, r);
+        }
+    }
+}
+
+/// Logger which sends audit records to one logger and all others to
+/// another
+///
+/// Audit records often have to go to a separate compliance store
+/// from operational logs.  AUDIT records, and span OPEN and CLOSE
+/// records, are passed to `audit`, and all other records to `other`.
+/// Spans go with the audit stream so that the audit records can be
+/// related to the spans they were logged within.  For example:
+///
+/// ```ignore
+/// stakker.set_logger(filter, split_audit(audit_logger, stderr_sink(Color::Auto)));
+/// ```
+///
+/// To route spans with the other records instead, use a
+/// [`LevelRoutingSink`] directly:
+///
+/// ```ignore
+/// let mut routing = LevelRoutingSink::new()
+///     .route(&[LogLevel::Audit], audit_logger)
+///     .fallback(other_logger);
+/// ```
+///
+/// [`LevelRoutingSink`]: struct.LevelRoutingSink.html
+pub fn split_audit(
+    audit: impl FnMut(&mut Core, &LogRecord<'_>) + 'static,
+    other: impl FnMut(&mut Core, &LogRecord<'_>) + 'static,
+) -> BoxedLogger {
+    let mut routing = LevelRoutingSink::new()
+        .route(&[LogLevel::Audit, LogLevel::Open, LogLevel::Close], audit)
+        .fallback(other);
+    Box::new(move |core, r| routing.log(core, r))
+}
+
+/// Logger wrapper which limits the rate of records passed through
+///
+/// This protects downstream systems during log storms.  It uses a
+/// token bucket, based on the `Core` clock (so it works in virtual
+/// time).  The bucket holds up to `burst` tokens, and is refilled at
+/// `rate` tokens per second.  Each record passed through uses up one
+/// token, and records arriving when the bucket is empty are dropped.
+/// So `burst` records may be passed through at once, but over time
+/// the rate is limited to `rate` records per second.
+///
+/// Dropped records are counted.  When the next record is passed
+/// through after some have been dropped, a WARN-level summary record
+/// with a `dropped` count is passed through first.  If a storm is
+/// followed by silence, the summary would be delayed until the next
+/// record, so call [`RateLimitSink::flush`] from a periodic timer to
+/// get it sooner, and at shutdown.  Any count still pending when the
+/// sink is dropped is lost, since the summary can't be logged without
+/// a `Core`.
+///
+/// [`RateLimitSink::flush`]: struct.RateLimitSink.html#method.flush
+pub struct RateLimitSink {
+    inner: BoxedLogger,
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last: Option<Instant>,
+    dropped: u64,
+}
+
+impl RateLimitSink {
+    /// Create a rate-limiter which passes up to `rate` records per
+    /// second through to `inner`, with bursts of up to `burst`
+    /// records.  The bucket starts full.
+    pub fn new(
+        rate: u32,
+        burst: u32,
+        inner: impl FnMut(&mut Core, &LogRecord<'_>) + 'static,
+    ) -> Self {
+        Self {
+            inner: Box::new(inner),
+            rate: rate.into(),
+            burst: burst.into(),
+            tokens: burst.into(),
+            last: None,
+            dropped: 0,
+        }
+    }
+
+    /// Pass the record through, or drop it if the limit is exceeded
+    pub fn log(&mut self, core: &mut Core, r: &LogRecord<'_>) {
+        let now = core.now();
+        if let Some(last) = self.last {
+            let secs = now.saturating_duration_since(last).as_secs_f64();
+            self.tokens = (self.tokens + secs * self.rate).min(self.burst);
+        }
+        self.last = Some(now);
+
+        if self.tokens < 1.0 {
+            self.dropped += 1;
+            return;
+        }
+        self.tokens -= 1.0;
+
+        self.flush(core);
+        (self.inner)(core, r);
+    }
+
+    /// Pass through the summary record for any records dropped since
+    /// the last one.  Does nothing if none have been dropped.  The
+    /// summary doesn't use up a token.
+    pub fn flush(&mut self, core: &mut Core) {
+        if self.dropped > 0 {
+            let dropped = std::mem::replace(&mut self.dropped, 0);
+            (self.inner)(
+                core,
+                &LogRecord {
+                    id: 0,
+                    level: LogLevel::Warn,
+                    target: "",
+                    fmt: format_args!("Log records dropped due to rate limit"),
+                    kvscan: &|output: &mut dyn LogVisitor| output.kv_u64(Some("dropped"), dropped),
+                },
+            );
+        }
+    }
+}
+
+/// Logger wrapper which adds a fixed set of labels to every record
+///
+/// The labels are output as string key-value pairs before the
+/// record's own key-value pairs, e.g. `service=api env=prod`.  This
+/// identifies the deployment that produced each record when logs from
+/// several services are aggregated, without needing a
+/// [`LogContext`] on every thread.  For example:
+///
+/// ```ignore
+/// let mut sink = LabeledSink::new(stderr_sink(Color::Auto))
+///     .label("service", "api")
+///     .label("env", "prod");
+/// stakker.set_logger(filter, move |core, r| sink.log(core, r));
+/// ```
+///
+/// [`LogContext`]: struct.LogContext.html
+pub struct LabeledSink {
+    inner: BoxedLogger,
+    labels: Vec<(String, String)>,
+}
+
+impl LabeledSink {
+    /// Create a wrapper which passes records through to `inner`, with
+    /// no labels yet
+    pub fn new(inner: impl FnMut(&mut Core, &LogRecord<'_>) + 'static) -> Self {
+        Self {
+            inner: Box::new(inner),
+            labels: Vec::new(),
+        }
+    }
+
+    /// Add a label to be output on every record
+    pub fn label(mut self, key: impl Into<String>, val: impl Into<String>) -> Self {
+        self.labels.push((key.into(), val.into()));
+        self
+    }
+
+    /// Pass the record through with the labels added
+    pub fn log(&mut self, core: &mut Core, r: &LogRecord<'_>) {
+        let labels = &self.labels;
+        let kvscan = |output: &mut dyn LogVisitor| {
+            for (key, val) in labels {
+                output.kv_str(Some(key), val);
+            }
+            (r.kvscan)(output);
+        };
+        (self.inner)(
+            core,
+            &LogRecord {
+                id: r.id,
+                level: r.level,
+                target: r.target,
+                fmt: r.fmt,
+                kvscan: &kvscan,
+            },
+        );
+    }
+}
+
+/// Owned copy of a log record, for rewriting by [`MapRecordSink`]
+///
+/// [`MapRecordSink`]: struct.MapRecordSink.html
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordView {
+    /// Level of the record
+    pub level: LogLevel,
+    /// `LogID` of the record
+    pub id: LogID,
+    /// Target of the record, or `""`
+    pub target: String,
+    /// Freeform text of the record, formatted
+    pub msg: String,
+    /// Key-value pairs of the record, in order
+    pub kv: Vec<(String, Value)>,
+}
+
+impl RecordView {
+    /// Make an owned copy of a record
+    pub fn new(r: &LogRecord<'_>) -> Self {
+        Self {
+            level: r.level,
+            id: r.id,
+            target: r.target.to_string(),
+            msg: r.fmt.to_string(),
+            kv: tree::collect(r.kvscan),
+        }
+    }
+
+    /// Get the value of the first top-level pair with the given key
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.kv.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+}
+
+/// Logger wrapper which passes records through a transform function
+///
+/// Each record is copied into a [`RecordView`] and passed to the
+/// function, which may return it unchanged, return a rewritten
+/// version, or return `None` to drop the record.  Records returned
+/// are passed on to the inner logger.  This allows general filtering
+/// and rewriting of whole records, e.g. dropping records based on
+/// conditions that change at runtime, or renaming targets.  For
+/// example:
+///
+/// ```ignore
+/// let mut sink = MapRecordSink::new(
+///     |mut view: RecordView| {
+///         if view.target == "net::tcp" {
+///             view.target = "net".to_string();
+///         }
+///         Some(view)
+///     },
+///     stderr_sink(Color::Auto),
+/// );
+/// stakker.set_logger(filter, move |core, r| sink.log(core, r));
+/// ```
+///
+/// Since every record is copied, this is more costly than wrappers
+/// such as [`LabeledSink`] which work on the record in place.
+///
+/// [`LabeledSink`]: struct.LabeledSink.html
+/// [`RecordView`]: struct.RecordView.html
+pub struct MapRecordSink<F> {
+    map: F,
+    inner: BoxedLogger,
+}
+
+impl<F: FnMut(RecordView) -> Option<RecordView>> MapRecordSink<F> {
+    /// Create a wrapper which passes records through `map` and then
+    /// on to `inner`
+    pub fn new(map: F, inner: impl FnMut(&mut Core, &LogRecord<'_>) + 'static) -> Self {
+        Self {
+            map,
+            inner: Box::new(inner),
+        }
+    }
+
+    /// Transform the record, and pass it on unless it was dropped
+    pub fn log(&mut self, core: &mut Core, r: &LogRecord<'_>) {
+        if let Some(view) = (self.map)(RecordView::new(r)) {
+            let kvscan = |output: &mut dyn LogVisitor| {
+                for (key, val) in &view.kv {
+                    val.visit(Some(key), output);
+                }
+            };
+            (self.inner)(
+                core,
+                &LogRecord {
+                    id: view.id,
+                    level: view.level,
+                    target: &view.target,
+                    fmt: format_args!("{}", view.msg),
+                    kvscan: &kvscan,
+                },
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        default_sink, quick_init_with, write_json_sink, write_sink, JsonArraySink, LabeledSink,
+        LevelRoutingSink, LogVisitor, MapRecordSink, RateLimitSink, RecordView, TypeProfiler,
+        Value,
+    };
+    use crate::macro_support::max_level_allows;
+    use crate::test::strip_features;
+    use crate::{audit, debug, error, info, trace, KvSingleLine, KvToJson, RedactingVisitor};
+    use stakker::{Core, LogFilter, LogLevel, LogRecord, Stakker};
+    use std::cell::{Cell, RefCell};
+    use std::io;
+    use std::rc::Rc;
+    use std::time::{Duration, Instant};
+
+    type Output = Rc<RefCell<Vec<String>>>;
+
+    // Set up a Stakker with the given logger
+    fn setup(logger: impl FnMut(&mut Core, &LogRecord<'_>) + 'static) -> Stakker {
+        let mut stakker = Stakker::new(Instant::now());
+        stakker.set_logger(
+            LogFilter::all(&[LogLevel::Trace, LogLevel::Audit, LogLevel::Open]),
+            strip_features(&[], logger),
+        );
+        stakker
+    }
+
+    // Logger which appends the formatted text to `out`
+    fn capture(out: &Output) -> impl FnMut(&mut Core, &LogRecord<'_>) {
+        let out = out.clone();
+        move |_, r| {
+            out.borrow_mut().push(format!(
+                "{} {}{}",
+                r.level,
+                r.fmt,
+                KvSingleLine::new(r.kvscan, " ", "")
+            ))
+        }
+    }
+
+    // Lines expected from records at the given levels, leaving out
+    // those compiled out by the `max_level_*` features
+    fn expect(lines: &[(LogLevel, &'static str)]) -> Vec<&'static str> {
+        lines
+            .iter()
+            .filter(|(level, _)| max_level_allows(*level))
+            .map(|(_, line)| *line)
+            .collect()
+    }
+
+    #[test]
+    fn type_profiler() {
+        let mut tp = TypeProfiler::new();
+        tp.scan(&|lv: &mut dyn LogVisitor| {
+            lv.kv_u64(Some("port"), 80);
+            lv.kv_str(Some("host"), "localhost");
+            lv.kv_map(Some("map"));
+            lv.kv_i64(Some("delta"), -1);
+            lv.kv_mapend(Some("map"));
+        });
+        assert!(tp.inconsistent().is_empty());
+        tp.scan(&|lv: &mut dyn LogVisitor| {
+            lv.kv_fmt(Some("port"), &format_args!("{}", "http"));
+            lv.kv_null(Some("host"));
+            lv.kv_map(Some("map"));
+            lv.kv_i64(Some("delta"), 1);
+            lv.kv_arr(Some("list"));
+            lv.kv_u64(None, 1);
+            lv.kv_arrend(Some("list"));
+            lv.kv_mapend(Some("map"));
+        });
+        assert_eq!(tp.inconsistent(), vec![("port", vec!["str", "u64"])]);
+        assert_eq!(tp.types("map.list[]"), vec!["u64"]);
+        assert_eq!(tp.types("host"), vec!["str"]);
+    }
+
+    #[test]
+    fn level_routing() {
+        let errors = Output::default();
+        let traces = Output::default();
+        let other = Output::default();
+        let mut routing = LevelRoutingSink::new()
+            .route(&[LogLevel::Error, LogLevel::Warn], capture(&errors))
+            .route(&[LogLevel::Trace], capture(&traces))
+            .fallback(capture(&other));
+        let mut stakker = setup(move |core, r| routing.log(core, r));
+        let s = &mut stakker;
+        error!([s], "A");
+        trace!([s], "B");
+        info!([s], "C");
+        assert_eq!(*errors.borrow(), expect(&[(LogLevel::Error, "ERROR A")]));
+        assert_eq!(*traces.borrow(), expect(&[(LogLevel::Trace, "TRACE B")]));
+        assert_eq!(*other.borrow(), expect(&[(LogLevel::Info, "INFO C")]));
+    }
+
+    #[test]
+    fn split_audit() {
+        let audit_out = Output::default();
+        let other = Output::default();
+        let mut stakker = setup(super::split_audit(capture(&audit_out), capture(&other)));
+        let s = &mut stakker;
+        audit!([s], Login, user: "jim");
+        info!([s], "Started");
+        let id = s.log_span_open("session", 0, |_| ());
+        s.log_span_close(id, format_args!(""), |_| ());
+        assert_eq!(
+            *audit_out.borrow(),
+            vec!["AUDIT Login user=jim", "OPEN session", "CLOSE "]
+        );
+        assert_eq!(*other.borrow(), expect(&[(LogLevel::Info, "INFO Started")]));
+    }
+
+    #[test]
+    fn rate_limit() {
+        if !max_level_allows(LogLevel::Info) {
+            return;
+        }
+        let out = Output::default();
+        let mut limiter = RateLimitSink::new(2, 3, capture(&out));
+        let mut stakker = setup(move |core, r| limiter.log(core, r));
+        let s = &mut stakker;
+        for i in 0..6 {
+            info!([s], i, "Record");
+        }
+        assert_eq!(
+            *out.borrow(),
+            vec!["INFO Record i=0", "INFO Record i=1", "INFO Record i=2"]
+        );
+        out.borrow_mut().clear();
+
+        // Half a second gives one more token
+        let now = s.now() + Duration::from_millis(500);
+        s.run(now, false);
+        for i in 6..8 {
+            info!([s], i, "Record");
+        }
+        assert_eq!(
+            *out.borrow(),
+            vec![
+                "WARN Log records dropped due to rate limit dropped=3",
+                "INFO Record i=6"
+            ]
+        );
+    }
+
+    #[test]
+    fn rate_limit_flush() {
+        if !max_level_allows(LogLevel::Info) {
+            return;
+        }
+        let out = Output::default();
+        let limiter = Rc::new(RefCell::new(RateLimitSink::new(1, 1, capture(&out))));
+        let limiter2 = limiter.clone();
+        let mut stakker = setup(move |core, r| limiter2.borrow_mut().log(core, r));
+        let s = &mut stakker;
+        for i in 0..3 {
+            info!([s], i, "Record");
+        }
+        limiter.borrow_mut().flush(s);
+        limiter.borrow_mut().flush(s);
+        assert_eq!(
+            *out.borrow(),
+            vec![
+                "INFO Record i=0",
+                "WARN Log records dropped due to rate limit dropped=2"
+            ]
+        );
+    }
+
+    #[test]
+    fn json_lines() {
+        if !max_level_allows(LogLevel::Info) {
+            return;
+        }
+        let buf = Buffer::default();
+        {
+            let mut s = setup(write_json_sink(buf.clone(), &[]));
+            let s = &mut s;
+            error!([s], target: "net", port: 80, "Failed");
+            info!([s], target: "", "Started");
+        }
+        assert_eq!(
+            buf.take(),
+            concat!(
+                r#"{"level":"ERROR","id":0,"target":"net","msg":"Failed","kv":{"port":80}}"#,
+                "\n",
+                r#"{"level":"INFO","id":0,"msg":"Started","kv":{}}"#,
+                "\n"
+            )
+        );
+
+        // Pinned keys
+        {
+            let mut s = setup(write_json_sink(buf.clone(), &["error", "missing"]));
+            let s = &mut s;
+            info!([s], target: "", port: 80, error: "refused", "Failed");
+        }
+        assert_eq!(
+            buf.take(),
+            concat!(
+                r#"{"level":"INFO","id":0,"msg":"Failed","kv":{"error":"refused","port":80}}"#,
+                "\n"
+            )
+        );
+    }
+
+    #[test]
+    fn quick_init() {
+        {
+            let mut s = Stakker::new(Instant::now());
+            super::quick_init(&mut s);
+            assert!(s.log_check(LogLevel::Info));
+            assert!(s.log_check(LogLevel::Audit));
+            assert!(!s.log_check(LogLevel::Debug));
+        }
+
+        let buf = Buffer::default();
+        {
+            let mut s = Stakker::new(Instant::now());
+            quick_init_with(
+                &mut s,
+                strip_features(&[], default_sink(buf.clone(), false)),
+            );
+            let s = &mut s;
+            info!([s], target: "", port: 80, "Started");
+            debug!([s], target: "", "Filtered out");
+        }
+        let expected = if !max_level_allows(LogLevel::Info) {
+            ""
+        } else if cfg!(feature = "default_json") {
+            "{\"level\":\"INFO\",\"id\":0,\"msg\":\"Started\",\"kv\":{\"port\":80}}\n"
+        } else {
+            "INFO  #0 Started port=80\n"
+        };
+        assert_eq!(buf.take(), expected);
+    }
+
+    #[test]
+    fn map_record() {
+        if !max_level_allows(LogLevel::Info) {
+            return;
+        }
+        let out = Output::default();
+        let threshold = Rc::new(Cell::new(100));
+        let threshold2 = threshold.clone();
+        let mut sink = MapRecordSink::new(
+            move |mut view: RecordView| {
+                if let Some(Value::U64(ms)) = view.get("ms") {
+                    if *ms < threshold2.get() {
+                        return None;
+                    }
+                }
+                if view.target == "net::tcp" {
+                    view.target = "net".to_string();
+                }
+                Some(view)
+            },
+            {
+                let out = out.clone();
+                move |_: &mut Core, r: &LogRecord<'_>| {
+                    let kv = KvSingleLine::new(r.kvscan, " ", "");
+                    out.borrow_mut()
+                        .push(format!("{} [{}] {}{}", r.level, r.target, r.fmt, kv));
+                }
+            },
+        );
+        let mut stakker = setup(move |core, r| sink.log(core, r));
+        let s = &mut stakker;
+        info!([s], target: "", ms: 50_u64, "Fast");
+        info!([s], target: "", ms: 150_u64, "Slow");
+        threshold.set(10);
+        info!([s], target: "", ms: 50_u64, "Fast");
+        info!([s], target: "net::tcp", port: 80, "Connected");
+        assert_eq!(
+            *out.borrow(),
+            vec![
+                "INFO [] Slow ms=150",
+                "INFO [] Fast ms=50",
+                "INFO [net] Connected port=80"
+            ]
+        );
+    }
+
+    #[test]
+    fn map_record_secret() {
+        // A secret passed through a `RecordView` is still masked by a
+        // redacting sink after it
+        let out = Output::default();
+        let mut sink = MapRecordSink::new(Some, {
+            let out = out.clone();
+            move |_: &mut Core, r: &LogRecord<'_>| {
+                let kvscan =
+                    |lv: &mut dyn LogVisitor| (r.kvscan)(&mut RedactingVisitor::new(lv, &[]));
+                out.borrow_mut()
+                    .push(KvToJson::new(&kvscan, "", "").to_string());
+            }
+        });
+        let mut stakker = setup(move |core, r| sink.log(core, r));
+        let s = &mut stakker;
+        let token = "abc123";
+        audit!([s], Login, user: "jim", secret!(token));
+        assert_eq!(*out.borrow(), vec![r#""user":"jim","token":"<redacted>""#]);
+    }
+
+    #[test]
+    fn labeled() {
+        let out = Output::default();
+        let mut sink = LabeledSink::new(capture(&out))
+            .label("service", "api")
+            .label("env", "prod");
+        let mut stakker = setup(move |core, r| sink.log(core, r));
+        let s = &mut stakker;
+        info!([s], port: 80, "Listening");
+        error!([s], "Failed");
+        assert_eq!(
+            *out.borrow(),
+            expect(&[
+                (
+                    LogLevel::Info,
+                    "INFO Listening service=api env=prod port=80"
+                ),
+                (LogLevel::Error, "ERROR Failed service=api env=prod")
+            ])
+        );
+    }
+
+    // Writer into a shared buffer
+    #[derive(Clone, Default)]
+    struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(data);
+            Ok(data.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Buffer {
+        fn take(&self) -> String {
+            String::from_utf8(self.0.take()).unwrap()
+        }
+    }
+
+    #[test]
+    fn write_lines() {
+        if !max_level_allows(LogLevel::Info) {
+            return;
+        }
+        let buf = Buffer::default();
+        {
+            let mut s = setup(write_sink(buf.clone(), false));
+            let s = &mut s;
+            error!([s], target: "net", port: 80, "Failed");
+            info!([s], target: "", "Started");
+        }
+        assert_eq!(
+            buf.take(),
+            "ERROR #0 net: Failed port=80\nINFO  #0 Started\n"
+        );
+
+        let buf = Buffer::default();
+        {
+            let mut s = setup(write_sink(buf.clone(), true));
+            let s = &mut s;
+            error!([s], target: "", port: 80, "Failed");
+        }
+        assert_eq!(
+            buf.take(),
+            "\x1b[1;31mERROR\x1b[0m #0 Failed \x1b[2mport=\x1b[0m80\n"
+        );
+    }
+
+    #[test]
+    fn json_array() {
+        if !max_level_allows(LogLevel::Info) {
+            return;
+        }
+        let batches = Output::default();
+        let batches2 = batches.clone();
+        let sink = Rc::new(RefCell::new(JsonArraySink::new(1000, move |b| {
+            batches2.borrow_mut().push(b.to_string())
+        })));
+        let sink2 = sink.clone();
+        let mut stakker = setup(move |core, r| sink2.borrow_mut().log(core, r));
+        let s = &mut stakker;
+        info!([s], target: "", port: 80, "Started");
+        error!([s], target: "net", "Lost \"link\"");
+        trace!([s], target: "", "Tick");
+        assert!(batches.borrow().is_empty());
+        sink.borrow_mut().flush();
+        sink.borrow_mut().flush();
+        let tick = if max_level_allows(LogLevel::Trace) {
+            r#",{"level":"TRACE","id":0,"msg":"Tick","kv":{}}"#
+        } else {
+            ""
+        };
+        assert_eq!(
+            *batches.borrow(),
+            vec![format!(
+                "{}{}{}]",
+                r#"[{"level":"INFO","id":0,"msg":"Started","kv":{"port":80}},"#,
+                r#"{"level":"ERROR","id":0,"target":"net","msg":"Lost \"link\"","kv":{}}"#,
+                tick
+            )]
+        );
+        let v: serde_json::Value = serde_json::from_str(&batches.borrow()[0]).unwrap();
+        assert_eq!(
+            v.as_array().unwrap().len(),
+            if tick.is_empty() { 2 } else { 3 }
+        );
+
+        // Size threshold
+        batches.borrow_mut().clear();
+        *sink.borrow_mut() = JsonArraySink::new(60, {
+            let batches = batches.clone();
+            move |b| batches.borrow_mut().push(b.to_string())
+        });
+        for i in 0..3 {
+            info!([s], target: "", i, "Record");
+        }
+        assert_eq!(batches.borrow().len(), 1);
+        drop(stakker);
+        drop(sink);
+        assert_eq!(batches.borrow().len(), 2);
+        for b in batches.borrow().iter() {
+            serde_json::from_str::<serde_json::Value>(b).unwrap();
+        }
+
+        // Pinned keys
+        batches.borrow_mut().clear();
+        let mut sink = JsonArraySink::new(1000, {
+            let batches = batches.clone();
+            move |b| batches.borrow_mut().push(b.to_string())
+        })
+        .pinned_keys(&["user"]);
+        let mut stakker = setup(move |core, r| sink.log(core, r));
+        let s = &mut stakker;
+        audit!([s], Login, target: "", port: 22, user: "jim");
+        drop(stakker);
+        assert_eq!(
+            *batches.borrow(),
+            vec![r#"[{"level":"AUDIT","id":0,"msg":"Login","kv":{"user":"jim","port":22}}]"#]
+        );
+    }
+}
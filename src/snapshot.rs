@@ -0,0 +1,80 @@
+use crate::Visitable;
+use stakker::LogVisitor;
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+
+type Provider = dyn Fn(&mut dyn LogVisitor);
+
+thread_local! {
+    static REGISTRY: RefCell<Vec<(String, Weak<Provider>)>> = RefCell::new(Vec::new());
+}
+
+/// Register a state snapshot provider for [`dump_all!`]
+///
+/// `provider` is called whenever a snapshot is logged, and should
+/// output the current state as key-value pairs.  These are placed
+/// in a map under the key `name`.  Since an actor's state is not
+/// accessible from outside the actor, the provider will normally
+/// capture some shared state, e.g. a `stakker::Share` or some
+/// counters in an `Rc<Cell<_>>`.
+///
+/// The registry is per-thread, and only holds a weak reference to
+/// the provider.  The returned [`SnapshotGuard`] holds the strong
+/// reference, and the provider is unregistered when the guard is
+/// dropped.  So for an actor, keep the guard in the actor's state,
+/// and the provider will be removed automatically when the actor
+/// terminates.
+///
+/// [`SnapshotGuard`]: struct.SnapshotGuard.html
+/// [`dump_all!`]: macro.dump_all.html
+pub fn register_snapshot(
+    name: impl Into<String>,
+    provider: impl Fn(&mut dyn LogVisitor) + 'static,
+) -> SnapshotGuard {
+    let provider: Rc<Provider> = Rc::new(provider);
+    REGISTRY.with(|r| r.borrow_mut().push((name.into(), Rc::downgrade(&provider))));
+    SnapshotGuard {
+        _provider: provider,
+    }
+}
+
+/// Guard which keeps a snapshot provider registered
+///
+/// See [`register_snapshot`].
+///
+/// [`register_snapshot`]: fn.register_snapshot.html
+pub struct SnapshotGuard {
+    _provider: Rc<Provider>,
+}
+
+/// All currently-registered state snapshots
+///
+/// When visited, this outputs a map containing the output of every
+/// live provider registered with [`register_snapshot`] on this
+/// thread, in order of registration.  This is used by
+/// [`dump_all!`], but may also be logged directly.
+///
+/// [`dump_all!`]: macro.dump_all.html
+/// [`register_snapshot`]: fn.register_snapshot.html
+pub struct Snapshots;
+
+impl Visitable for Snapshots {
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        // Take strong references and drop dead entries first, so that
+        // the registry isn't borrowed whilst the providers run
+        let live: Vec<(String, Rc<Provider>)> = REGISTRY.with(|r| {
+            let mut r = r.borrow_mut();
+            r.retain(|(_, p)| p.strong_count() > 0);
+            r.iter()
+                .filter_map(|(name, p)| p.upgrade().map(|p| (name.clone(), p)))
+                .collect()
+        });
+        output.kv_map(key);
+        for (name, provider) in live {
+            output.kv_map(Some(&name));
+            provider(output);
+            output.kv_mapend(Some(&name));
+        }
+        output.kv_mapend(key);
+    }
+}
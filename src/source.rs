@@ -0,0 +1,107 @@
+//! Traits describing what the logging macros need from `[cx]`
+
+use crate::LogCx;
+use stakker::{Actor, Core, Cx, LogID, Stakker};
+use std::any::type_name;
+
+/// Source of a `LogID` for the logging macros
+///
+/// The first value in the `[a]` or `[a, b]` argument of the logging
+/// macros must provide `a.access_log_id()`.  This trait expresses
+/// that requirement.  It is implemented for [`LogCx`], and for the
+/// **Stakker** types that already provide that method: `Cx` and
+/// `Actor` give the actor's `LogID`, and `Core` and `Stakker` give
+/// zero.  Implement it for your own types to allow logging against
+/// them with `[source, core]`.  The macros call the method by name,
+/// so inherent methods with the same signature also work.
+///
+/// [`LogCx`]: struct.LogCx.html
+pub trait LogIdSource {
+    /// Get the `LogID` to log against
+    fn access_log_id(&self) -> LogID;
+
+    /// Get the target to use when no `target:` is given to the
+    /// logging macros, or `""` for no preference.  This is only
+    /// called with the `target-actor-type` feature.  `Cx` and `Actor`
+    /// return the actor's type name, and by default this returns
+    /// `""`.
+    fn log_target(&self) -> &'static str {
+        ""
+    }
+}
+
+/// Source of a `Core` reference for the logging macros
+///
+/// The last value in the `[a]` or `[a, b]` argument of the logging
+/// macros must provide `a.access_core()`.  This trait expresses that
+/// requirement.  It is implemented for [`LogCx`], and for the
+/// **Stakker** types that already provide that method: `Cx`, `Core`
+/// and `Stakker`.  Implement both this and [`LogIdSource`] for your
+/// own types to use them as `[cx]` directly.
+///
+/// [`LogCx`]: struct.LogCx.html
+/// [`LogIdSource`]: trait.LogIdSource.html
+pub trait CoreSource {
+    /// Get the `Core` reference to log through
+    fn access_core(&mut self) -> &mut Core;
+}
+
+impl LogIdSource for LogCx<'_> {
+    fn access_log_id(&self) -> LogID {
+        LogCx::access_log_id(self)
+    }
+}
+
+impl CoreSource for LogCx<'_> {
+    fn access_core(&mut self) -> &mut Core {
+        LogCx::access_core(self)
+    }
+}
+
+impl<A> LogIdSource for Cx<'_, A> {
+    fn access_log_id(&self) -> LogID {
+        Cx::access_log_id(self)
+    }
+    fn log_target(&self) -> &'static str {
+        type_name::<A>()
+    }
+}
+
+impl<A> CoreSource for Cx<'_, A> {
+    fn access_core(&mut self) -> &mut Core {
+        Core::access_core(self)
+    }
+}
+
+impl<A> LogIdSource for Actor<A> {
+    fn access_log_id(&self) -> LogID {
+        Actor::access_log_id(self)
+    }
+    fn log_target(&self) -> &'static str {
+        type_name::<A>()
+    }
+}
+
+impl LogIdSource for Core {
+    fn access_log_id(&self) -> LogID {
+        Core::access_log_id(self)
+    }
+}
+
+impl CoreSource for Core {
+    fn access_core(&mut self) -> &mut Core {
+        self
+    }
+}
+
+impl LogIdSource for Stakker {
+    fn access_log_id(&self) -> LogID {
+        Core::access_log_id(self)
+    }
+}
+
+impl CoreSource for Stakker {
+    fn access_core(&mut self) -> &mut Core {
+        Core::access_core(self)
+    }
+}
@@ -3,9 +3,13 @@ use std::cell::Cell;
 use std::collections::HashMap;
 use std::net::Ipv4Addr;
 use std::rc::Rc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use crate::{error, KvSingleLine, Visitable};
+use crate::macro_support::max_level_allows;
+use crate::{
+    audit_msg, debug, dump_all, error, info, log_build_info, log_on_change, log_once,
+    register_snapshot, trace_val, warn_every, KvSingleLine, LogContext, LogCx, Visitable,
+};
 
 // TODO: Need tests of all the different shortcuts
 // TODO: Need test of audit!
@@ -24,6 +28,9 @@ impl Visitable for MyType {
 
 #[test]
 fn error_formatting() {
+    if !max_level_allows(LogLevel::Error) {
+        return;
+    }
     let mut stakker = Stakker::new(Instant::now());
     let s = &mut stakker;
     let out = Rc::new(Cell::new(String::new()));
@@ -31,15 +38,15 @@ fn error_formatting() {
 
     s.set_logger(
         LogFilter::all(&[LogLevel::Trace, LogLevel::Audit, LogLevel::Open]),
-        move |_, r| {
+        strip_features(&[], move |_, r| {
             out2.set(format!(
                 "{} #{} {} {}",
                 r.level,
                 r.id,
                 r.fmt,
-                KvSingleLine::new(r.kvscan, "{", "}")
+                KvSingleLine::braced(r.kvscan)
             ));
-        },
+        }),
     );
 
     let a = "TEST";
@@ -66,3 +73,736 @@ fn error_formatting() {
         _ => panic!("Unexpected output: {}", o),
     }
 }
+
+// Keys added to every record by optional features.  The test loggers
+// remove these, so that exact-output assertions hold whatever
+// features are enabled.
+const FEATURE_KEYS: &[&str] = &["loc", "template_hash", "_render_ns", "recent"];
+
+// Wrap a logger to remove the top-level `FEATURE_KEYS` from records,
+// other than those in `keep`
+pub(crate) fn strip_features(
+    keep: &'static [&'static str],
+    mut logger: impl FnMut(&mut Core, &LogRecord<'_>),
+) -> impl FnMut(&mut Core, &LogRecord<'_>) {
+    let strip: Vec<&str> = FEATURE_KEYS
+        .iter()
+        .copied()
+        .filter(|k| !keep.contains(k))
+        .collect();
+    move |core, r| {
+        let kvscan = |lv: &mut dyn LogVisitor| {
+            (r.kvscan)(&mut StripKeys {
+                inner: lv,
+                keys: &strip,
+                depth: 0,
+                skip: 0,
+            })
+        };
+        let r = LogRecord {
+            id: r.id,
+            level: r.level,
+            target: r.target,
+            fmt: r.fmt,
+            kvscan: &kvscan,
+        };
+        logger(core, &r);
+    }
+}
+
+// Visitor which drops top-level items with the given keys
+struct StripKeys<'a> {
+    inner: &'a mut dyn LogVisitor,
+    keys: &'a [&'a str],
+    depth: usize, // Depth of nesting of forwarded maps and arrays
+    skip: usize,  // Depth of nesting within a dropped map or array
+}
+
+impl StripKeys<'_> {
+    fn dropped(&self, key: Option<&str>) -> bool {
+        self.skip > 0 || (self.depth == 0 && matches!(key, Some(k) if self.keys.contains(&k)))
+    }
+    fn start(&mut self, key: Option<&str>) -> bool {
+        if self.dropped(key) {
+            self.skip += 1;
+            false
+        } else {
+            self.depth += 1;
+            true
+        }
+    }
+    fn end(&mut self) -> bool {
+        if self.skip > 0 {
+            self.skip -= 1;
+            false
+        } else {
+            self.depth = self.depth.saturating_sub(1);
+            true
+        }
+    }
+}
+
+impl LogVisitor for StripKeys<'_> {
+    fn kv_u64(&mut self, key: Option<&str>, val: u64) {
+        if !self.dropped(key) {
+            self.inner.kv_u64(key, val);
+        }
+    }
+    fn kv_i64(&mut self, key: Option<&str>, val: i64) {
+        if !self.dropped(key) {
+            self.inner.kv_i64(key, val);
+        }
+    }
+    fn kv_f64(&mut self, key: Option<&str>, val: f64) {
+        if !self.dropped(key) {
+            self.inner.kv_f64(key, val);
+        }
+    }
+    fn kv_bool(&mut self, key: Option<&str>, val: bool) {
+        if !self.dropped(key) {
+            self.inner.kv_bool(key, val);
+        }
+    }
+    fn kv_null(&mut self, key: Option<&str>) {
+        if !self.dropped(key) {
+            self.inner.kv_null(key);
+        }
+    }
+    fn kv_str(&mut self, key: Option<&str>, val: &str) {
+        if !self.dropped(key) {
+            self.inner.kv_str(key, val);
+        }
+    }
+    fn kv_fmt(&mut self, key: Option<&str>, val: &std::fmt::Arguments<'_>) {
+        if !self.dropped(key) {
+            self.inner.kv_fmt(key, val);
+        }
+    }
+    fn kv_map(&mut self, key: Option<&str>) {
+        if self.start(key) {
+            self.inner.kv_map(key);
+        }
+    }
+    fn kv_mapend(&mut self, key: Option<&str>) {
+        if self.end() {
+            self.inner.kv_mapend(key);
+        }
+    }
+    fn kv_arr(&mut self, key: Option<&str>) {
+        if self.start(key) {
+            self.inner.kv_arr(key);
+        }
+    }
+    fn kv_arrend(&mut self, key: Option<&str>) {
+        if self.end() {
+            self.inner.kv_arrend(key);
+        }
+    }
+}
+
+fn setup() -> (Stakker, Rc<Cell<String>>) {
+    setup_keeping(&[])
+}
+
+// As `setup`, but keeping the given `FEATURE_KEYS` in the output
+fn setup_keeping(keep: &'static [&'static str]) -> (Stakker, Rc<Cell<String>>) {
+    let mut stakker = Stakker::new(Instant::now());
+    let out = Rc::new(Cell::new(String::new()));
+    let out2 = out.clone();
+    stakker.set_logger(
+        LogFilter::all(&[LogLevel::Trace, LogLevel::Audit, LogLevel::Open]),
+        strip_features(keep, move |_, r| {
+            out2.set(format!(
+                "{} #{} {} {}",
+                r.level,
+                r.id,
+                r.fmt,
+                KvSingleLine::braced(r.kvscan)
+            ));
+        }),
+    );
+    (stakker, out)
+}
+
+#[test]
+fn target() {
+    if !max_level_allows(LogLevel::Info) {
+        return;
+    }
+    let mut stakker = Stakker::new(Instant::now());
+    let out = Rc::new(Cell::new(String::new()));
+    let out2 = out.clone();
+    stakker.set_logger(LogFilter::all(&[LogLevel::Trace]), move |_, r| {
+        out2.set(r.target.to_string());
+    });
+    let s = &mut stakker;
+    info!([s], "Default");
+    let expected = if cfg!(feature = "target-module-path") {
+        "stakker_log::test"
+    } else {
+        ""
+    };
+    assert_eq!(out.take(), expected);
+    info!([s], target: "net", "Explicit");
+    assert_eq!(out.take(), "net");
+    info!([s], target: "", "Explicit empty");
+    assert_eq!(out.take(), "");
+}
+
+#[test]
+fn actor_target() {
+    if !max_level_allows(LogLevel::Info) {
+        return;
+    }
+    struct Worker;
+    impl Worker {
+        fn init(cx: CX![]) -> Option<Self> {
+            info!([cx], "Started");
+            info!([cx], target: "net", "Explicit");
+            Some(Self)
+        }
+    }
+    let now = Instant::now();
+    let mut stakker = Stakker::new(now);
+    let out = Rc::new(Cell::new(Vec::new()));
+    let out2 = out.clone();
+    stakker.set_logger(LogFilter::all(&[LogLevel::Trace]), move |_, r| {
+        let mut targets = out2.take();
+        targets.push(r.target.to_string());
+        out2.set(targets);
+    });
+    let s = &mut stakker;
+    let worker = actor!(s, Worker::init(), ret_nop!());
+    s.run(now, false);
+    info!([worker, s], "Against actor");
+    info!([s], "Core");
+    let (actor_type, default) = (
+        std::any::type_name::<Worker>(),
+        if cfg!(feature = "target-module-path") {
+            "stakker_log::test"
+        } else {
+            ""
+        },
+    );
+    let expected = if cfg!(feature = "target-actor-type") {
+        [actor_type, "net", actor_type, default]
+    } else {
+        [default, "net", default, default]
+    };
+    assert_eq!(out.take(), expected);
+}
+
+#[test]
+fn build_info() {
+    if !max_level_allows(LogLevel::Info) {
+        return;
+    }
+    let (mut stakker, out) = setup();
+    let s = &mut stakker;
+    let profile = if cfg!(debug_assertions) {
+        "debug"
+    } else {
+        "release"
+    };
+    // Neither variable is set when building the tests
+    log_build_info!([s], git_hash_var: "STAKKER_LOG_TEST_UNSET");
+    assert_eq!(
+        out.take(),
+        format!(
+            "INFO #0 Build info {{crate_version={} rustc_version git_hash profile={}}}",
+            env!("CARGO_PKG_VERSION"),
+            profile
+        )
+    );
+}
+
+#[test]
+fn audit_msg() {
+    let (mut stakker, out) = setup();
+    let s = &mut stakker;
+    let port = 80;
+    audit_msg!([s], TcpConnectFailure);
+    assert_eq!(out.take(), "AUDIT #0 TcpConnectFailure {}");
+    audit_msg!([s], TcpConnectFailure, port, "key": "a,b",);
+    assert_eq!(out.take(), "AUDIT #0 TcpConnectFailure {port=80 key=a,b}");
+    audit_msg!([s], TcpConnectFailure, "Retrying");
+    assert_eq!(out.take(), "AUDIT #0 TcpConnectFailure {note=Retrying}");
+    audit_msg!([s], ("Tag".to_string()), port, %port, "Retry {} of {}", 1, 3);
+    assert_eq!(
+        out.take(),
+        "AUDIT #0 Tag {port=80 port=80 note=\"Retry 1 of 3\"}"
+    );
+    audit_msg!([s], "Literal", a: "x", "Note",);
+    assert_eq!(out.take(), "AUDIT #0 Literal {a=x note=Note}");
+}
+
+#[test]
+fn both_modifier() {
+    if !max_level_allows(LogLevel::Info) {
+        return;
+    }
+    let (mut stakker, out) = setup();
+    let s = &mut stakker;
+    let peer = String::from("hello");
+    info!([s], both!(name, peer, len), "Test");
+    assert_eq!(out.take(), "INFO #0 Test {name=hello name_len=5}");
+}
+
+#[test]
+fn debug_literal_key() {
+    if !max_level_allows(LogLevel::Info) {
+        return;
+    }
+    let (mut stakker, out) = setup();
+    let s = &mut stakker;
+    let peer = Some(3);
+    // Must expand to a single expression, like the other modifiers
+    #[allow(clippy::match_single_binding)]
+    match peer {
+        _ => info!([s], "peer-id": ?peer, "Test"),
+    }
+    assert_eq!(out.take(), "INFO #0 Test {peer-id=Some(3)}");
+}
+
+#[test]
+fn flat_modifier() {
+    if !max_level_allows(LogLevel::Info) {
+        return;
+    }
+    let (mut stakker, out) = setup();
+    let s = &mut stakker;
+    let mut headers = std::collections::BTreeMap::new();
+    headers.insert("host".to_string(), "example.com".to_string());
+    headers.insert("accept".to_string(), "*/*".to_string());
+    let status = 200;
+    info!([s], status, flat!(headers), path: "/", "Test");
+    assert_eq!(
+        out.take(),
+        "INFO #0 Test {status=200 accept=*/* host=example.com path=/}"
+    );
+    let empty = HashMap::<&str, u32>::new();
+    info!([s], flat!(empty), "Test");
+    assert_eq!(out.take(), "INFO #0 Test {}");
+}
+
+#[test]
+fn secret_modifier() {
+    if !max_level_allows(LogLevel::Info) {
+        return;
+    }
+    let (mut stakker, out) = setup();
+    let s = &mut stakker;
+    let token = "abc123";
+    let req = (7, "xyz");
+    info!([s], secret!(token), pin: secret!(req.0), "key": secret!(req.1), "Test");
+    assert_eq!(out.take(), "INFO #0 Test {token=abc123 pin=7 key=xyz}");
+}
+
+#[test]
+fn val() {
+    let (mut stakker, out) = setup();
+    let s = &mut stakker;
+    let base = 20;
+    let line = line!() + 1;
+    let total = trace_val!([s], base + 1) * 2;
+    assert_eq!(total, 42);
+    // Only the logging is compiled out by the `max_level_*` features
+    let traced = max_level_allows(LogLevel::Trace);
+    if traced {
+        assert_eq!(
+            out.take(),
+            format!("TRACE #0 {}:{} {{base\\20+\\201=21}}", file!(), line)
+        );
+    }
+    // Owned values are moved through
+    let name = String::from("worker");
+    let moved: String = trace_val!([s], name);
+    assert_eq!(moved, "worker");
+    if traced {
+        assert!(out.take().ends_with("{name=worker}"));
+    }
+}
+
+#[test]
+fn hex_modifier() {
+    if !max_level_allows(LogLevel::Info) {
+        return;
+    }
+    let (mut stakker, out) = setup();
+    let s = &mut stakker;
+    let payload = vec![0xca_u8, 0xfe];
+    info!([s], data: hex!(payload), "bytes": hex!([1_u8, 2]), "Test");
+    assert_eq!(out.take(), "INFO #0 Test {data=cafe bytes=0102}");
+}
+
+#[test]
+fn snapshot_dump() {
+    if !max_level_allows(LogLevel::Info) {
+        return;
+    }
+    let (mut stakker, out) = setup();
+    let s = &mut stakker;
+    let count = Rc::new(Cell::new(1_u64));
+    let count2 = count.clone();
+    let _g1 = register_snapshot("a", move |lv| lv.kv_u64(Some("count"), count2.get()));
+    let g2 = register_snapshot("b", |lv| lv.kv_str(Some("state"), "idle"));
+    count.set(2);
+    dump_all!([s]);
+    assert_eq!(
+        out.take(),
+        "INFO #0 State snapshot {snapshot{a{count=2} b{state=idle}}}"
+    );
+    drop(g2);
+    dump_all!([s]);
+    assert_eq!(out.take(), "INFO #0 State snapshot {snapshot{a{count=2}}}");
+}
+
+#[test]
+fn context_scope() {
+    if !max_level_allows(LogLevel::Info) {
+        return;
+    }
+    let (mut stakker, out) = setup();
+    let s = &mut stakker;
+    LogContext::scope(
+        |lv| lv.kv_u64(Some("request_id"), 1234),
+        || {
+            info!([s], a: 1, "Inside");
+            assert_eq!(out.take(), "INFO #0 Inside {request_id=1234 a=1}");
+            let _guard = LogContext::enter(|lv| lv.kv_str(Some("user"), "fred"));
+            info!([s], "Nested");
+            assert_eq!(out.take(), "INFO #0 Nested {request_id=1234 user=fred}");
+        },
+    );
+    info!([s], a: 1, "Outside");
+    assert_eq!(out.take(), "INFO #0 Outside {a=1}");
+
+    // Context values may use the context themselves
+    LogContext::scope(
+        |lv| {
+            let _guard = LogContext::enter(|lv| lv.kv_u64(Some("ignored"), 1));
+            lv.kv_str(Some("user"), "fred");
+        },
+        || {
+            info!([s], "Reentrant");
+            assert_eq!(out.take(), "INFO #0 Reentrant {user=fred}");
+        },
+    );
+}
+
+#[test]
+fn on_change() {
+    if !max_level_allows(LogLevel::Info) {
+        return;
+    }
+    let (mut stakker, out) = setup();
+    let s = &mut stakker;
+    let mut log = |v: u32| {
+        log_on_change!([s], Info, "temp", v);
+        out.take()
+    };
+    assert_eq!(log(20), "INFO #0 Value changed {temp=20}");
+    assert_eq!(log(20), "");
+    assert_eq!(log(20), "");
+    assert_eq!(log(21), "INFO #0 Value changed {temp=21}");
+    assert_eq!(log(20), "INFO #0 Value changed {temp=20}");
+}
+
+#[test]
+fn virtual_time() {
+    if !max_level_allows(LogLevel::Info) {
+        return;
+    }
+    let (mut stakker, out) = setup();
+    let now = stakker.now();
+    stakker.run(now + Duration::from_millis(1500), false);
+    let mut lcx = LogCx::new(0, &mut stakker);
+    let now = lcx.now();
+    info!([lcx], now, "Tick");
+    assert_eq!(out.take(), "INFO #0 Tick {now=1.5}");
+}
+
+#[test]
+fn log_cx_from_source() {
+    if !max_level_allows(LogLevel::Info) {
+        return;
+    }
+    struct Worker;
+    impl Worker {
+        fn init(_: CX![]) -> Option<Self> {
+            Some(Self)
+        }
+    }
+    let (mut stakker, out) = setup();
+    let s = &mut stakker;
+    let owner = actor!(s, Worker::init(), ret_nop!());
+    let worker: Actor<Worker> = owner.clone();
+    let id = worker.access_log_id();
+    assert_ne!(id, 0);
+    let mut lcx = LogCx::from_source(&worker, s);
+    info!([lcx], "Background");
+    assert_eq!(out.take(), format!("INFO #{} Background {{}}", id));
+}
+
+#[test]
+fn child_span() {
+    if !max_level_allows(LogLevel::Info) {
+        return;
+    }
+    let (mut stakker, out) = setup();
+    let mut lcx = LogCx::new(0, &mut stakker);
+    info!([lcx], "Before");
+    let mut lines = vec![out.take()];
+    let id = lcx.with_child("request", |child| {
+        lines.push(out.take());
+        info!([child], "Inside");
+        lines.push(out.take());
+        child.access_log_id()
+    });
+    lines.push(out.take());
+    info!([lcx], "After");
+    lines.push(out.take());
+    assert_ne!(id, 0);
+    assert_eq!(
+        lines,
+        [
+            "INFO #0 Before {}".to_string(),
+            format!("OPEN #{} request {{}}", id),
+            format!("INFO #{} Inside {{}}", id),
+            format!("CLOSE #{}  {{}}", id),
+            "INFO #0 After {}".to_string(),
+        ]
+    );
+}
+
+#[test]
+fn custom_source() {
+    if !max_level_allows(LogLevel::Info) {
+        return;
+    }
+    // Doesn't have inherent `access_*` methods, so the macros must
+    // find the trait methods
+    struct Request<'a> {
+        id: LogID,
+        core: &'a mut Core,
+    }
+    impl crate::LogIdSource for Request<'_> {
+        fn access_log_id(&self) -> LogID {
+            self.id
+        }
+    }
+    impl crate::CoreSource for Request<'_> {
+        fn access_core(&mut self) -> &mut Core {
+            self.core
+        }
+    }
+    let (mut stakker, out) = setup();
+    let mut req = Request {
+        id: 42,
+        core: &mut stakker,
+    };
+    info!([req], path: "/", "Request");
+    assert_eq!(out.take(), "INFO #42 Request {path=/}");
+    warn_every!([req], every: Duration::from_secs(1), "Slow");
+    assert_eq!(out.take(), "WARN #42 Slow {}");
+}
+
+#[test]
+fn every() {
+    if !max_level_allows(LogLevel::Warn) {
+        return;
+    }
+    fn spam(s: &mut Stakker, n: u32) {
+        warn_every!([s], every: Duration::from_secs(1), n, "Spam");
+    }
+    let (mut stakker, out) = setup();
+    let t0 = stakker.now();
+    let mut logged = Vec::new();
+    for (n, ms) in [0, 10, 999, 1000, 1500, 2100, 2200].iter().enumerate() {
+        stakker.run(t0 + Duration::from_millis(*ms), false);
+        spam(&mut stakker, n as u32);
+        logged.push(out.take());
+    }
+    assert_eq!(
+        logged,
+        [
+            "WARN #0 Spam {n=0}",
+            "",
+            "",
+            "WARN #0 Spam {n=3}",
+            "",
+            "WARN #0 Spam {n=5}",
+            "",
+        ]
+    );
+}
+
+#[test]
+fn once() {
+    if !max_level_allows(LogLevel::Info) {
+        return;
+    }
+    fn deprecated(s: &mut Stakker) {
+        log_once!([s], Info, "Deprecated");
+    }
+    let (mut stakker, out) = setup();
+    let mut count = 0;
+    for _ in 0..5 {
+        deprecated(&mut stakker);
+        if !out.take().is_empty() {
+            count += 1;
+        }
+    }
+    assert_eq!(count, 1);
+}
+
+#[cfg(feature = "max_level_info")]
+#[test]
+fn max_level() {
+    let (mut stakker, out) = setup();
+    let s = &mut stakker;
+    let count = Cell::new(0);
+    let eval = || {
+        count.set(count.get() + 1);
+        count.get()
+    };
+    let only_logged = 5;
+    debug!([s], n: eval(), ?only_logged, "Debug {}", eval());
+    crate::trace!([s], n: eval(), "Trace {}", eval());
+    assert_eq!(count.get(), 0);
+    assert_eq!(out.take(), "");
+    info!([s], n: eval(), "Info");
+    assert_eq!(count.get(), 1);
+    assert_eq!(out.take(), "INFO #0 Info {n=1}");
+}
+
+#[cfg(feature = "recent-messages")]
+#[test]
+fn recent_messages() {
+    let (mut stakker, out) = setup_keeping(&["recent"]);
+    let s = &mut stakker;
+    crate::set_recent_capacity(2);
+    info!([s], "One");
+    debug!([s], n: 2, "Two");
+    crate::warn!([s], "Three {}", 3);
+    out.take();
+    error!([s], code: 5, "Failed");
+    assert_eq!(
+        out.take(),
+        "ERROR #0 Failed {code=5 recent[\"DEBUG Two\" \"WARN Three 3\"]}"
+    );
+    crate::set_recent_capacity(8);
+}
+
+#[cfg(feature = "location")]
+#[test]
+fn location() {
+    let (mut stakker, out) = setup_keeping(&["loc"]);
+    let s = &mut stakker;
+    let line = line!() + 1;
+    info!([s], target: "net", port: 80, "Test");
+    assert_eq!(
+        out.take(),
+        format!("INFO #0 Test {{loc={}:{} port=80}}", file!(), line)
+    );
+
+    // A `loc` given in the call replaces the call site
+    info!([s], loc: "elsewhere", port: 80, "Test");
+    assert_eq!(out.take(), "INFO #0 Test {loc=elsewhere port=80}");
+    info!([s], "loc": 5, "Test");
+    assert_eq!(out.take(), "INFO #0 Test {loc=5}");
+
+    // Any other `loc` is renamed to avoid a clash
+    let mut extra = std::collections::BTreeMap::new();
+    extra.insert("loc", "flat");
+    let line = line!() + 1;
+    info!([s], flat!(extra), "Test");
+    assert_eq!(
+        out.take(),
+        format!("INFO #0 Test {{loc={}:{} kv.loc=flat}}", file!(), line)
+    );
+    let _guard = LogContext::enter(|lv| lv.kv_str(Some("loc"), "context"));
+    info!([s], loc: "elsewhere", "Test");
+    assert_eq!(out.take(), "INFO #0 Test {kv.loc=context loc=elsewhere}");
+}
+
+#[cfg(feature = "template-hash")]
+#[test]
+fn template_hash() {
+    let (mut stakker, out) = setup_keeping(&["template_hash"]);
+    let s = &mut stakker;
+    let hash = |out: &str| {
+        out.split("template_hash=")
+            .nth(1)
+            .map(|h| h.trim_end_matches('}').to_string())
+    };
+    info!([s], "Connected to {}", "alpha");
+    let h1 = hash(&out.take()).unwrap();
+    info!([s], n: 2, "Connected to {}", "beta");
+    let h2 = hash(&out.take()).unwrap();
+    info!([s], "Disconnected from {}", "alpha");
+    let h3 = hash(&out.take()).unwrap();
+    assert_eq!(h1.len(), 16);
+    assert_eq!(h1, h2);
+    assert_ne!(h1, h3);
+    assert_eq!(
+        h1,
+        format!(
+            "{:016x}",
+            crate::macro_support::template_hash("Connected to {}")
+        )
+    );
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn assert_logs() {
+    use crate::Value;
+    crate::assert_logs!(
+        |s| {
+            info!([s], "Starting");
+            error!([s], code: 5_u64, "Failed");
+        },
+        |records| {
+            assert_eq!(records.len(), 2);
+            assert_eq!(records[1].level, LogLevel::Error);
+            assert_eq!(records[1].msg, "Failed");
+            assert_eq!(records[1].get("code"), Some(&Value::U64(5)));
+        }
+    );
+}
+
+#[cfg(feature = "render-timing")]
+#[test]
+fn render_timing() {
+    let (mut stakker, out) = setup_keeping(&["_render_ns"]);
+    let s = &mut stakker;
+    info!([s], a: 1, "Timed");
+    let out = out.take();
+    let ns = out
+        .strip_prefix("INFO #0 Timed {a=1 _render_ns=")
+        .and_then(|rest| rest.strip_suffix('}'))
+        .unwrap_or_else(|| panic!("Unexpected output: {}", out));
+    ns.parse::<u64>().unwrap();
+}
+
+#[test]
+fn lazy_message() {
+    if !max_level_allows(LogLevel::Info) {
+        return;
+    }
+    let mut stakker = Stakker::new(Instant::now());
+    stakker.set_logger(LogFilter::all(&[LogLevel::Info]), |_, r| {
+        let _ = r.fmt.to_string();
+    });
+    let s = &mut stakker;
+    let calls = Cell::new(0);
+    let expensive = || {
+        calls.set(calls.get() + 1);
+        "expensive"
+    };
+    debug!([s], "Message: {}", expensive());
+    assert_eq!(calls.get(), 0);
+    info!([s], "Message: {}", expensive());
+    assert_eq!(calls.get(), 1);
+}
@@ -0,0 +1,33 @@
+//! Capturing of log records for tests, enabled by the `test-util`
+//! feature
+
+use crate::RecordView;
+use stakker::{LogFilter, LogLevel, Stakker};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Instant;
+
+/// Run a closure against a temporary `Stakker`, capturing the records
+/// it logs
+///
+/// A new `Stakker` is created with a logger that accepts all levels,
+/// including audit and span records, and `body` is called with it.
+/// The records logged are returned as [`RecordView`] values, in
+/// order.  Since only one `Stakker` may exist on a thread at a time,
+/// this must not be called whilst another `Stakker` exists on the
+/// same thread.  See also [`assert_logs!`].
+///
+/// [`RecordView`]: struct.RecordView.html
+/// [`assert_logs!`]: macro.assert_logs.html
+pub fn capture_logs(body: impl FnOnce(&mut Stakker)) -> Vec<RecordView> {
+    let mut stakker = Stakker::new(Instant::now());
+    let records = Rc::new(RefCell::new(Vec::new()));
+    let records2 = records.clone();
+    stakker.set_logger(
+        LogFilter::all(&[LogLevel::Trace, LogLevel::Audit, LogLevel::Open]),
+        move |_, r| records2.borrow_mut().push(RecordView::new(r)),
+    );
+    body(&mut stakker);
+    drop(stakker);
+    records.take()
+}
@@ -0,0 +1,292 @@
+//! In-memory tree of key-value data, for output formats which can't
+//! be generated in a single pass of the visitor
+
+use crate::adapters::RevealSecrets;
+use crate::visit::SECRET_KEY;
+use crate::Visitable;
+use stakker::LogVisitor;
+use std::fmt::Arguments;
+
+/// A value captured from a `LogVisitor` call
+///
+/// This is an owned copy of a key-value value, as collected for a
+/// [`RecordView`].  Formatted values are captured as strings.  Maps
+/// are kept as a list of pairs so that the original order is
+/// preserved.  A `Value` can be logged again, since it implements
+/// [`Visitable`].
+///
+/// [`RecordView`]: struct.RecordView.html
+/// [`Visitable`]: trait.Visitable.html
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    /// From `kv_u64`
+    U64(u64),
+    /// From `kv_i64`
+    I64(i64),
+    /// From `kv_f64`
+    F64(f64),
+    /// From `kv_bool`
+    Bool(bool),
+    /// From `kv_null`
+    Null,
+    /// From `kv_str` or `kv_fmt`
+    Str(String),
+    /// From `kv_map` through to `kv_mapend`
+    Map(Vec<(String, Value)>),
+    /// From `kv_arr` through to `kv_arrend`
+    Arr(Vec<Value>),
+    /// From a value marked with [`Secret`], which is output again
+    /// with the same marker
+    ///
+    /// [`Secret`]: struct.Secret.html
+    Secret(Box<Value>),
+}
+
+impl Visitable for Value {
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        match self {
+            Value::U64(v) => output.kv_u64(key, *v),
+            Value::I64(v) => output.kv_i64(key, *v),
+            Value::F64(v) => output.kv_f64(key, *v),
+            Value::Bool(v) => output.kv_bool(key, *v),
+            Value::Null => output.kv_null(key),
+            Value::Str(v) => output.kv_str(key, v),
+            Value::Map(pairs) => {
+                output.kv_map(key);
+                for (k, v) in pairs {
+                    v.visit(Some(k), output);
+                }
+                output.kv_mapend(key);
+            }
+            Value::Arr(items) => {
+                output.kv_arr(key);
+                for v in items {
+                    v.visit(None, output);
+                }
+                output.kv_arrend(key);
+            }
+            Value::Secret(v) => {
+                output.kv_map(key);
+                v.visit(Some(SECRET_KEY), output);
+                output.kv_mapend(key);
+            }
+        }
+    }
+}
+
+/// Collect all the key-value pairs output by `kvscan`.  Missing keys
+/// within maps are taken as `""`.
+pub(crate) fn collect(kvscan: &dyn Fn(&mut dyn LogVisitor)) -> Vec<(String, Value)> {
+    let mut collector = Collector {
+        stack: vec![(String::new(), Value::Map(Vec::new()))],
+    };
+    kvscan(&mut collector);
+    // Close any containers left open by a badly-behaved visitable
+    while collector.stack.len() > 1 {
+        collector.end();
+    }
+    match collector.stack.pop() {
+        Some((_, Value::Map(pairs))) => pairs,
+        _ => Vec::new(),
+    }
+}
+
+/// Collect all the key-value pairs output by `kvscan`, with values
+/// marked by `Secret` collected as plain values.  This is for
+/// renderers, which show secrets unmasked.
+pub(crate) fn collect_revealed(kvscan: &dyn Fn(&mut dyn LogVisitor)) -> Vec<(String, Value)> {
+    collect(&|lv| kvscan(&mut RevealSecrets::new(lv)))
+}
+
+/// Collect the value output by a single `Visitable`
+pub(crate) fn collect_value<V: Visitable + ?Sized>(val: &V) -> Value {
+    collect(&|lv| val.visit(Some(""), lv))
+        .pop()
+        .map_or(Value::Null, |(_, v)| v)
+}
+
+/// Compute a JSON merge patch (RFC 7396) which transforms `old` into
+/// `new`.  If both are maps, the patch is a map with null for each
+/// key deleted, the new value for each key added, and a nested patch
+/// for each key changed.  Otherwise the patch is `new` itself.
+pub(crate) fn merge_patch(old: &Value, new: &Value) -> Value {
+    match (old, new) {
+        (Value::Map(old), Value::Map(new)) => {
+            let mut patch = Vec::new();
+            for (key, oval) in old {
+                match new.iter().find(|(k, _)| k == key) {
+                    None => patch.push((key.clone(), Value::Null)),
+                    Some((_, nval)) if nval != oval => {
+                        patch.push((key.clone(), merge_patch(oval, nval)))
+                    }
+                    Some(_) => (),
+                }
+            }
+            for (key, nval) in new {
+                if !old.iter().any(|(k, _)| k == key) {
+                    patch.push((key.clone(), nval.clone()));
+                }
+            }
+            Value::Map(patch)
+        }
+        _ => new.clone(),
+    }
+}
+
+struct Collector {
+    // Containers currently open, with the key each will be added with
+    stack: Vec<(String, Value)>,
+}
+
+impl Collector {
+    fn push(&mut self, key: Option<&str>, val: Value) {
+        if let Some((_, top)) = self.stack.last_mut() {
+            match top {
+                Value::Map(pairs) => pairs.push((key.unwrap_or("").to_string(), val)),
+                Value::Arr(items) => items.push(val),
+                _ => (),
+            }
+        }
+    }
+    fn start(&mut self, key: Option<&str>, val: Value) {
+        self.stack.push((key.unwrap_or("").to_string(), val));
+    }
+    fn end(&mut self) {
+        if self.stack.len() > 1 {
+            if let Some((key, val)) = self.stack.pop() {
+                let val = match val {
+                    Value::Map(mut pairs) if pairs.len() == 1 && pairs[0].0 == SECRET_KEY => {
+                        Value::Secret(Box::new(pairs.remove(0).1))
+                    }
+                    val => val,
+                };
+                self.push(Some(&key), val);
+            }
+        }
+    }
+}
+
+impl LogVisitor for Collector {
+    fn kv_u64(&mut self, key: Option<&str>, val: u64) {
+        self.push(key, Value::U64(val));
+    }
+    fn kv_i64(&mut self, key: Option<&str>, val: i64) {
+        self.push(key, Value::I64(val));
+    }
+    fn kv_f64(&mut self, key: Option<&str>, val: f64) {
+        self.push(key, Value::F64(val));
+    }
+    fn kv_bool(&mut self, key: Option<&str>, val: bool) {
+        self.push(key, Value::Bool(val));
+    }
+    fn kv_null(&mut self, key: Option<&str>) {
+        self.push(key, Value::Null);
+    }
+    fn kv_str(&mut self, key: Option<&str>, val: &str) {
+        self.push(key, Value::Str(val.to_string()));
+    }
+    fn kv_fmt(&mut self, key: Option<&str>, val: &Arguments<'_>) {
+        self.push(key, Value::Str(val.to_string()));
+    }
+    fn kv_map(&mut self, key: Option<&str>) {
+        self.start(key, Value::Map(Vec::new()));
+    }
+    fn kv_mapend(&mut self, _: Option<&str>) {
+        self.end();
+    }
+    fn kv_arr(&mut self, key: Option<&str>) {
+        self.start(key, Value::Arr(Vec::new()));
+    }
+    fn kv_arrend(&mut self, _: Option<&str>) {
+        self.end();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{collect, collect_revealed, collect_value, merge_patch, Value};
+    use crate::{Secret, Visitable};
+
+    #[test]
+    fn test() {
+        let pairs = collect(&|lv| {
+            lv.kv_u64(Some("a"), 1);
+            lv.kv_map(Some("m"));
+            lv.kv_arr(Some("l"));
+            lv.kv_null(None);
+            lv.kv_fmt(None, &format_args!("{}", 2));
+            lv.kv_arrend(Some("l"));
+            lv.kv_mapend(Some("m"));
+            lv.kv_map(Some("open"));
+        });
+        assert_eq!(
+            pairs,
+            vec![
+                ("a".to_string(), Value::U64(1)),
+                (
+                    "m".to_string(),
+                    Value::Map(vec![(
+                        "l".to_string(),
+                        Value::Arr(vec![Value::Null, Value::Str("2".to_string())])
+                    )])
+                ),
+                ("open".to_string(), Value::Map(Vec::new())),
+            ]
+        );
+    }
+
+    #[test]
+    fn secret() {
+        let pairs = collect(&|lv| {
+            Secret(&"abc").visit(Some("token"), lv);
+            lv.kv_map(Some("m"));
+            lv.kv_str(Some("$secret"), "x");
+            lv.kv_u64(Some("y"), 1);
+            lv.kv_mapend(Some("m"));
+        });
+        let token = Value::Secret(Box::new(Value::Str("abc".to_string())));
+        assert_eq!(pairs[0], ("token".to_string(), token.clone()));
+        // Only a single-entry map is a `Secret` wrapper
+        assert!(matches!(pairs[1].1, Value::Map(_)));
+        // The marker is output again when visited
+        assert_eq!(collect_value(&token), token);
+        assert_eq!(
+            collect_revealed(&|lv| token.visit(Some("token"), lv)),
+            vec![("token".to_string(), Value::Str("abc".to_string()))]
+        );
+    }
+
+    #[test]
+    fn patch() {
+        let m = |pairs: &[(&str, Value)]| {
+            Value::Map(
+                pairs
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.clone()))
+                    .collect(),
+            )
+        };
+        let old = m(&[
+            ("a", m(&[("x", Value::U64(1)), ("y", Value::U64(2))])),
+            ("b", Value::Bool(true)),
+            ("l", Value::Arr(vec![Value::U64(1)])),
+        ]);
+        let new = m(&[
+            ("a", m(&[("x", Value::U64(1)), ("y", Value::U64(5))])),
+            ("l", Value::Arr(vec![Value::U64(1), Value::U64(2)])),
+            ("c", Value::Null),
+        ]);
+        assert_eq!(
+            merge_patch(&old, &new),
+            m(&[
+                ("a", m(&[("y", Value::U64(5))])),
+                ("b", Value::Null),
+                ("l", Value::Arr(vec![Value::U64(1), Value::U64(2)])),
+                ("c", Value::Null),
+            ])
+        );
+        assert_eq!(merge_patch(&old, &old), m(&[]));
+        assert_eq!(merge_patch(&old, &Value::U64(3)), Value::U64(3));
+        assert_eq!(collect_value(&7_u8), Value::U64(7));
+    }
+}
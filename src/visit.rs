@@ -1,6 +1,29 @@
-use stakker::LogVisitor;
+use crate::tree;
+use stakker::{Core, LogVisitor};
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::borrow::Cow;
 use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet, LinkedList, VecDeque};
+use std::error::Error;
+use std::fmt;
 use std::fmt::Arguments;
+use std::fmt::Write as _;
+use std::hash::Hash;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::num::{
+    NonZeroI128, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI8, NonZeroIsize, NonZeroU128,
+    NonZeroU16, NonZeroU32, NonZeroU64, NonZeroU8, NonZeroUsize, ParseFloatError, ParseIntError,
+    TryFromIntError,
+};
+use std::ops::{Range, RangeInclusive};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str::Utf8Error;
+use std::sync::atomic::{
+    AtomicBool, AtomicI16, AtomicI32, AtomicI64, AtomicI8, AtomicIsize, AtomicU16, AtomicU32,
+    AtomicU64, AtomicU8, AtomicUsize, Ordering,
+};
+use std::sync::{Arc, TryLockError, TryLockResult};
+use std::time::{Duration, Instant, SystemTime, TryFromFloatSecsError, UNIX_EPOCH};
 
 /// This trait allows a `stakker::LogVisitor` to visit various
 /// fundamental Rust types and collections.
@@ -10,14 +33,26 @@ use std::fmt::Arguments;
 /// methods available on the `stakker::LogVisitor`.  Most fundamental
 /// Rust types, plus map-like and array-like collections have a
 /// straightforward mapping.  Integer types larger than 64-bits are
-/// output as strings.  `Option` is handled by including the key-value
-/// pair for `Some`, or omitting it for `None`.
+/// output as strings.  `Option` is output as the inner value for
+/// `Some`, or as null for `None`.  Common standard library error
+/// types such as `ParseIntError` are output as a map containing
+/// `kind` and `message`, where `kind` is the error's kind if it has
+/// one (e.g. `InvalidDigit`), or else the type name.  The `std::sync::atomic` types are output as
+/// their current value, read with a relaxed load.
 ///
 /// For other types which must be formatted as a string, construct a
 /// `std::fmt::Arguments` instance using `format_args!` first, and
 /// then visit that.  For your own complex types which need structured
 /// output, you should write your own [`Visitable`] implementation.
 ///
+/// There is no blanket implementation for all `Display` types, even
+/// behind a feature flag.  It would overlap with this crate's
+/// implementations for numbers, strings and collections, which Rust
+/// doesn't allow without the unstable specialization feature.
+/// Instead, use the `%` shortcut in the logging macros, which works
+/// for any `Display` type, or wrap the value in [`AsDisplay`], which
+/// also works for the elements of a collection.
+///
 /// For example:
 ///
 /// ```notest
@@ -27,18 +62,58 @@ use std::fmt::Arguments;
 /// format_args!("{:?}", var4).visit("key4", visitor); // Debug formatting
 /// ```
 ///
+/// [`AsDisplay`]: struct.AsDisplay.html
 /// [`Visitable`]: trait.Visitable.html
 pub trait Visitable {
     fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor);
 }
 
-// Option handling
+// Option handling: `None` is output as null.  Since `Some` outputs
+// the inner value directly, nested options collapse naturally:
+// `Some(Some(v))` outputs `v`, and a `None` at any depth outputs a
+// single null.
 impl<T: Visitable> Visitable for Option<T> {
     #[inline]
     fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
-        if let Some(ref v) = self {
-            v.visit(key, output);
+        match self {
+            Some(v) => v.visit(key, output),
+            None => output.kv_null(key),
+        }
+    }
+}
+
+// Smart pointers are output the same as the value they point to.
+// Since `V` may be unsized, this includes trait objects such as
+// `Box<dyn Visitable>` and slices such as `Rc<[T]>`.  (`Rc<str>` is
+// not covered, because only `&str` implements `Visitable`.)
+macro_rules! visit_deref {
+    ($fr:ident) => {
+        impl<V: Visitable + ?Sized> Visitable for $fr<V> {
+            #[inline]
+            fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+                (**self).visit(key, output)
+            }
+        }
+    };
+}
+
+visit_deref!(Box);
+visit_deref!(Rc);
+visit_deref!(Arc);
+
+// Result handling: output as a single-key map, `{"Ok": value}` or
+// `{"Err": error}`.  This was preferred over a flat pair of `ok` and
+// `err` keys because it can't be confused with a map that happens to
+// contain those keys, and it keeps the value's own structure intact.
+impl<T: Visitable, E: Visitable> Visitable for Result<T, E> {
+    #[inline]
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        output.kv_map(key);
+        match self {
+            Ok(v) => v.visit(Some("Ok"), output),
+            Err(e) => e.visit(Some("Err"), output),
         }
+        output.kv_mapend(key);
     }
 }
 
@@ -46,7 +121,7 @@ impl<T: Visitable> Visitable for Option<T> {
 impl Visitable for &str {
     #[inline]
     fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
-        output.kv_str(key, *self);
+        output.kv_str(key, self);
     }
 }
 
@@ -57,6 +132,13 @@ impl Visitable for String {
     }
 }
 
+impl<'a> Visitable for Cow<'a, str> {
+    #[inline]
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        output.kv_str(key, self);
+    }
+}
+
 // Null or unit handling
 impl Visitable for () {
     #[inline]
@@ -73,6 +155,24 @@ impl<'a> Visitable for Arguments<'a> {
     }
 }
 
+// Paths are output as strings using `to_string_lossy()`.  This means
+// that on platforms that allow paths which aren't valid Unicode, any
+// invalid sequences are replaced with U+FFFD, so the logged value may
+// not exactly match the bytes of the path.
+impl Visitable for Path {
+    #[inline]
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        output.kv_str(key, &self.to_string_lossy());
+    }
+}
+
+impl Visitable for PathBuf {
+    #[inline]
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        self.as_path().visit(key, output);
+    }
+}
+
 // Copy types and convertible types
 macro_rules! visit_copy_as {
     ($fr:ty, $to:ty, $method:ident) => {
@@ -99,6 +199,58 @@ visit_copy_as!(f32, f64, kv_f64);
 visit_copy_as!(f64, f64, kv_f64);
 visit_copy_as!(bool, bool, kv_bool);
 
+// Non-zero integers are output the same as the plain integer type
+macro_rules! visit_nonzero {
+    ($fr:ty) => {
+        impl $crate::Visitable for $fr {
+            #[inline]
+            fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+                self.get().visit(key, output);
+            }
+        }
+    };
+}
+
+visit_nonzero!(NonZeroU8);
+visit_nonzero!(NonZeroU16);
+visit_nonzero!(NonZeroU32);
+visit_nonzero!(NonZeroU64);
+visit_nonzero!(NonZeroU128);
+visit_nonzero!(NonZeroUsize);
+visit_nonzero!(NonZeroI8);
+visit_nonzero!(NonZeroI16);
+visit_nonzero!(NonZeroI32);
+visit_nonzero!(NonZeroI64);
+visit_nonzero!(NonZeroI128);
+visit_nonzero!(NonZeroIsize);
+
+// Atomics are output as a snapshot of the current value.  A relaxed
+// load is enough for this: logging a value doesn't need to be ordered
+// against any other memory access, and a stronger ordering would add
+// a cost on some platforms just for logging.
+macro_rules! visit_atomic {
+    ($fr:ty) => {
+        impl $crate::Visitable for $fr {
+            #[inline]
+            fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+                self.load(Ordering::Relaxed).visit(key, output);
+            }
+        }
+    };
+}
+
+visit_atomic!(AtomicBool);
+visit_atomic!(AtomicU8);
+visit_atomic!(AtomicU16);
+visit_atomic!(AtomicU32);
+visit_atomic!(AtomicU64);
+visit_atomic!(AtomicUsize);
+visit_atomic!(AtomicI8);
+visit_atomic!(AtomicI16);
+visit_atomic!(AtomicI32);
+visit_atomic!(AtomicI64);
+visit_atomic!(AtomicIsize);
+
 // Types that we have to just format out as a string
 macro_rules! visit_as_display {
     ($fr:ty) => {
@@ -115,6 +267,29 @@ visit_as_display!(char);
 visit_as_display!(u128);
 visit_as_display!(i128);
 
+// Network addresses output as their canonical string form.  These use
+// `kv_str` rather than `kv_fmt` so that renderers see a plain string
+// value.  Socket addresses are output in the familiar flat
+// `host:port` form (`[host]:port` for IPv6) rather than as a map of
+// `ip` and `port`.
+macro_rules! visit_as_string {
+    ($fr:ty) => {
+        impl $crate::Visitable for $fr {
+            #[inline]
+            fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+                output.kv_str(key, &self.to_string());
+            }
+        }
+    };
+}
+
+visit_as_string!(IpAddr);
+visit_as_string!(Ipv4Addr);
+visit_as_string!(Ipv6Addr);
+visit_as_string!(SocketAddr);
+visit_as_string!(SocketAddrV4);
+visit_as_string!(SocketAddrV6);
+
 // Array-like objects
 macro_rules! visit_arr {
     ($t:ident, $fr:ty) => {
@@ -139,6 +314,41 @@ visit_arr!(T, HashSet<T>);
 visit_arr!(T, BTreeSet<T>);
 visit_arr!(T, BinaryHeap<T>);
 
+impl<T: Visitable, const N: usize> Visitable for [T; N] {
+    #[inline]
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        self[..].visit(key, output);
+    }
+}
+
+impl<'a, T: Visitable + Clone> Visitable for Cow<'a, [T]> {
+    #[inline]
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        self[..].visit(key, output);
+    }
+}
+
+/// This trait allows a map-like value to output its entries as
+/// separate key-value pairs, without enclosing them in a map.
+///
+/// This is used by the `flat!(value)` shortcut of the logging macros
+/// to splice entries whose keys are only known at runtime, such as
+/// parsed HTTP headers, into a record alongside its other key-value
+/// pairs.  It is implemented for `HashMap` and `BTreeMap` with
+/// string-like keys.  A `HashMap` outputs its entries in an arbitrary
+/// order, which may differ from run to run, so use a `BTreeMap` if
+/// the order matters, e.g. for comparing log lines.
+pub trait FlattenVisitable {
+    fn visit_flat(&self, output: &mut dyn LogVisitor);
+}
+
+impl<T: FlattenVisitable + ?Sized> FlattenVisitable for &T {
+    #[inline]
+    fn visit_flat(&self, output: &mut dyn LogVisitor) {
+        (**self).visit_flat(output);
+    }
+}
+
 // Map-like objects
 macro_rules! visit_map {
     ($fr:ident) => {
@@ -146,10 +356,17 @@ macro_rules! visit_map {
             #[inline]
             fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
                 output.kv_map(key);
+                self.visit_flat(output);
+                output.kv_mapend(key);
+            }
+        }
+
+        impl<K: AsRef<str>, V: Visitable> FlattenVisitable for $fr<K, V> {
+            #[inline]
+            fn visit_flat(&self, output: &mut dyn LogVisitor) {
                 for (k, v) in self {
                     v.visit(Some(k.as_ref()), output);
                 }
-                output.kv_mapend(key);
             }
         }
     };
@@ -157,3 +374,1635 @@ macro_rules! visit_map {
 
 visit_map!(HashMap);
 visit_map!(BTreeMap);
+
+// `Duration` output as a map of whole seconds (`secs`) and the
+// nanoseconds part (`nanos`), which keeps full precision and allows
+// numeric aggregation downstream.  Use `DurationBoth` for a
+// human-readable form as well.
+impl Visitable for Duration {
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        output.kv_map(key);
+        output.kv_u64(Some("secs"), self.as_secs());
+        output.kv_u64(Some("nanos"), self.subsec_nanos().into());
+        output.kv_mapend(key);
+    }
+}
+
+// Ranges output as a map of their bounds, to keep them typed for
+// filtering downstream, with `inclusive: true` added for an inclusive
+// range to distinguish `0..=9` from `0..9`
+impl<T: Visitable> Visitable for Range<T> {
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        output.kv_map(key);
+        self.start.visit(Some("start"), output);
+        self.end.visit(Some("end"), output);
+        output.kv_mapend(key);
+    }
+}
+
+impl<T: Visitable> Visitable for RangeInclusive<T> {
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        output.kv_map(key);
+        self.start().visit(Some("start"), output);
+        self.end().visit(Some("end"), output);
+        output.kv_bool(Some("inclusive"), true);
+        output.kv_mapend(key);
+    }
+}
+
+// `SystemTime` output as an RFC 3339 UTC timestamp string, e.g.
+// `2023-11-14T22:13:20.5Z`, with the fractional seconds trimmed of
+// trailing zeros and left out if zero.  Times before the epoch are
+// formatted correctly as earlier dates rather than failing.
+impl Visitable for SystemTime {
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        // Signed seconds since the epoch, with non-negative nanos
+        let (secs, nanos) = match self.duration_since(UNIX_EPOCH) {
+            Ok(d) => (d.as_secs() as i64, d.subsec_nanos()),
+            Err(e) => {
+                let d = e.duration();
+                let secs = -(d.as_secs() as i64);
+                match d.subsec_nanos() {
+                    0 => (secs, 0),
+                    n => (secs - 1, 1_000_000_000 - n),
+                }
+            }
+        };
+        let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+        let tod = secs.rem_euclid(86400);
+        let mut out = format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            year,
+            month,
+            day,
+            tod / 3600,
+            tod / 60 % 60,
+            tod % 60
+        );
+        if nanos != 0 {
+            let frac = format!("{:09}", nanos);
+            out.push('.');
+            out.push_str(frac.trim_end_matches('0'));
+        }
+        out.push('Z');
+        output.kv_str(key, &out);
+    }
+}
+
+// Convert days since 1970-01-01 to a (year, month, day) date in the
+// proleptic Gregorian calendar, using Howard Hinnant's algorithm
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+// Tuples output as arrays, with each element visited without a key
+macro_rules! visit_tuple {
+    ($($t:ident $i:tt),+) => {
+        impl<$($t: Visitable),+> Visitable for ($($t,)+) {
+            #[inline]
+            fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+                output.kv_arr(key);
+                $( self.$i.visit(None, output); )+
+                output.kv_arrend(key);
+            }
+        }
+    };
+}
+
+visit_tuple!(A 0);
+visit_tuple!(A 0, B 1);
+visit_tuple!(A 0, B 1, C 2);
+visit_tuple!(A 0, B 1, C 2, D 3);
+visit_tuple!(A 0, B 1, C 2, D 3, E 4);
+visit_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+visit_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+visit_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+visit_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8);
+visit_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9);
+visit_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10);
+visit_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10, L 11);
+
+// Error types output as a map of `kind` and `message`.  The `kind`
+// is the error's own kind where the type provides one, otherwise the
+// type name.
+macro_rules! visit_error {
+    ($fr:ident) => {
+        impl Visitable for $fr {
+            fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+                output.kv_map(key);
+                output.kv_str(Some("kind"), ::std::stringify!($fr));
+                output.kv_fmt(Some("message"), &format_args!("{}", self));
+                output.kv_mapend(key);
+            }
+        }
+    };
+    ($fr:ident, $kind:ident) => {
+        impl Visitable for $fr {
+            fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+                output.kv_map(key);
+                output.kv_fmt(Some("kind"), &format_args!("{:?}", self.$kind()));
+                output.kv_fmt(Some("message"), &format_args!("{}", self));
+                output.kv_mapend(key);
+            }
+        }
+    };
+}
+
+visit_error!(ParseIntError, kind);
+visit_error!(ParseFloatError);
+visit_error!(Utf8Error);
+visit_error!(TryFromIntError);
+visit_error!(TryFromFloatSecsError);
+
+/// Any error output with its chain of sources
+///
+/// `ErrInfo(&err)` outputs a map with the `message` from the error's
+/// `Display` implementation, and a `chain` array containing the
+/// message of each error returned by following `source()`, nearest
+/// first.  The array is empty if there is no source.  This works for
+/// any `std::error::Error`, including boxed errors and those from
+/// crates such as `anyhow` via `as_ref()`.
+///
+/// No backtrace is output, since the `request_ref`/`provide`
+/// interface for getting one from a `dyn Error` is not available on
+/// stable Rust.  If you have captured a `Backtrace` yourself, log it
+/// alongside using [`BacktraceStructured`].
+///
+/// [`BacktraceStructured`]: struct.BacktraceStructured.html
+pub struct ErrInfo<'a>(pub &'a dyn Error);
+
+impl<'a> Visitable for ErrInfo<'a> {
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        output.kv_map(key);
+        output.kv_fmt(Some("message"), &format_args!("{}", self.0));
+        output.kv_arr(Some("chain"));
+        visit_sources(self.0, output);
+        output.kv_arrend(Some("chain"));
+        output.kv_mapend(key);
+    }
+}
+
+/// Error output with the messages of its `source()` chain
+///
+/// `ErrorChain(&err)` outputs a map with the error's `message`, and a
+/// `causes` array containing the message of each error found by
+/// following `source()`, nearest first.  If the error has no source,
+/// the `causes` array is omitted, so a plain error gives just
+/// `{message: ".."}`.  This is the compact form, for logging errors
+/// from libraries which wrap lower-level errors.  See [`ErrInfo`]
+/// for a form which always includes the array.
+///
+/// [`ErrInfo`]: struct.ErrInfo.html
+pub struct ErrorChain<'a>(pub &'a dyn Error);
+
+impl<'a> Visitable for ErrorChain<'a> {
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        output.kv_map(key);
+        output.kv_fmt(Some("message"), &format_args!("{}", self.0));
+        if self.0.source().is_some() {
+            output.kv_arr(Some("causes"));
+            visit_sources(self.0, output);
+            output.kv_arrend(Some("causes"));
+        }
+        output.kv_mapend(key);
+    }
+}
+
+// Output the message of each error in the `source()` chain as an
+// array item
+fn visit_sources(err: &dyn Error, output: &mut dyn LogVisitor) {
+    let mut source = err.source();
+    while let Some(e) = source {
+        output.kv_fmt(None, &format_args!("{}", e));
+        source = e.source();
+    }
+}
+
+/// Float value with metadata about non-finite conditions
+///
+/// This outputs a map `{"value": x, "finite": bool, "sign": "+"/"-"}`,
+/// with `value` output as null if it is NaN.  This makes non-finite
+/// values explicit and easy to filter for when debugging numerical
+/// code.
+pub struct FloatInfo(pub f64);
+
+impl Visitable for FloatInfo {
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        let v = self.0;
+        output.kv_map(key);
+        if v.is_nan() {
+            output.kv_null(Some("value"));
+        } else {
+            output.kv_f64(Some("value"), v);
+        }
+        output.kv_bool(Some("finite"), v.is_finite());
+        output.kv_str(Some("sign"), if v.is_sign_negative() { "-" } else { "+" });
+        output.kv_mapend(key);
+    }
+}
+
+/// Slice output as a single string of joined elements
+///
+/// Each element is formatted with `Display`, and the elements are
+/// separated by the given separator, e.g. `Joined(&tags, ",")` gives
+/// `"a,b,c"`.  This gives more compact human-readable output than the
+/// structured array output for slices, at the cost of losing the
+/// type information.
+pub struct Joined<'a, T>(pub &'a [T], pub &'static str);
+
+impl<'a, T: fmt::Display> fmt::Display for Joined<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut sep = "";
+        for v in self.0 {
+            write!(f, "{}{}", sep, v)?;
+            sep = self.1;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, T: fmt::Display> Visitable for Joined<'a, T> {
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        output.kv_fmt(key, &format_args!("{}", self));
+    }
+}
+
+/// Byte slice output as a single lowercase hex string
+///
+/// For example `Hex(&[0xde, 0xad])` gives `"dead"`, and an empty
+/// slice gives `""`.  Without this, a `&[u8]` is output as an array
+/// of numbers, which is bulky and hard to read for packet payloads
+/// and similar binary data.  In the logging macros, `key: hex!(expr)`
+/// is a shortcut for `key: Hex(expr.as_ref())`.
+///
+/// The hex wrappers and [`Base64`] build their string in a
+/// thread-local buffer which is kept between calls.  A buffer grown
+/// beyond 64KiB is released after use.
+///
+/// [`Base64`]: struct.Base64.html
+pub struct Hex<'a>(pub &'a [u8]);
+
+impl<'a> Visitable for Hex<'a> {
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        with_scratch(|buf| {
+            write_hex(buf, self.0, 0, false);
+            output.kv_str(key, buf);
+        });
+    }
+}
+
+/// Byte slice output as a single uppercase hex string
+///
+/// This works as for [`Hex`], but gives e.g. `"DEAD"`.
+///
+/// [`Hex`]: struct.Hex.html
+pub struct HexUpper<'a>(pub &'a [u8]);
+
+impl<'a> Visitable for HexUpper<'a> {
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        with_scratch(|buf| {
+            write_hex(buf, self.0, 0, true);
+            output.kv_str(key, buf);
+        });
+    }
+}
+
+/// Byte slice output as a string of hex digits in groups
+///
+/// The bytes are output as lowercase hex, with a space between each
+/// group of the given number of bytes, e.g. `HexGrouped(&data, 2)`
+/// gives `"dead beef 0102"`.  This is more readable than undelimited
+/// hex for fixed-layout binary data such as register dumps.  A group
+/// size of zero outputs all the bytes as a single group.
+pub struct HexGrouped<'a>(pub &'a [u8], pub usize);
+
+impl<'a> Visitable for HexGrouped<'a> {
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        with_scratch(|buf| {
+            write_hex(buf, self.0, self.1, false);
+            output.kv_str(key, buf);
+        });
+    }
+}
+
+// Format bytes as hex into `out`, with a space between each group of
+// `group` bytes, or no spaces if `group` is zero
+fn write_hex(out: &mut String, data: &[u8], group: usize, upper: bool) {
+    let group = if group == 0 { usize::MAX } else { group };
+    out.reserve(data.len() * 3);
+    for (i, b) in data.iter().enumerate() {
+        if i != 0 && i % group == 0 {
+            out.push(' ');
+        }
+        let _ = if upper {
+            write!(out, "{:02X}", b)
+        } else {
+            write!(out, "{:02x}", b)
+        };
+    }
+}
+
+// Largest scratch buffer capacity kept between calls.  Anything
+// bigger is dropped after use so that one huge payload doesn't pin
+// that memory for the life of the thread.  This is a bound on the
+// memory kept per thread, not a tuned value.
+const SCRATCH_MAX: usize = 64 * 1024;
+
+thread_local! {
+    static SCRATCH: std::cell::Cell<String> = const { std::cell::Cell::new(String::new()) };
+}
+
+// Run `f` with an empty thread-local `String` to build a value in,
+// for the hex and base64 wrappers, since `LogVisitor` only accepts a
+// complete `&str`.  The buffer is taken out of the cell whilst in
+// use, so a nested call just gets a fresh `String` instead of
+// panicking.
+fn with_scratch<R>(f: impl FnOnce(&mut String) -> R) -> R {
+    let mut buf = SCRATCH.with(|c| c.take());
+    buf.clear();
+    let rv = f(&mut buf);
+    if buf.capacity() <= SCRATCH_MAX {
+        SCRATCH.with(|c| c.set(buf));
+    }
+    rv
+}
+
+/// Byte slice output as its length and CRC-32 checksum
+///
+/// `Checksummed(&buf)` outputs `{len: 9, crc32: 3421780262}`, where
+/// the checksum is the standard CRC-32 (as used by zlib, gzip and
+/// Ethernet) as a number.  This allows buffers to be identified and
+/// compared across log records without logging their full contents.
+pub struct Checksummed<'a>(pub &'a [u8]);
+
+impl<'a> Visitable for Checksummed<'a> {
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        output.kv_map(key);
+        output.kv_u64(Some("len"), self.0.len() as u64);
+        output.kv_u64(Some("crc32"), crc32(self.0).into());
+        output.kv_mapend(key);
+    }
+}
+
+/// Byte slice output as statistics on its contents
+///
+/// `ByteStats(&buf)` outputs `{len, distinct, shannon_entropy}`,
+/// where `distinct` is the number of distinct byte values present,
+/// and `shannon_entropy` is the Shannon entropy of the byte values in
+/// bits per byte, from 0 to 8.  This shows whether a buffer looks
+/// random or compressed (close to 8), text or structured data (lower)
+/// or padding (close to 0), without logging the contents.  An empty
+/// slice has entropy 0.
+pub struct ByteStats<'a>(pub &'a [u8]);
+
+impl<'a> Visitable for ByteStats<'a> {
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        let mut counts = [0_usize; 256];
+        for b in self.0 {
+            counts[usize::from(*b)] += 1;
+        }
+        let len = self.0.len() as f64;
+        let mut entropy = 0.0;
+        for &count in counts.iter().filter(|c| **c > 0) {
+            let p = count as f64 / len;
+            entropy -= p * p.log2();
+        }
+        output.kv_map(key);
+        output.kv_u64(Some("len"), self.0.len() as u64);
+        output.kv_u64(
+            Some("distinct"),
+            counts.iter().filter(|c| **c > 0).count() as u64,
+        );
+        output.kv_f64(Some("shannon_entropy"), entropy.max(0.0));
+        output.kv_mapend(key);
+    }
+}
+
+// Bitwise CRC-32 (reflected polynomial 0xEDB88320).  This is slower
+// than a table-driven version, but it is only used for logging and
+// avoids a dependency.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0_u32;
+    for b in data {
+        crc ^= u32::from(*b);
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+/// Byte slice output as a base64 string
+///
+/// By default this uses the standard alphabet with `=` padding (RFC
+/// 4648 section 4), e.g. `Base64::new(b"ab")` gives `"YWI="`.  Use
+/// [`Base64::url_safe`] to select the URL-safe alphabet (`-` and `_`
+/// in place of `+` and `/`), and [`Base64::pad`] to control whether
+/// the output is padded to a multiple of 4 characters.  This is more
+/// compact than hex for log pipelines that carry binary data.
+///
+/// [`Base64::pad`]: struct.Base64.html#method.pad
+/// [`Base64::url_safe`]: struct.Base64.html#method.url_safe
+pub struct Base64<'a> {
+    data: &'a [u8],
+    url_safe: bool,
+    pad: bool,
+}
+
+impl<'a> Base64<'a> {
+    /// Output the given bytes using the standard alphabet, with
+    /// padding
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            url_safe: false,
+            pad: true,
+        }
+    }
+
+    /// Use the URL-safe alphabet.  Padding is unaffected, so add
+    /// `.pad(false)` if the unpadded form is required.
+    pub fn url_safe(mut self) -> Self {
+        self.url_safe = true;
+        self
+    }
+
+    /// Select whether the output is padded with `=` to a multiple of
+    /// 4 characters.  Defaults to `true`.
+    pub fn pad(mut self, pad: bool) -> Self {
+        self.pad = pad;
+        self
+    }
+}
+
+impl<'a> fmt::Display for Base64<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let alphabet: &[u8; 64] = if self.url_safe {
+            b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_"
+        } else {
+            b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/"
+        };
+        for chunk in self.data.chunks(3) {
+            let b = [
+                chunk[0],
+                *chunk.get(1).unwrap_or(&0),
+                *chunk.get(2).unwrap_or(&0),
+            ];
+            let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    f.write_char(char::from(alphabet[(n >> (18 - 6 * i)) as usize & 63]))?;
+                } else if self.pad {
+                    f.write_char('=')?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Visitable for Base64<'a> {
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        with_scratch(|buf| {
+            let _ = write!(buf, "{}", self);
+            output.kv_str(key, buf);
+        });
+    }
+}
+
+/// Slice of (key, value) pairs output as a map
+///
+/// This is a common shape for dynamically-built field lists.  Since
+/// tuples are output as arrays, a plain `&[(String, V)]` would be
+/// output as an array of 2-element arrays, so wrap it in `Pairs` to
+/// get a map instead, e.g. `fields: Pairs(&list)`.
+pub struct Pairs<'a, K, V>(pub &'a [(K, V)]);
+
+impl<'a, K: AsRef<str>, V: Visitable> Visitable for Pairs<'a, K, V> {
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        output.kv_map(key);
+        for (k, v) in self.0 {
+            v.visit(Some(k.as_ref()), output);
+        }
+        output.kv_mapend(key);
+    }
+}
+
+/// Value output using its `Display` formatting
+///
+/// This makes any `Display` type usable where a [`Visitable`] is
+/// needed, e.g. `ids: Vec<AsDisplay<Uuid>>` in a struct with a
+/// `Visitable` implementation, or `peer: AsDisplay(&addr)` in a
+/// logging macro, which is equivalent to `%peer`.  The value is
+/// passed to the visitor as a formatted string, so any structure is
+/// lost.
+///
+/// [`Visitable`]: trait.Visitable.html
+pub struct AsDisplay<T>(pub T);
+
+impl<T: fmt::Display> Visitable for AsDisplay<T> {
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        output.kv_fmt(key, &format_args!("{}", self.0));
+    }
+}
+
+/// Slice output as a map of only its non-default elements
+///
+/// `SparseArr(&table)` outputs a map from index to value, including
+/// only those elements which are not equal to `T::default()`, e.g.
+/// `{"3": 7, "90": 2}` for a mostly-zero table.  This keeps logging
+/// of large sparse arrays compact, e.g. `trace!([cx], table:
+/// SparseArr(&entries))`.  An all-default slice gives an empty map.
+pub struct SparseArr<'a, T>(pub &'a [T]);
+
+impl<'a, T: PartialEq + Default + Visitable> Visitable for SparseArr<'a, T> {
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        let default = T::default();
+        let mut index = String::new();
+        output.kv_map(key);
+        for (i, v) in self.0.iter().enumerate() {
+            if *v != default {
+                index.clear();
+                let _ = write!(index, "{}", i);
+                v.visit(Some(&index), output);
+            }
+        }
+        output.kv_mapend(key);
+    }
+}
+
+/// Value marked as sensitive
+///
+/// `Secret(&token)` outputs the value so that a [`RedactingVisitor`]
+/// replaces it with `"<redacted>"`, whatever its key, whilst the
+/// renderers in this crate output the plain value.  This lets the
+/// call site tag a field as sensitive, so that the sinks which redact
+/// secrets mask it, whilst other sinks (e.g. a local debug log) see
+/// the real value.  The `secret!` shortcut of the logging macros
+/// wraps a value in `Secret`, e.g. `info!([cx], secret!(token),
+/// "Login")`.
+///
+/// The marker is carried in the visited data, by outputting the
+/// value within a map under the reserved key `$secret`.  So it
+/// survives a record being collected and visited again, e.g. by a
+/// [`MapRecordSink`], and a `LogVisitor` that doesn't know about the
+/// marker sees `token{$secret=...}` rather than losing it.
+///
+/// [`MapRecordSink`]: struct.MapRecordSink.html
+/// [`RedactingVisitor`]: struct.RedactingVisitor.html
+pub struct Secret<'a, V: ?Sized>(pub &'a V);
+
+// Key of the single entry of the map output by `Secret`
+pub(crate) const SECRET_KEY: &str = "$secret";
+
+impl<'a, V: Visitable + ?Sized> Visitable for Secret<'a, V> {
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        output.kv_map(key);
+        self.0.visit(Some(SECRET_KEY), output);
+        output.kv_mapend(key);
+    }
+}
+
+/// Map value with its entries output at the parent level
+///
+/// This strips the outer map delimiters from a map-shaped value, so
+/// that its entries appear directly within the enclosing map, like
+/// `#[serde(flatten)]`.  It is intended for use within a
+/// [`Visitable`] implementation for a struct that contains another
+/// struct whose fields should not be nested:
+///
+/// ```ignore
+/// impl Visitable for Request {
+///     fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+///         output.kv_map(key);
+///         self.id.visit(Some("id"), output);
+///         Flatten(&self.peer).visit(None, output);
+///         output.kv_mapend(key);
+///     }
+/// }
+/// ```
+///
+/// The wrapped value must output a map.  Any other value is passed
+/// through unchanged, with the key given.
+///
+/// With the `derive` feature, mark the field with `#[log(flatten)]`
+/// instead to get the same effect in a derived implementation:
+///
+/// ```ignore
+/// #[derive(Visitable)]
+/// struct Request {
+///     id: u64,
+///     #[log(flatten)]
+///     peer: Peer,
+/// }
+/// ```
+///
+/// [`Visitable`]: trait.Visitable.html
+pub struct Flatten<'a, V: ?Sized>(pub &'a V);
+
+impl<'a, V: Visitable + ?Sized> Visitable for Flatten<'a, V> {
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        let mut flatten = Flattener {
+            output,
+            depth: 0,
+            stripped: false,
+        };
+        self.0.visit(key, &mut flatten);
+    }
+}
+
+// Visitor which passes everything through except the outermost map
+// delimiters
+struct Flattener<'a> {
+    output: &'a mut dyn LogVisitor,
+    depth: usize,
+    stripped: bool, // Outermost map delimiters were stripped
+}
+
+impl<'a> LogVisitor for Flattener<'a> {
+    fn kv_u64(&mut self, key: Option<&str>, val: u64) {
+        self.output.kv_u64(key, val);
+    }
+    fn kv_i64(&mut self, key: Option<&str>, val: i64) {
+        self.output.kv_i64(key, val);
+    }
+    fn kv_f64(&mut self, key: Option<&str>, val: f64) {
+        self.output.kv_f64(key, val);
+    }
+    fn kv_bool(&mut self, key: Option<&str>, val: bool) {
+        self.output.kv_bool(key, val);
+    }
+    fn kv_null(&mut self, key: Option<&str>) {
+        self.output.kv_null(key);
+    }
+    fn kv_str(&mut self, key: Option<&str>, val: &str) {
+        self.output.kv_str(key, val);
+    }
+    fn kv_fmt(&mut self, key: Option<&str>, val: &Arguments<'_>) {
+        self.output.kv_fmt(key, val);
+    }
+    fn kv_map(&mut self, key: Option<&str>) {
+        if self.depth == 0 {
+            self.stripped = true;
+        } else {
+            self.output.kv_map(key);
+        }
+        self.depth += 1;
+    }
+    fn kv_mapend(&mut self, key: Option<&str>) {
+        self.depth = self.depth.saturating_sub(1);
+        if self.depth != 0 || !self.stripped {
+            self.output.kv_mapend(key);
+        }
+    }
+    fn kv_arr(&mut self, key: Option<&str>) {
+        self.depth += 1;
+        self.output.kv_arr(key);
+    }
+    fn kv_arrend(&mut self, key: Option<&str>) {
+        self.depth = self.depth.saturating_sub(1);
+        self.output.kv_arrend(key);
+    }
+}
+
+/// Set membership of selected elements, output as a map
+///
+/// For large sets where only specific memberships matter, this avoids
+/// dumping the whole set.  It outputs a map of each probe element
+/// (formatted with `Display`) to a bool indicating whether it is in
+/// the set.  For example `Contains(&set, &["a", "b"])` gives
+/// `{"a": true, "b": false}`.
+pub struct Contains<'a, T>(pub &'a HashSet<T>, pub &'a [T]);
+
+impl<'a, T: Eq + Hash + fmt::Display> Visitable for Contains<'a, T> {
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        let mut name = String::new();
+        output.kv_map(key);
+        for probe in self.1 {
+            name.clear();
+            let _ = write!(name, "{}", probe);
+            output.kv_bool(Some(&name), self.0.contains(probe));
+        }
+        output.kv_mapend(key);
+    }
+}
+
+/// How a [`Metric`] value should be aggregated
+///
+/// [`Metric`]: struct.Metric.html
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Aggregation {
+    /// Values are counts or amounts to be summed, output as `"sum"`
+    Sum,
+    /// Each value is the current level of something, output as
+    /// `"gauge"`
+    Gauge,
+    /// Values are samples to be collected into a distribution, output
+    /// as `"histogram"`
+    Histogram,
+}
+
+impl Aggregation {
+    /// Get the name used in the output
+    pub fn name(self) -> &'static str {
+        match self {
+            Aggregation::Sum => "sum",
+            Aggregation::Gauge => "gauge",
+            Aggregation::Histogram => "histogram",
+        }
+    }
+}
+
+/// Value output along with its permitted range
+///
+/// `Bounded(n, 1, 100)` outputs `{value: n, min: 1, max: 100,
+/// in_range: bool}`, where `in_range` is true if `min <= value <=
+/// max`.  This suits logging configuration values, making any that
+/// are out of range obvious, e.g. `warn!([cx], pool_size: Bounded(n,
+/// 1, 100), "Pool size")`.  A value that can't be compared, such as a
+/// NaN float, is not in range.
+pub struct Bounded<T>(pub T, pub T, pub T);
+
+impl<T: Visitable + PartialOrd> Visitable for Bounded<T> {
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        output.kv_map(key);
+        self.0.visit(Some("value"), output);
+        self.1.visit(Some("min"), output);
+        self.2.visit(Some("max"), output);
+        output.kv_bool(Some("in_range"), self.1 <= self.0 && self.0 <= self.2);
+        output.kv_mapend(key);
+    }
+}
+
+/// Numeric value annotated with a unit and an [`Aggregation`] hint
+///
+/// `Metric(35, "ms", Aggregation::Histogram)` outputs `{value: 35,
+/// unit: "ms", agg: "histogram"}`.  This gives a standard way to mark
+/// up metric values which works across all output formats, so that
+/// sinks which route metrics elsewhere can recognise them and know
+/// how to handle them.
+///
+/// [`Aggregation`]: enum.Aggregation.html
+pub struct Metric<V>(pub V, pub &'static str, pub Aggregation);
+
+impl<V: Visitable> Visitable for Metric<V> {
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        output.kv_map(key);
+        self.0.visit(Some("value"), output);
+        output.kv_str(Some("unit"), self.1);
+        output.kv_str(Some("agg"), self.2.name());
+        output.kv_mapend(key);
+    }
+}
+
+/// Difference between two values output as a JSON merge patch
+///
+/// `MergePatch(&old, &new)` collects both values and outputs the
+/// patch that transforms `old` into `new`, following the semantics
+/// of RFC 7396: if both are maps, the patch is a map containing null
+/// for each key that was deleted, the new value for each key that
+/// was added, and for each key whose value changed, the patch for
+/// that value, recursing into nested maps.  Unchanged keys are left
+/// out, so identical maps give an empty map.  Anything other than a
+/// pair of maps, including arrays, is replaced whole, i.e. the patch
+/// is the new value.  As in RFC 7396, a key whose new value is null
+/// can't be told apart from a deleted key.  This suits audit trails
+/// of configuration changes, for example:
+///
+/// ```ignore
+/// audit!([cx], ConfigChanged, changes: MergePatch(&old_config, &new_config));
+/// ```
+pub struct MergePatch<'a, V: ?Sized>(pub &'a V, pub &'a V);
+
+impl<'a, V: Visitable + ?Sized> Visitable for MergePatch<'a, V> {
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        let old = tree::collect_value(self.0);
+        let new = tree::collect_value(self.1);
+        tree::merge_patch(&old, &new).visit(key, output);
+    }
+}
+
+/// Outcome of a `try_lock` call output as a map
+///
+/// `LockStatus(&mutex.try_lock())` outputs `{acquired: false,
+/// would_block: true, poisoned: false}` and so on, which allows lock
+/// contention to be diagnosed from the logs.  This works with the
+/// `TryLockResult` from `Mutex::try_lock`, `RwLock::try_read` and
+/// `RwLock::try_write`.  A poisoned lock is still acquired, so it
+/// gives `acquired: true` along with `poisoned: true`.
+pub struct LockStatus<'a, G>(pub &'a TryLockResult<G>);
+
+impl<'a, G> Visitable for LockStatus<'a, G> {
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        let (would_block, poisoned) = match self.0 {
+            Ok(_) => (false, false),
+            Err(TryLockError::WouldBlock) => (true, false),
+            Err(TryLockError::Poisoned(_)) => (false, true),
+        };
+        output.kv_map(key);
+        output.kv_bool(Some("acquired"), !would_block);
+        output.kv_bool(Some("would_block"), would_block);
+        output.kv_bool(Some("poisoned"), poisoned);
+        output.kv_mapend(key);
+    }
+}
+
+/// Interval between two `Instant` values output as a map
+///
+/// `Interval(start, end)` outputs `{duration_ms: 12.5}`, i.e. the
+/// time from `start` to `end` in milliseconds as a float, so that
+/// sub-millisecond timings are not lost.  This suits logging the
+/// timing of a completed operation from captured start and end
+/// instants.  If `end` is earlier than `start`, `duration_ms` is
+/// output as null.
+pub struct Interval(pub Instant, pub Instant);
+
+impl Visitable for Interval {
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        output.kv_map(key);
+        match self.1.checked_duration_since(self.0) {
+            Some(d) => output.kv_f64(Some("duration_ms"), d.as_secs_f64() * 1000.0),
+            None => output.kv_null(Some("duration_ms")),
+        }
+        output.kv_mapend(key);
+    }
+}
+
+/// `Duration` output as a map of machine-readable and human-readable
+/// forms
+///
+/// This outputs `{ms: 1500, human: "1.5s"}`, i.e. the whole number of
+/// milliseconds, plus a string with the unit chosen adaptively
+/// according to the magnitude (`s`, `ms`, `µs` or `ns`), with up to 3
+/// decimal places.
+pub struct DurationBoth(pub Duration);
+
+impl fmt::Display for DurationBoth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ns = self.0.as_nanos();
+        let (div, unit) = match ns {
+            1_000_000_000.. => (1_000_000_000, "s"),
+            1_000_000.. => (1_000_000, "ms"),
+            1_000.. => (1_000, "µs"),
+            _ => return write!(f, "{}ns", ns),
+        };
+        let frac = (ns % div) * 1000 / div;
+        write!(f, "{}", ns / div)?;
+        if frac != 0 {
+            let frac = format!("{:03}", frac);
+            write!(f, ".{}", frac.trim_end_matches('0'))?;
+        }
+        f.write_str(unit)
+    }
+}
+
+impl Visitable for DurationBoth {
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        output.kv_map(key);
+        output.kv_u64(Some("ms"), self.0.as_millis() as u64);
+        output.kv_fmt(Some("human"), &format_args!("{}", self));
+        output.kv_mapend(key);
+    }
+}
+
+/// **Stakker** virtual time, output as seconds since the runtime
+/// started
+///
+/// **Stakker** works in terms of `Instant` values, which have no
+/// meaningful absolute value, so this holds the offset from the
+/// `Instant` passed to `Stakker::new`, and outputs it as a floating
+/// point number of seconds.  Use [`VirtualTime::now`] or
+/// [`LogCx::now`] to get the current time from `Core`.  For example:
+///
+/// ```ignore
+/// info!([cx], now: VirtualTime::now(cx), "Timer expired");
+/// ```
+///
+/// [`LogCx::now`]: struct.LogCx.html#method.now
+/// [`VirtualTime::now`]: struct.VirtualTime.html#method.now
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct VirtualTime(pub Duration);
+
+impl VirtualTime {
+    /// Get the current virtual time from `Core`
+    pub fn now(core: &Core) -> Self {
+        Self(core.now().saturating_duration_since(core.start_instant()))
+    }
+}
+
+impl Visitable for VirtualTime {
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        output.kv_f64(key, self.0.as_secs_f64());
+    }
+}
+
+/// Backtrace output as structured frames
+///
+/// This outputs a map with a `status` string (`captured`, `disabled`
+/// or `unsupported`) and a `frames` array.  Each frame is output as
+/// a map `{"fn": ..., "file": ..., "line": ...}`, with `file` and
+/// `line` omitted if unknown.
+///
+/// Since structured access to the frames of a `std::backtrace::Backtrace`
+/// is not yet stable, this works by parsing the formatted output of
+/// the backtrace.  This is best-effort: any line that can't be
+/// parsed is output within the `frames` array as a plain string
+/// instead, so no information is lost if the format changes.
+pub struct BacktraceStructured<'a>(pub &'a Backtrace);
+
+enum BtFrame<'a> {
+    Frame {
+        func: &'a str,
+        file: Option<&'a str>,
+        line: Option<u64>,
+    },
+    Text(&'a str),
+}
+
+fn parse_backtrace(text: &str) -> Vec<BtFrame<'_>> {
+    let mut frames = Vec::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(loc) = line.strip_prefix("at ") {
+            if let Some(BtFrame::Frame { file, line, .. }) = frames.last_mut() {
+                if file.is_none() {
+                    // Location is "file:line:col"
+                    let mut it = loc.rsplitn(3, ':');
+                    let _col = it.next();
+                    let lnum = it.next().and_then(|l| l.parse().ok());
+                    if let (Some(lnum), Some(f)) = (lnum, it.next()) {
+                        *file = Some(f);
+                        *line = Some(lnum);
+                    } else {
+                        *file = Some(loc);
+                    }
+                    continue;
+                }
+            }
+        } else if let Some((num, func)) = line.split_once(": ") {
+            if num.bytes().all(|b| b.is_ascii_digit()) {
+                frames.push(BtFrame::Frame {
+                    func,
+                    file: None,
+                    line: None,
+                });
+                continue;
+            }
+        }
+        frames.push(BtFrame::Text(line));
+    }
+    frames
+}
+
+fn visit_frames(frames: &[BtFrame<'_>], output: &mut dyn LogVisitor) {
+    for frame in frames {
+        match frame {
+            BtFrame::Frame { func, file, line } => {
+                output.kv_map(None);
+                output.kv_str(Some("fn"), func);
+                if let Some(file) = file {
+                    output.kv_str(Some("file"), file);
+                }
+                if let Some(line) = line {
+                    line.visit(Some("line"), output);
+                }
+                output.kv_mapend(None);
+            }
+            BtFrame::Text(text) => output.kv_str(None, text),
+        }
+    }
+}
+
+impl<'a> Visitable for BacktraceStructured<'a> {
+    fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+        let status = match self.0.status() {
+            BacktraceStatus::Captured => "captured",
+            BacktraceStatus::Disabled => "disabled",
+            BacktraceStatus::Unsupported => "unsupported",
+            _ => "unknown",
+        };
+        output.kv_map(key);
+        output.kv_str(Some("status"), status);
+        output.kv_arr(Some("frames"));
+        if let BacktraceStatus::Captured = self.0.status() {
+            visit_frames(&parse_backtrace(&self.0.to_string()), output);
+        }
+        output.kv_arrend(Some("frames"));
+        output.kv_mapend(key);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        parse_backtrace, visit_frames, Aggregation, AsDisplay, BacktraceStructured, Base64,
+        Bounded, ByteStats, Checksummed, Contains, DurationBoth, ErrInfo, ErrorChain, Flatten,
+        FloatInfo, Hex, HexGrouped, HexUpper, Interval, Joined, LockStatus, MergePatch, Metric,
+        Pairs, SparseArr, Visitable,
+    };
+    use crate::{KvSingleLine, KvToJson};
+    use stakker::LogVisitor;
+    use std::backtrace::Backtrace;
+    use std::borrow::Cow;
+    use std::collections::{BTreeMap, HashSet};
+    use std::fmt;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4};
+    use std::path::{Path, PathBuf};
+    use std::rc::Rc;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant, UNIX_EPOCH};
+
+    fn render<V: Visitable + ?Sized>(val: &V) -> String {
+        KvSingleLine::new(&|lv| val.visit(Some("v"), lv), "", "").to_string()
+    }
+
+    #[test]
+    fn nonzero() {
+        let handle = std::num::NonZeroU32::new(42).unwrap();
+        assert_eq!(render(&handle), "v=42");
+        assert_eq!(render(&std::num::NonZeroI64::new(-3).unwrap()), "v=-3");
+        let kvscan = |lv: &mut dyn LogVisitor| handle.visit(Some("handle"), lv);
+        assert_eq!(KvToJson::new(&kvscan, "", "").to_string(), r#""handle":42"#);
+    }
+
+    #[test]
+    fn atomic() {
+        use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering};
+        let count = AtomicU64::new(0);
+        count.store(42, Ordering::SeqCst);
+        assert_eq!(render(&count), "v=42");
+        assert_eq!(render(&AtomicI32::new(-7)), "v=-7");
+        assert_eq!(render(&AtomicBool::new(true)), "v=true");
+    }
+
+    #[test]
+    fn errors() {
+        let e = "abc".parse::<u32>().unwrap_err();
+        assert_eq!(
+            render(&e),
+            "v{kind=InvalidDigit message=\"invalid digit found in string\"}"
+        );
+
+        let bytes = vec![b'a', 0xFF];
+        let e = std::str::from_utf8(&bytes).unwrap_err();
+        assert_eq!(
+            render(&e),
+            "v{kind=Utf8Error message=\"invalid utf-8 sequence of 1 bytes from index 1\"}"
+        );
+
+        let e = Duration::try_from_secs_f64(-1.0).unwrap_err();
+        // The message wording varies between Rust versions
+        assert!(render(&e).starts_with("v{kind=TryFromFloatSecsError message="));
+    }
+
+    #[test]
+    fn err_info() {
+        #[derive(Debug)]
+        struct Outer(std::num::ParseIntError);
+        impl std::fmt::Display for Outer {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "bad config")
+            }
+        }
+        impl std::error::Error for Outer {
+            fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+                Some(&self.0)
+            }
+        }
+        let e = Outer("x".parse::<u32>().unwrap_err());
+        assert_eq!(
+            render(&ErrInfo(&e)),
+            "v{message=\"bad config\" chain[\"invalid digit found in string\"]}"
+        );
+        let out = KvToJson::new(&|lv| ErrInfo(&e.0).visit(Some("v"), lv), "", "").to_string();
+        assert_eq!(
+            out,
+            r#""v":{"message":"invalid digit found in string","chain":[]}"#
+        );
+    }
+
+    #[test]
+    fn error_chain() {
+        #[derive(Debug)]
+        enum ConfigError {
+            Load(LoadError),
+        }
+        #[derive(Debug)]
+        struct LoadError(std::num::ParseIntError);
+        impl std::fmt::Display for ConfigError {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "config failed")
+            }
+        }
+        impl std::fmt::Display for LoadError {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "load failed")
+            }
+        }
+        impl std::error::Error for ConfigError {
+            fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+                match self {
+                    ConfigError::Load(e) => Some(e),
+                }
+            }
+        }
+        impl std::error::Error for LoadError {
+            fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+                Some(&self.0)
+            }
+        }
+        let e = ConfigError::Load(LoadError("x".parse::<u32>().unwrap_err()));
+        let out = KvToJson::new(&|lv| ErrorChain(&e).visit(Some("err"), lv), "", "").to_string();
+        assert_eq!(
+            out,
+            r#""err":{"message":"config failed","causes":["load failed","invalid digit found in string"]}"#
+        );
+        let ConfigError::Load(LoadError(inner)) = &e;
+        assert_eq!(
+            render(&ErrorChain(inner)),
+            "v{message=\"invalid digit found in string\"}"
+        );
+    }
+
+    #[test]
+    fn float_info() {
+        assert_eq!(render(&FloatInfo(1.5)), "v{value=1.5 finite=true sign=+}");
+        assert_eq!(render(&FloatInfo(f64::NAN)), "v{value finite=false sign=+}");
+        assert_eq!(
+            render(&FloatInfo(f64::NEG_INFINITY)),
+            "v{value=-inf finite=false sign=-}"
+        );
+    }
+
+    #[test]
+    fn joined() {
+        assert_eq!(render(&Joined(&["a", "b", "c"], ",")), "v=a,b,c");
+        assert_eq!(render(&Joined(&[1, 2], " + ")), "v=\"1 + 2\"");
+        assert_eq!(render(&Joined::<u8>(&[], ",")), "v=");
+    }
+
+    #[test]
+    fn pairs() {
+        let v: Vec<(String, u32)> = vec![("a".to_string(), 1), ("b".to_string(), 2)];
+        assert_eq!(render(&Pairs(&v)), "v{a=1 b=2}");
+        let out = KvSingleLine::new(&|lv| v.visit(Some("v"), lv), "", "").to_string();
+        assert_eq!(out, "v[[a 1] [b 2]]");
+    }
+
+    #[test]
+    fn as_display() {
+        struct Version(u16, u16);
+        impl fmt::Display for Version {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "v{}.{}", self.0, self.1)
+            }
+        }
+        assert_eq!(render(&AsDisplay(Version(1, 2))), "v=v1.2");
+        // Numbers become strings
+        let out = KvToJson::new(&|lv| AsDisplay(42).visit(Some("v"), lv), "", "").to_string();
+        assert_eq!(out, r#""v":"42""#);
+        let list = vec![AsDisplay(Version(1, 0)), AsDisplay(Version(2, 1))];
+        assert_eq!(render(&list), "v[v1.0 v2.1]");
+    }
+
+    #[test]
+    fn sparse_arr() {
+        let mut table = [0u32; 100];
+        table[3] = 7;
+        table[90] = 2;
+        assert_eq!(render(&SparseArr(&table)), "v{3=7 90=2}");
+        assert_eq!(render(&SparseArr(&[0u32; 4])), "v{}");
+        let names = ["", "a", ""].map(String::from);
+        assert_eq!(render(&SparseArr(&names)), "v{1=a}");
+    }
+
+    #[test]
+    fn fixed_array() {
+        let a: [i32; 3] = [1, -2, 3];
+        assert_eq!(render(&a), "v[1 -2 3]");
+        let out = KvToJson::new(&|lv| a.visit(Some("v"), lv), "", "").to_string();
+        assert_eq!(out, r#""v":[1,-2,3]"#);
+        assert_eq!(render(&[0_u8; 0]), "v[]");
+    }
+
+    #[test]
+    fn flatten() {
+        struct Peer {
+            addr: &'static str,
+            port: u16,
+        }
+        impl Visitable for Peer {
+            fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+                output.kv_map(key);
+                self.addr.visit(Some("addr"), output);
+                self.port.visit(Some("port"), output);
+                output.kv_mapend(key);
+            }
+        }
+        struct Request {
+            id: u64,
+            peer: Peer,
+            tags: Vec<&'static str>,
+        }
+        impl Visitable for Request {
+            fn visit(&self, key: Option<&str>, output: &mut dyn LogVisitor) {
+                output.kv_map(key);
+                self.id.visit(Some("id"), output);
+                Flatten(&self.peer).visit(None, output);
+                Flatten(&self.tags).visit(Some("tags"), output);
+                output.kv_mapend(key);
+            }
+        }
+        let req = Request {
+            id: 7,
+            peer: Peer {
+                addr: "10.0.0.1",
+                port: 80,
+            },
+            tags: vec!["a"],
+        };
+        assert_eq!(render(&req), "v{id=7 addr=10.0.0.1 port=80 tags[a]}");
+    }
+
+    #[test]
+    fn net_addrs() {
+        let ip4 = Ipv4Addr::new(192, 168, 0, 1);
+        let ip6: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        assert_eq!(render(&ip4), "v=192.168.0.1");
+        assert_eq!(render(&IpAddr::V6(ip6)), "v=2001:db8::1");
+        let sa = SocketAddr::new(IpAddr::V6(ip6), 443);
+        let out = KvToJson::new(&|lv| sa.visit(Some("v"), lv), "", "").to_string();
+        assert_eq!(out, r#""v":"[2001:db8::1]:443""#);
+        assert_eq!(render(&SocketAddrV4::new(ip4, 80)), "v=192.168.0.1:80");
+    }
+
+    #[test]
+    fn smart_pointers() {
+        assert_eq!(render(&Rc::new(5_u32)), "v=5");
+        assert_eq!(render(&Arc::new(String::from("a b"))), "v=\"a b\"");
+        assert_eq!(render(&Box::new([1, 2])), "v[1 2]");
+        let boxed: Box<dyn Visitable> = Box::new(true);
+        assert_eq!(render(&boxed), "v=true");
+        let slice: Rc<[u8]> = Rc::from(&[7_u8][..]);
+        assert_eq!(render(&slice), "v[7]");
+    }
+
+    #[test]
+    fn cow() {
+        let borrowed: Cow<'_, str> = Cow::Borrowed("a b");
+        let owned: Cow<'_, str> = Cow::Owned(String::from("xyz"));
+        assert_eq!(render(&borrowed), "v=\"a b\"");
+        assert_eq!(render(&owned), "v=xyz");
+        let borrowed: Cow<'_, [u32]> = Cow::Borrowed(&[1, 2]);
+        let owned: Cow<'_, [u32]> = Cow::Owned(vec![3]);
+        assert_eq!(render(&borrowed), "v[1 2]");
+        assert_eq!(render(&owned), "v[3]");
+    }
+
+    #[test]
+    fn paths() {
+        let p = PathBuf::from("/var/log/app.log");
+        assert_eq!(render(&p), "v=/var/log/app.log");
+        assert_eq!(render(p.as_path()), "v=/var/log/app.log");
+    }
+    #[cfg(unix)]
+    #[test]
+    fn paths_non_utf8() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        let p = Path::new(OsStr::from_bytes(b"/tmp/a\xffb"));
+        assert_eq!(render(p), "v=/tmp/a\u{fffd}b");
+    }
+
+    #[test]
+    fn tuples() {
+        assert_eq!(render(&(3_u32, -4_i32)), "v[3 -4]");
+        let t = (1_u8, "two", 3.5_f64, String::from("four"));
+        let out = KvToJson::new(&|lv| t.visit(Some("t"), lv), "", "").to_string();
+        assert_eq!(out, r#""t":[1,"two",3.5,"four"]"#);
+        assert_eq!(
+            render(&(1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12)),
+            "v[1 2 3 4 5 6 7 8 9 10 11 12]"
+        );
+    }
+
+    #[test]
+    fn system_time() {
+        let at = |secs: u64, nanos: u32| UNIX_EPOCH + Duration::new(secs, nanos);
+        assert_eq!(render(&UNIX_EPOCH), "v=1970-01-01T00:00:00Z");
+        assert_eq!(render(&at(1_700_000_000, 0)), "v=2023-11-14T22:13:20Z");
+        assert_eq!(
+            render(&at(1_700_000_000, 500_000_000)),
+            "v=2023-11-14T22:13:20.5Z"
+        );
+        assert_eq!(
+            render(&at(951_782_400, 1)),
+            "v=2000-02-29T00:00:00.000000001Z"
+        );
+        let before = UNIX_EPOCH - Duration::new(1, 250_000_000);
+        assert_eq!(render(&before), "v=1969-12-31T23:59:58.75Z");
+        let before = UNIX_EPOCH - Duration::from_secs(86400 * 365);
+        assert_eq!(render(&before), "v=1969-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn merge_patch() {
+        let mut old = BTreeMap::new();
+        old.insert(
+            "a",
+            [("x", 1), ("y", 2)]
+                .iter()
+                .cloned()
+                .collect::<BTreeMap<_, _>>(),
+        );
+        old.insert("b", [("z", 3)].iter().cloned().collect());
+        let mut new = old.clone();
+        new.get_mut("a").unwrap().insert("y", 5);
+        new.remove("b");
+        new.insert("c", [("w", 4)].iter().cloned().collect());
+        let out =
+            KvToJson::new(&|lv| MergePatch(&old, &new).visit(Some("v"), lv), "", "").to_string();
+        assert_eq!(out, r#""v":{"a":{"y":5},"b":null,"c":{"w":4}}"#);
+        assert_eq!(render(&MergePatch(&old, &old)), "v{}");
+        assert_eq!(render(&MergePatch(&[1, 2][..], &[3][..])), "v[3]");
+    }
+
+    #[test]
+    fn lock_status() {
+        let mutex = Arc::new(Mutex::new(0));
+        assert_eq!(
+            render(&LockStatus(&mutex.try_lock())),
+            "v{acquired=true would_block=false poisoned=false}"
+        );
+        let guard = mutex.lock().unwrap();
+        assert_eq!(
+            render(&LockStatus(&mutex.try_lock())),
+            "v{acquired=false would_block=true poisoned=false}"
+        );
+        drop(guard);
+        let mutex2 = mutex.clone();
+        let _ = std::thread::spawn(move || {
+            let _guard = mutex2.lock().unwrap();
+            panic!("Poison the lock");
+        })
+        .join();
+        assert_eq!(
+            render(&LockStatus(&mutex.try_lock())),
+            "v{acquired=true would_block=false poisoned=true}"
+        );
+    }
+
+    #[test]
+    fn bounded() {
+        let out = KvToJson::new(
+            &|lv| Bounded(8_u32, 1, 100).visit(Some("pool_size"), lv),
+            "",
+            "",
+        )
+        .to_string();
+        assert_eq!(
+            out,
+            r#""pool_size":{"value":8,"min":1,"max":100,"in_range":true}"#
+        );
+        assert_eq!(
+            render(&Bounded(0_i32, 1, 100)),
+            "v{value=0 min=1 max=100 in_range=false}"
+        );
+        assert_eq!(
+            render(&Bounded(101_i32, 1, 100)),
+            "v{value=101 min=1 max=100 in_range=false}"
+        );
+        assert_eq!(
+            render(&Bounded(f64::NAN, 0.0, 1.0)),
+            "v{value=NaN min=0 max=1 in_range=false}"
+        );
+    }
+
+    #[test]
+    fn metric() {
+        let m = Metric(35_u32, "ms", Aggregation::Histogram);
+        let out = KvToJson::new(&|lv| m.visit(Some("latency"), lv), "", "").to_string();
+        assert_eq!(
+            out,
+            r#""latency":{"value":35,"unit":"ms","agg":"histogram"}"#
+        );
+        assert_eq!(
+            render(&Metric(1.5, "bytes", Aggregation::Gauge)),
+            "v{value=1.5 unit=bytes agg=gauge}"
+        );
+        assert_eq!(Aggregation::Sum.name(), "sum");
+    }
+
+    #[test]
+    fn interval() {
+        let start = Instant::now();
+        let end = start + Duration::from_micros(2500);
+        assert_eq!(render(&Interval(start, end)), "v{duration_ms=2.5}");
+        assert_eq!(render(&Interval(start, start)), "v{duration_ms=0}");
+        let out =
+            KvToJson::new(&|lv| Interval(end, start).visit(Some("v"), lv), "", "").to_string();
+        assert_eq!(out, r#""v":{"duration_ms":null}"#);
+    }
+
+    #[test]
+    fn checksummed() {
+        assert_eq!(
+            render(&Checksummed(b"123456789")),
+            "v{len=9 crc32=3421780262}"
+        );
+        assert_eq!(render(&Checksummed(b"")), "v{len=0 crc32=0}");
+        let out =
+            KvToJson::new(&|lv| Checksummed(b"abc").visit(Some("frame"), lv), "", "").to_string();
+        assert_eq!(out, r#""frame":{"len":3,"crc32":891568578}"#);
+    }
+
+    #[test]
+    fn byte_stats() {
+        assert_eq!(
+            render(&ByteStats(&[0; 1000])),
+            "v{len=1000 distinct=1 shannon_entropy=0}"
+        );
+        assert_eq!(
+            render(&ByteStats(&[])),
+            "v{len=0 distinct=0 shannon_entropy=0}"
+        );
+        let uniform: Vec<u8> = (0..=255).cycle().take(1024).collect();
+        assert_eq!(
+            render(&ByteStats(&uniform)),
+            "v{len=1024 distinct=256 shannon_entropy=8}"
+        );
+        assert_eq!(
+            render(&ByteStats(b"abab")),
+            "v{len=4 distinct=2 shannon_entropy=1}"
+        );
+    }
+
+    #[test]
+    fn base64() {
+        let enc = |data: &[u8]| Base64::new(data).to_string();
+        assert_eq!(enc(b""), "");
+        assert_eq!(enc(b"f"), "Zg==");
+        assert_eq!(enc(b"fo"), "Zm8=");
+        assert_eq!(enc(b"foo"), "Zm9v");
+        assert_eq!(enc(b"foobar"), "Zm9vYmFy");
+        let nopad = |data: &[u8]| Base64::new(data).pad(false).to_string();
+        assert_eq!(nopad(b""), "");
+        assert_eq!(nopad(b"f"), "Zg");
+        assert_eq!(nopad(b"fo"), "Zm8");
+        assert_eq!(nopad(b"foo"), "Zm9v");
+        let data = [0xfb, 0xff, 0xbf];
+        assert_eq!(Base64::new(&data).to_string(), "+/+/");
+        assert_eq!(Base64::new(&data).url_safe().to_string(), "-_-_");
+        assert_eq!(Base64::new(&data[..1]).url_safe().to_string(), "-w==");
+        assert_eq!(render(&Base64::new(b"fo")), "v=\"Zm8=\"");
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!(render(&Hex(&[])), "v=");
+        assert_eq!(render(&Hex(&[0x00, 0x0f, 0xab, 0xff])), "v=000fabff");
+        assert_eq!(render(&HexUpper(&[])), "v=");
+        assert_eq!(render(&HexUpper(&[0x00, 0x0f, 0xab, 0xff])), "v=000FABFF");
+        let out = KvToJson::new(&|lv| Hex(&[1, 2, 3]).visit(Some("v"), lv), "", "").to_string();
+        assert_eq!(out, r#""v":"010203""#);
+    }
+
+    #[test]
+    fn scratch_reuse() {
+        // Output via the scratch buffer must match building a fresh
+        // `String`, including when a shorter value follows a longer
+        // one, and for values too big to keep the buffer for
+        let json =
+            |v: &dyn Visitable| KvToJson::new(&|lv| v.visit(Some("v"), lv), "", "").to_string();
+        for len in [300, 3, 0, 40000, 5] {
+            let data: Vec<u8> = (0..len).map(|i| (i * 7) as u8).collect();
+            let hex: String = data.iter().map(|b| format!("{:02x}", b)).collect();
+            assert_eq!(json(&Hex(&data)), format!("\"v\":\"{}\"", hex));
+            assert_eq!(
+                json(&HexUpper(&data)),
+                format!("\"v\":\"{}\"", hex.to_uppercase())
+            );
+            let b64 = Base64::new(&data).to_string();
+            assert_eq!(json(&Base64::new(&data)), format!("\"v\":\"{}\"", b64));
+        }
+    }
+
+    #[test]
+    fn hex_grouped() {
+        let data = [0xde, 0xad, 0xbe, 0xef, 0x01, 0x02];
+        assert_eq!(render(&HexGrouped(&data, 2)), "v=\"dead beef 0102\"");
+        assert_eq!(render(&HexGrouped(&data, 4)), "v=\"deadbeef 0102\"");
+        assert_eq!(render(&HexGrouped(&data, 0)), "v=deadbeef0102");
+        assert_eq!(render(&HexGrouped(&[], 2)), "v=");
+    }
+
+    #[test]
+    fn option() {
+        assert_eq!(render(&Some(443_u32)), "v=443");
+        assert_eq!(render(&None::<u32>), "v");
+        let v = vec![Some(1_u32), None, Some(3)];
+        let out = KvToJson::new(&|lv| v.visit(Some("v"), lv), "", "").to_string();
+        assert_eq!(out, r#""v":[1,null,3]"#);
+        let out = KvToJson::new(&|lv| None::<u32>.visit(Some("port"), lv), "", "").to_string();
+        assert_eq!(out, r#""port":null"#);
+    }
+
+    #[test]
+    fn nested_option() {
+        let json =
+            |v: &dyn Visitable| KvToJson::new(&|lv| v.visit(Some("v"), lv), "", "").to_string();
+        assert_eq!(json(&Some(Some(5_u32))), r#""v":5"#);
+        assert_eq!(json(&Some(None::<u32>)), r#""v":null"#);
+        assert_eq!(json(&None::<Option<u32>>), r#""v":null"#);
+        assert_eq!(json(&Some(Some(None::<u32>))), r#""v":null"#);
+        assert_eq!(render(&Some(None::<u32>)), "v");
+    }
+
+    #[test]
+    fn result() {
+        let r: Result<u32, &str> = Ok(200);
+        let out = KvToJson::new(&|lv| r.visit(Some("r"), lv), "", "").to_string();
+        assert_eq!(out, r#""r":{"Ok":200}"#);
+        let code = 404;
+        let r: Result<u32, std::fmt::Arguments> = Err(format_args!("Not found: {}", code));
+        let out = KvToJson::new(&|lv| r.visit(Some("r"), lv), "", "").to_string();
+        assert_eq!(out, r#""r":{"Err":"Not found: 404"}"#);
+    }
+
+    #[test]
+    fn contains() {
+        let set: HashSet<&str> = ["read", "write"].iter().copied().collect();
+        assert_eq!(
+            render(&Contains(&set, &["read", "exec"])),
+            "v{read=true exec=false}"
+        );
+        assert_eq!(render(&Contains(&set, &[])), "v{}");
+    }
+
+    #[test]
+    fn range() {
+        assert_eq!(render(&(2..10)), "v{start=2 end=10}");
+        assert_eq!(render(&(-1_i64..=1)), "v{start=-1 end=1 inclusive=true}");
+        let out = KvToJson::new(&|lv| (0_usize..4).visit(Some("r"), lv), "", "").to_string();
+        assert_eq!(out, r#""r":{"start":0,"end":4}"#);
+    }
+
+    #[test]
+    fn duration() {
+        let d = Duration::new(1, 234_000_005);
+        let out = KvToJson::new(&|lv| d.visit(Some("d"), lv), "", "").to_string();
+        assert_eq!(out, r#""d":{"secs":1,"nanos":234000005}"#);
+        let v = vec![Duration::from_millis(1500), Duration::ZERO];
+        let out = KvToJson::new(&|lv| v.visit(Some("v"), lv), "", "").to_string();
+        assert_eq!(
+            out,
+            r#""v":[{"secs":1,"nanos":500000000},{"secs":0,"nanos":0}]"#
+        );
+    }
+
+    #[test]
+    fn duration_both() {
+        let d = Duration::from_millis(1500);
+        assert_eq!(render(&DurationBoth(d)), "v{ms=1500 human=1.5s}");
+        let d = Duration::from_micros(2250);
+        assert_eq!(render(&DurationBoth(d)), "v{ms=2 human=2.25ms}");
+        let d = Duration::from_nanos(42);
+        assert_eq!(render(&DurationBoth(d)), "v{ms=0 human=42ns}");
+    }
+
+    #[test]
+    fn backtrace() {
+        let bt = Backtrace::force_capture();
+        let out = render(&BacktraceStructured(&bt));
+        assert!(out.starts_with("v{status=captured frames["), "{}", out);
+
+        let text = "   0: app::main\n             at ./src/main.rs:12:5\n   1: std::rt::lang_start\n  unexpected\n";
+        let frames = parse_backtrace(text);
+        let out = KvSingleLine::new(
+            &|lv| {
+                lv.kv_arr(Some("frames"));
+                visit_frames(&frames, lv);
+                lv.kv_arrend(Some("frames"));
+            },
+            "",
+            "",
+        )
+        .to_string();
+        assert_eq!(
+            out,
+            "frames[{fn=app::main file=./src/main.rs line=12} {fn=std::rt::lang_start} unexpected]"
+        );
+    }
+}
@@ -0,0 +1,242 @@
+//! Derive macro for the `Visitable` trait of **stakker_log**
+//!
+//! Don't use this crate directly.  Enable the `derive` feature of
+//! **stakker_log** instead, which re-exports the macro as
+//! `stakker_log::Visitable`.  See the documentation there.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote, quote_spanned};
+use syn::spanned::Spanned;
+use syn::{
+    parse_macro_input, parse_quote, Attribute, Data, DeriveInput, Error, Fields, GenericParam,
+    LitStr, Result,
+};
+
+/// Derive `Visitable` for a struct or enum
+///
+/// A struct with named fields is output as a map containing each
+/// field under its name.  A tuple struct is output as an array of
+/// its fields, and a unit struct as an empty map.  An enum is output
+/// as a map with the variant name under the key `variant`, followed
+/// by the fields of the variant.  Fields of tuple variants are given
+/// keys `0`, `1` and so on.
+///
+/// These attributes are supported on fields:
+///
+/// - `#[log(skip)]` to leave the field out
+/// - `#[log(rename = "name")]` to output the field under a different
+///   key
+/// - `#[log(display)]` to output the field's `Display` formatting
+///   instead of using its `Visitable` implementation
+/// - `#[log(debug)]` to output the field's `Debug` formatting
+/// - `#[log(flatten)]` to output the entries of a map-shaped field
+///   directly in the enclosing map, using `stakker_log::Flatten`.
+///   This isn't supported on the fields of a tuple struct, since
+///   those are output as an array.
+///
+/// `#[log(rename = "name")]` may also be used on enum variants.  All
+/// type parameters of the type are required to implement
+/// `Visitable`.
+#[proc_macro_derive(Visitable, attributes(log))]
+pub fn derive_visitable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(input) {
+        Ok(ts) => ts.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand(mut input: DeriveInput) -> Result<TokenStream2> {
+    let body = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(_) => {
+                let (pat, items) = visit_fields(&data.fields, false)?;
+                quote! {
+                    let Self #pat = self;
+                    output.kv_map(key);
+                    #items
+                    output.kv_mapend(key);
+                }
+            }
+            Fields::Unnamed(_) => {
+                let (pat, items) = visit_fields(&data.fields, true)?;
+                quote! {
+                    let Self #pat = self;
+                    output.kv_arr(key);
+                    #items
+                    output.kv_arrend(key);
+                }
+            }
+            Fields::Unit => quote! {
+                output.kv_map(key);
+                output.kv_mapend(key);
+            },
+        },
+        Data::Enum(data) => {
+            let mut arms = Vec::new();
+            for variant in &data.variants {
+                let attrs = FieldAttrs::parse(&variant.attrs)?;
+                if attrs.skip || attrs.fmt.is_some() || attrs.flatten {
+                    return Err(Error::new_spanned(
+                        &variant.ident,
+                        "only `rename` is supported on enum variants",
+                    ));
+                }
+                let ident = &variant.ident;
+                let name = attrs.rename.unwrap_or_else(|| ident.to_string());
+                let (pat, items) = visit_fields(&variant.fields, false)?;
+                arms.push(quote! {
+                    Self::#ident #pat => {
+                        output.kv_map(key);
+                        output.kv_str(::std::option::Option::Some("variant"), #name);
+                        #items
+                        output.kv_mapend(key);
+                    }
+                });
+            }
+            if arms.is_empty() {
+                quote! { match *self {} }
+            } else {
+                quote! { match self { #(#arms)* } }
+            }
+        }
+        Data::Union(data) => {
+            return Err(Error::new_spanned(
+                data.union_token,
+                "`Visitable` can't be derived for a union",
+            ));
+        }
+    };
+
+    for param in &mut input.generics.params {
+        if let GenericParam::Type(param) = param {
+            param.bounds.push(parse_quote!(::stakker_log::Visitable));
+        }
+    }
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let name = &input.ident;
+    Ok(quote! {
+        impl #impl_generics ::stakker_log::Visitable for #name #ty_generics #where_clause {
+            #[allow(unused_variables)] // Not used for an empty enum
+            fn visit(
+                &self,
+                key: ::std::option::Option<&str>,
+                output: &mut dyn ::stakker_log::stakker::LogVisitor,
+            ) {
+                #body
+            }
+        }
+    })
+}
+
+/// Generate a pattern which binds the fields to local variables, and
+/// the code to visit those fields.  `unkeyed` selects output of
+/// unnamed fields as array items, rather than with keys `0`, `1` and
+/// so on.
+fn visit_fields(fields: &Fields, unkeyed: bool) -> Result<(TokenStream2, TokenStream2)> {
+    let mut binds = Vec::new();
+    let mut items = Vec::new();
+    for (i, field) in fields.iter().enumerate() {
+        let attrs = FieldAttrs::parse(&field.attrs)?;
+        let var = format_ident!("f{}", i);
+        if attrs.skip {
+            continue;
+        }
+        if attrs.flatten && unkeyed {
+            return Err(Error::new_spanned(
+                field,
+                "`flatten` is not supported on the fields of a tuple struct",
+            ));
+        }
+        binds.push(match &field.ident {
+            Some(ident) => quote! { #ident: #var },
+            None => {
+                let index = syn::Index::from(i);
+                quote! { #index: #var }
+            }
+        });
+        let key = if unkeyed {
+            quote! { ::std::option::Option::None }
+        } else {
+            let name = match (attrs.rename, &field.ident) {
+                (Some(name), _) => name,
+                (None, Some(ident)) => ident.to_string(),
+                (None, None) => i.to_string(),
+            };
+            quote! { ::std::option::Option::Some(#name) }
+        };
+        items.push(match attrs.fmt {
+            None if attrs.flatten => quote_spanned! {field.ty.span()=>
+                ::stakker_log::Visitable::visit(&::stakker_log::Flatten(#var), #key, output);
+            },
+            Some(Fmt::Display) => quote! {
+                output.kv_fmt(#key, &::std::format_args!("{}", #var));
+            },
+            Some(Fmt::Debug) => quote! {
+                output.kv_fmt(#key, &::std::format_args!("{:?}", #var));
+            },
+            // Report a missing `Visitable` implementation at the field
+            None => quote_spanned! {field.ty.span()=>
+                ::stakker_log::Visitable::visit(#var, #key, output);
+            },
+        });
+    }
+    let pat = match fields {
+        Fields::Unit => quote! {},
+        _ => quote! { { #(#binds,)* .. } },
+    };
+    Ok((pat, quote! { #(#items)* }))
+}
+
+enum Fmt {
+    Display,
+    Debug,
+}
+
+#[derive(Default)]
+struct FieldAttrs {
+    skip: bool,
+    rename: Option<String>,
+    fmt: Option<Fmt>,
+    flatten: bool,
+}
+
+impl FieldAttrs {
+    fn parse(attrs: &[Attribute]) -> Result<Self> {
+        let mut rv = Self::default();
+        for attr in attrs.iter().filter(|a| a.path().is_ident("log")) {
+            attr.parse_nested_meta(|meta| {
+                let fmt = if meta.path.is_ident("skip") {
+                    rv.skip = true;
+                    None
+                } else if meta.path.is_ident("rename") {
+                    rv.rename = Some(meta.value()?.parse::<LitStr>()?.value());
+                    None
+                } else if meta.path.is_ident("display") {
+                    Some(Fmt::Display)
+                } else if meta.path.is_ident("debug") {
+                    Some(Fmt::Debug)
+                } else if meta.path.is_ident("flatten") {
+                    rv.flatten = true;
+                    None
+                } else {
+                    return Err(meta.error(
+                        "unknown `log` attribute, expecting `skip`, `rename`, `display`, `debug` or `flatten`",
+                    ));
+                };
+                if fmt.is_some() {
+                    if rv.fmt.is_some() {
+                        return Err(meta.error("only one of `display` and `debug` may be given"));
+                    }
+                    rv.fmt = fmt;
+                }
+                if rv.flatten && rv.fmt.is_some() {
+                    return Err(meta.error("`flatten` can't be combined with `display` or `debug`"));
+                }
+                Ok(())
+            })?;
+        }
+        Ok(rv)
+    }
+}
@@ -0,0 +1,15 @@
+// Run with `cargo test --features max_level_info --test compile_fail`
+#[cfg(feature = "max_level_info")]
+#[test]
+fn disabled_level_type_checked() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/disabled_level_type_error.rs");
+}
+
+// Run with `cargo test --features derive --test compile_fail`
+#[cfg(feature = "derive")]
+#[test]
+fn derive_errors() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/derive_*.rs");
+}
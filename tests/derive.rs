@@ -0,0 +1,114 @@
+// Run with `cargo test --features derive --test derive`
+#![cfg(feature = "derive")]
+
+use stakker_log::{KvToJson, Visitable};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr};
+
+#[derive(Visitable)]
+struct Endpoint {
+    #[log(display)]
+    addr: IpAddr,
+    port: u16,
+}
+
+#[derive(Visitable)]
+struct Request<T> {
+    id: u64,
+    endpoint: Endpoint,
+    #[log(rename = "kind")]
+    typ: &'static str,
+    #[log(skip)]
+    #[allow(dead_code)]
+    body: Vec<u8>,
+    #[log(debug)]
+    flags: (bool, char),
+    extra: T,
+}
+
+#[derive(Visitable)]
+struct Pair(u32, &'static str);
+
+#[derive(Visitable)]
+struct Session {
+    user: &'static str,
+    #[log(flatten)]
+    endpoint: Endpoint,
+    #[log(flatten)]
+    tags: BTreeMap<String, u32>,
+    #[log(flatten)]
+    retries: u32,
+}
+
+#[derive(Visitable)]
+struct Marker;
+
+#[derive(Visitable)]
+enum State {
+    Idle,
+    #[log(rename = "busy")]
+    Busy {
+        jobs: u32,
+    },
+    Failed(String, #[log(debug)] Option<u8>),
+}
+
+fn json(v: &dyn Visitable) -> String {
+    KvToJson::new(&|lv| v.visit(Some("v"), lv), "", "").to_string()
+}
+
+#[test]
+fn nested_struct() {
+    let r = Request {
+        id: 7,
+        endpoint: Endpoint {
+            addr: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            port: 80,
+        },
+        typ: "get",
+        body: vec![1, 2, 3],
+        flags: (true, 'x'),
+        extra: Some(1.5),
+    };
+    assert_eq!(
+        json(&r),
+        r#""v":{"id":7,"endpoint":{"addr":"10.0.0.1","port":80},"kind":"get","flags":"(true, 'x')","extra":1.5}"#
+    );
+}
+
+#[test]
+fn tuple_and_unit() {
+    assert_eq!(json(&Pair(1, "a")), r#""v":[1,"a"]"#);
+    assert_eq!(json(&Marker), r#""v":{}"#);
+}
+
+#[test]
+fn flatten() {
+    let s = Session {
+        user: "jim",
+        endpoint: Endpoint {
+            addr: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            port: 80,
+        },
+        tags: vec![("zone".into(), 3)].into_iter().collect(),
+        retries: 2,
+    };
+    // A field which isn't map-shaped is output with its key as usual
+    assert_eq!(
+        json(&s),
+        r#""v":{"user":"jim","addr":"10.0.0.1","port":80,"zone":3,"retries":2}"#
+    );
+}
+
+#[test]
+fn enums() {
+    assert_eq!(json(&State::Idle), r#""v":{"variant":"Idle"}"#);
+    assert_eq!(
+        json(&State::Busy { jobs: 3 }),
+        r#""v":{"variant":"busy","jobs":3}"#
+    );
+    assert_eq!(
+        json(&State::Failed("timeout".into(), Some(2))),
+        r#""v":{"variant":"Failed","0":"timeout","1":"Some(2)"}"#
+    );
+}
@@ -0,0 +1,15 @@
+use stakker_log::Visitable;
+
+#[derive(Visitable)]
+struct Config {
+    #[log(hidden)]
+    password: String,
+}
+
+#[derive(Visitable)]
+union Bits {
+    a: u32,
+    b: f32,
+}
+
+fn main() {}
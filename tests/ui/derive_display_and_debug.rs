@@ -0,0 +1,9 @@
+use stakker_log::Visitable;
+
+#[derive(Visitable)]
+struct Config {
+    #[log(display, debug)]
+    name: String,
+}
+
+fn main() {}
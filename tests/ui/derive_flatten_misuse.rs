@@ -0,0 +1,17 @@
+use stakker_log::Visitable;
+
+#[derive(Visitable)]
+struct Inner {
+    a: u32,
+}
+
+#[derive(Visitable)]
+struct Outer {
+    #[log(flatten, display)]
+    inner: Inner,
+}
+
+#[derive(Visitable)]
+struct Wrapper(#[log(flatten)] Inner);
+
+fn main() {}
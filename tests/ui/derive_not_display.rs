@@ -0,0 +1,11 @@
+use stakker_log::Visitable;
+
+struct Opaque;
+
+#[derive(Visitable)]
+struct Holder {
+    #[log(display)]
+    inner: Opaque,
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+// With `max_level_info`, `debug!` is compiled out, but its arguments
+// must still be type-checked
+use stakker::Stakker;
+use stakker_log::debug;
+use std::time::Instant;
+
+fn main() {
+    let mut stakker = Stakker::new(Instant::now());
+    let s = &mut stakker;
+    let count: u32 = 1;
+    debug!([s], count: count.no_such_method(), "Debug");
+}